@@ -0,0 +1,12 @@
+BITS 16
+    org 0x7c00
+start:
+    mov ecx, 20000000
+again:
+    add eax, ecx
+    adc edx, 0
+    xor ebx, eax
+    cmp ebx, 7
+    sub ecx, 1
+    jnz again
+    jmp 0
@@ -1,17 +1,17 @@
-BITS 32
+BITS 16
     org 0x7c00
 start:
-    mov esi, msg
+    mov si, msg
     call puts
     jmp 0
 
 puts:
-    mov al, [esi]
-    inc esi
+    mov al, [si]
+    inc si
     cmp al, 0
     je puts_end
     mov ah, 0x0e
-    mov ebx, 15
+    mov bx, 15
     int 0x10
     jmp puts
 puts_end:
@@ -1,5 +1,9 @@
 use crate::emulator::{Emulator, Register8};
 use crate::io::io_out8;
+use crate::timing::{PIT_RATE, PIT_TICKS_PER_TIMER_TICK, RTC_RATE};
+
+/// The system timer ticks in a day, after which the count wraps around.
+const TIMER_TICKS_PER_DAY: u64 = 0x1800b0;
 
 static BIOS_TO_TERMINAL: [i32; 8] = [30, 34, 32, 36, 31, 35, 33, 37];
 
@@ -26,3 +30,39 @@ pub fn bios_video(emu: &mut Emulator) {
         _ => println!("not implemented BIOS video function: 0x{:02x}", func),
     }
 }
+
+fn to_bcd(value: u64) -> u8 {
+    (value / 10 * 16 + value % 10) as u8
+}
+
+/// AH=00h: CX:DX is the number of system timer ticks since midnight, when
+/// the machine is taken to have been switched on, and AL is set once a day
+/// has gone by.
+fn bios_time_read_ticks(emu: &mut Emulator) {
+    let ticks = emu.device_ticks(PIT_RATE) / PIT_TICKS_PER_TIMER_TICK;
+    let today = (ticks % TIMER_TICKS_PER_DAY) as u32;
+    emu.set_register16(1, (today >> 16) as u16);
+    emu.set_register16(2, today as u16);
+    emu.set_register8(Register8::Al, (ticks >= TIMER_TICKS_PER_DAY) as u8);
+}
+
+/// AH=02h: the time of the real-time clock in BCD, with the hours in CH,
+/// the minutes in CL and the seconds in DH. DL is 0: no daylight saving.
+fn bios_time_read_clock(emu: &mut Emulator) {
+    let seconds = emu.device_ticks(RTC_RATE) / RTC_RATE % 86400;
+    emu.set_register8(Register8::Ch, to_bcd(seconds / 3600));
+    emu.set_register8(Register8::Cl, to_bcd(seconds / 60 % 60));
+    emu.set_register8(Register8::Dh, to_bcd(seconds % 60));
+    emu.set_register8(Register8::Dl, 0);
+}
+
+/// INT 1Ah. The system timer and the real-time clock run on the emulated
+/// time derived from the cycle counter, not on the host's clock.
+pub fn bios_time(emu: &mut Emulator) {
+    let func = emu.get_register8(Register8::Ah);
+    match func {
+        0x00 => bios_time_read_ticks(emu),
+        0x02 => bios_time_read_clock(emu),
+        _ => println!("not implemented BIOS time function: 0x{:02x}", func),
+    }
+}
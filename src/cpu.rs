@@ -0,0 +1,85 @@
+/// The processor to emulate. Each model has the instructions and control
+/// bits of the ones before it; those a model lacks raise #UD or stay clear,
+/// as guest CPU detection code expects.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CpuModel {
+    I386,
+    /// Adds BSWAP, XADD, CMPXCHG, INVLPG and WBINVD, CR0.WP and CR0.AM with
+    /// alignment checking through EFLAGS.AC.
+    I486,
+    /// Adds CPUID, with the ID flag in EFLAGS to detect it, RDTSC,
+    /// CMPXCHG8B, RDMSR and WRMSR.
+    Pentium,
+}
+
+impl CpuModel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "386" => Some(CpuModel::I386),
+            "486" => Some(CpuModel::I486),
+            "pentium" | "586" => Some(CpuModel::Pentium),
+            _ => None,
+        }
+    }
+
+    /// Whether 0F `opcode` is an instruction on this model. The rest of the
+    /// two-byte map, including UD2 (0F 0B) and the CMOVcc and MMX rows of
+    /// later processors, raises #UD.
+    pub fn defines_two_byte_opcode(self, opcode: u8) -> bool {
+        match opcode {
+            0x00..=0x03 | 0x06 | 0x20..=0x23 => true,
+            0x80..=0xA1 | 0xA3..=0xA5 | 0xA8..=0xAD | 0xAF | 0xB2..=0xB7 | 0xBA..=0xBF => true,
+            // MOV to and from the test registers.
+            0x24 | 0x26 => self < CpuModel::Pentium,
+            0x08 | 0x09 | 0xB0 | 0xB1 | 0xC0 | 0xC1 | 0xC8..=0xCF => self >= CpuModel::I486,
+            0x30..=0x32 | 0xA2 | 0xC7 => self >= CpuModel::Pentium,
+            _ => false,
+        }
+    }
+}
+
+/// The vendor string CPUID returns in EBX, EDX and ECX.
+pub const CPUID_VENDOR: &[u8; 12] = b"GenuineIntel";
+/// CPUID leaf 1 EAX: family 5, model 2, stepping 5, a P54C Pentium.
+pub const CPUID_SIGNATURE: u32 = 0x0525;
+/// CPUID leaf 1 EDX: an on-chip FPU, the time-stamp counter, RDMSR and
+/// WRMSR, and CMPXCHG8B.
+pub const CPUID_FEATURES: u32 = 1 << 0 | 1 << 4 | 1 << 5 | 1 << 8;
+
+/// The model-specific registers of the Pentium: the machine check address
+/// and type, the time-stamp counter, and the control and event select
+/// register and two counters of the performance monitor.
+pub const MSR_P5_MC_ADDR: u32 = 0x00;
+pub const MSR_P5_MC_TYPE: u32 = 0x01;
+pub const MSR_TSC: u32 = 0x10;
+pub const MSR_CESR: u32 = 0x11;
+pub const MSR_CTR0: u32 = 0x12;
+pub const MSR_CTR1: u32 = 0x13;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undefined_two_byte_opcodes() {
+        for model in [CpuModel::I386, CpuModel::I486, CpuModel::Pentium] {
+            // UD2, CMOVcc and the last row stay undefined on every model.
+            assert!(!model.defines_two_byte_opcode(0x0B));
+            assert!(!model.defines_two_byte_opcode(0x40));
+            assert!(!model.defines_two_byte_opcode(0x4F));
+            assert!(!model.defines_two_byte_opcode(0xFF));
+            assert!(model.defines_two_byte_opcode(0xAF));
+        }
+    }
+
+    #[test]
+    fn later_models_add_opcodes() {
+        // BSWAP, then CPUID; the test registers go away on the Pentium.
+        assert!(!CpuModel::I386.defines_two_byte_opcode(0xC8));
+        assert!(CpuModel::I486.defines_two_byte_opcode(0xC8));
+        assert!(!CpuModel::I486.defines_two_byte_opcode(0xA2));
+        assert!(CpuModel::Pentium.defines_two_byte_opcode(0xA2));
+        assert!(CpuModel::I486.defines_two_byte_opcode(0x24));
+        assert!(!CpuModel::Pentium.defines_two_byte_opcode(0x24));
+    }
+}
@@ -1,16 +1,116 @@
-use crate::bios::bios_video;
+use crate::bios::{bios_time, bios_video};
+use crate::cpu::{
+    CpuModel, CPUID_FEATURES, CPUID_SIGNATURE, CPUID_VENDOR, MSR_CESR, MSR_CTR0, MSR_CTR1,
+    MSR_P5_MC_ADDR, MSR_P5_MC_TYPE, MSR_TSC,
+};
+use crate::exception::Exception;
+use crate::flags::{FlagsOp, LazyFlags};
+use crate::fpu::{ArithOp, Format, Fpu, Operand};
 use crate::io::io_in8;
 use crate::io::io_out8;
 use crate::modrm::ModRM;
+use crate::paging::{
+    Tlb, TlbEntry, PAGE_FRAME, PAGE_SIZE, PF_PROTECTION, PF_USER, PF_WRITE, PTE_ACCESSED,
+    PTE_DIRTY, PTE_PRESENT, PTE_USER, PTE_WRITABLE,
+};
+use crate::segment::{
+    Descriptor, DescriptorTable, Gate, Segment, SegmentRegister, ACCESS_ACCESSED, ACCESS_CODE,
+    ACCESS_DATA, SYSTEM_CALL_GATE, SYSTEM_CALL_GATE_16, SYSTEM_INTERRUPT_GATE,
+    SYSTEM_INTERRUPT_GATE_16, SYSTEM_LDT, SYSTEM_TASK_GATE, SYSTEM_TRAP_GATE, SYSTEM_TRAP_GATE_16,
+    SYSTEM_TSS, SYSTEM_TSS_16, SYSTEM_TSS_16_BUSY, SYSTEM_TSS_BUSY, TSS_BUSY,
+};
+use crate::timing::{self, Execution};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 
 const CARRY_FLAG: u32 = 1 << 0;
-const ZERO_FLAG: u32 = 1 << 1;
-const SIGN_FLAG: u32 = 1 << 2;
-const OVERFLOW_FLAG: u32 = 1 << 3;
+const EFLAGS_RESERVED: u32 = 1 << 1;
+const PARITY_FLAG: u32 = 1 << 2;
+const AUX_CARRY_FLAG: u32 = 1 << 4;
+const ZERO_FLAG: u32 = 1 << 6;
+const SIGN_FLAG: u32 = 1 << 7;
+const TRAP_FLAG: u32 = 1 << 8;
+const INTERRUPT_FLAG: u32 = 1 << 9;
+const DIRECTION_FLAG: u32 = 1 << 10;
+const OVERFLOW_FLAG: u32 = 1 << 11;
+const IOPL_MASK: u32 = 3 << 12;
+const NESTED_TASK_FLAG: u32 = 1 << 14;
+const RESUME_FLAG: u32 = 1 << 16;
+const VIRTUAL_8086_FLAG: u32 = 1 << 17;
+/// 486 and later.
+const ALIGNMENT_CHECK_FLAG: u32 = 1 << 18;
+/// Pentium: a flag that can be toggled means CPUID is available.
+const ID_FLAG: u32 = 1 << 21;
 
+const CR0_PE: u32 = 1 << 0;
+const CR0_MP: u32 = 1 << 1;
+const CR0_EM: u32 = 1 << 2;
+const CR0_TS: u32 = 1 << 3;
+/// Reads as 1: a 387-compatible coprocessor is present.
+const CR0_ET: u32 = 1 << 4;
+/// 486 and later: supervisor writes honor read-only pages.
+const CR0_WP: u32 = 1 << 16;
+/// 486 and later: alignment checking at CPL 3 when AC is set.
+const CR0_AM: u32 = 1 << 18;
+const CR0_PG: u32 = 1 << 31;
+const CR0_WRITABLE: u32 = CR0_PE | CR0_MP | CR0_EM | CR0_TS | CR0_PG;
+const CR0_WRITABLE_486: u32 = CR0_WRITABLE | CR0_WP | CR0_AM;
+/// The bits of CR0 that make up the 286 machine status word (LMSW, SMSW).
+const MSW_WRITABLE: u32 = CR0_PE | CR0_MP | CR0_EM | CR0_TS;
+
+/// DR6 status bits besides B0-B3, which report the breakpoints hit: BD for
+/// a debug register access caught by GD, BS for a single step and BT for a
+/// switch to a task with the T bit set. The reserved bits read as 1.
+const DR6_BD: u32 = 1 << 13;
+const DR6_BS: u32 = 1 << 14;
+const DR6_BT: u32 = 1 << 15;
+const DR6_RESERVED: u32 = 0xffff_0ff0;
+/// The L0/G0 to L3/G3 enable bits of DR7. The local ones, along with LE,
+/// are cleared on every task switch.
+const DR7_ENABLE: u32 = 0xff;
+const DR7_LOCAL: u32 = 0x155;
+/// General detect: debug register accesses raise #DB.
+const DR7_GD: u32 = 1 << 13;
+/// R/W field values of a breakpoint in DR7; 2 is undefined on the 386.
+const BREAK_EXECUTE: u32 = 0;
+const BREAK_WRITE: u32 = 1;
+const BREAK_READ_WRITE: u32 = 3;
+
+const ARITHMETIC_FLAGS: u32 =
+    CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | ZERO_FLAG | SIGN_FLAG | OVERFLOW_FLAG;
+/// Bits of the low EFLAGS byte transferred by LAHF and SAHF.
+const AH_FLAGS: u32 = SIGN_FLAG | ZERO_FLAG | AUX_CARRY_FLAG | PARITY_FLAG | CARRY_FLAG;
+const EFLAGS_WRITABLE: u32 = ARITHMETIC_FLAGS
+    | TRAP_FLAG
+    | INTERRUPT_FLAG
+    | DIRECTION_FLAG
+    | IOPL_MASK
+    | NESTED_TASK_FLAG
+    | RESUME_FLAG
+    | VIRTUAL_8086_FLAG;
+const EFLAGS_WRITABLE_486: u32 = EFLAGS_WRITABLE | ALIGNMENT_CHECK_FLAG;
+const EFLAGS_WRITABLE_PENTIUM: u32 = EFLAGS_WRITABLE_486 | ID_FLAG;
+
+const EFLAGS_NAMES: [(u32, &str); 14] = [
+    (CARRY_FLAG, "CF"),
+    (PARITY_FLAG, "PF"),
+    (AUX_CARRY_FLAG, "AF"),
+    (ZERO_FLAG, "ZF"),
+    (SIGN_FLAG, "SF"),
+    (TRAP_FLAG, "TF"),
+    (INTERRUPT_FLAG, "IF"),
+    (DIRECTION_FLAG, "DF"),
+    (OVERFLOW_FLAG, "OF"),
+    (NESTED_TASK_FLAG, "NT"),
+    (RESUME_FLAG, "RF"),
+    (VIRTUAL_8086_FLAG, "VM"),
+    (ALIGNMENT_CHECK_FLAG, "AC"),
+    (ID_FLAG, "ID"),
+];
+
+#[derive(Clone, Copy)]
 enum Register {
     Eax,
     Ecx,
@@ -35,27 +135,116 @@ pub enum Register8 {
 
 impl Register8 {
     pub fn from_usize(value: usize) -> Option<Self> {
-        match value {
-            0 => Some(Register8::Al),
-            1 => Some(Register8::Cl),
-            2 => Some(Register8::Dl),
-            3 => Some(Register8::Bl),
-            4 => Some(Register8::Ah),
-            5 => Some(Register8::Ch),
-            6 => Some(Register8::Dh),
-            7 => Some(Register8::Bh),
-            _ => None,
+        (value < 8).then(|| Register8::from_u8(value as u8))
+    }
+
+    /// The register selected by the low three bits of `value`, as in the
+    /// B0+r opcodes.
+    pub fn from_u8(value: u8) -> Self {
+        match value & 0x07 {
+            0 => Register8::Al,
+            1 => Register8::Cl,
+            2 => Register8::Dl,
+            3 => Register8::Bl,
+            4 => Register8::Ah,
+            5 => Register8::Ch,
+            6 => Register8::Dh,
+            _ => Register8::Bh,
+        }
+    }
+}
+
+/// Sign-extends the low `size` bits of `value`.
+fn sign_extend(value: u32, size: u32) -> i64 {
+    ((u64::from(value) << (64 - size)) as i64) >> (64 - size)
+}
+
+#[derive(Clone, Copy)]
+enum AluOp {
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+}
+
+impl AluOp {
+    fn from_u8(value: u8) -> Self {
+        match value & 0x07 {
+            0 => AluOp::Add,
+            1 => AluOp::Or,
+            2 => AluOp::Adc,
+            3 => AluOp::Sbb,
+            4 => AluOp::And,
+            5 => AluOp::Sub,
+            6 => AluOp::Xor,
+            _ => AluOp::Cmp,
         }
     }
 }
 
+/// Why a code byte of the current instruction could not be fetched.
+#[derive(Clone, Copy)]
+enum FetchFault {
+    /// The byte lies beyond the CS limit.
+    Limit,
+    /// The byte at this linear address missed the TLB.
+    Page(u32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RepeatPrefix {
+    Rep,
+    Repne,
+}
+
+/// Instruction prefixes decoded in front of the current opcode.
+#[derive(Clone, Copy, Default)]
+struct Prefixes {
+    repeat: Option<RepeatPrefix>,
+    operand_size: bool,
+    address_size: bool,
+    segment: Option<SegmentRegister>,
+}
+
+/// How a task switch was started. CALL and interrupts nest the new task
+/// inside the old one; JMP and IRET leave the old task.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TaskSwitch {
+    Jump,
+    Call,
+    Interrupt,
+    Iret,
+}
+
+/// The state of a task as loaded from its TSS.
+struct TaskState {
+    eip: u32,
+    eflags: u32,
+    registers: [u32; 8],
+    selectors: [u16; 6],
+    ldt: u16,
+    /// Only a 32-bit TSS holds a page directory base.
+    cr3: Option<u32>,
+    /// The T bit of a 32-bit TSS: entering the task raises a debug trap.
+    debug_trap: bool,
+}
+
 #[derive(Clone)]
 pub enum Instruction {
     MovR8Imm8,
     MovR32Imm32,
     MovR8Rm8,
     MovR32Rm32,
-    AddRm32R32,
+    AluRm8R8,
+    AluRm32R32,
+    AluR8Rm8,
+    AluR32Rm32,
+    AluAlImm8,
+    AluEaxImm32,
     MovRm8R8,
     MovRm32R32,
     IncR32,
@@ -63,59 +252,299 @@ pub enum Instruction {
     PopR32,
     PushImm32,
     PushImm8,
+    Code80,
+    Code81,
     Code83,
+    CodeC0,
+    CodeC1,
+    CodeD0,
+    CodeD1,
+    CodeD2,
+    CodeD3,
+    CodeF6,
+    CodeF7,
     MovRm32Imm32,
     InAlDx,
     OutDxAl,
     CodeFf,
     CallRel32,
     Ret,
+    RetImm16,
+    Retf,
+    RetfImm16,
+    CallFarPtr,
+    JmpFarPtr,
+    Iret,
     Leave,
+    Enter,
+    Pusha,
+    Popa,
+    Pushf,
+    Popf,
+    PushSreg,
+    PopSreg,
+    MovRm16Sreg,
+    MovSregRm16,
     ShortJump,
     NearJump,
-    CmpAlImm8,
-    CmpEaxImm32,
-    CmpR32Rm32,
-    Jc,
-    Jnc,
-    Jz,
-    Jnz,
-    Js,
-    Jns,
-    Jo,
-    Jno,
-    Jl,
-    Jle,
+    ShortJcc,
+    NearJcc,
+    Setcc,
+    MovzxR32Rm8,
+    MovzxR32Rm16,
+    MovsxR32Rm8,
+    MovsxR32Rm16,
+    ImulR32Rm32,
+    BitTestRm32R32,
+    Code0fBa,
+    BsfR32Rm32,
+    BsrR32Rm32,
+    ShldRm32R32Imm8,
+    ShldRm32R32Cl,
+    ShrdRm32R32Imm8,
+    ShrdRm32R32Cl,
+    Daa,
+    Das,
+    Aaa,
+    Aas,
+    Aam,
+    Aad,
+    Movs,
+    Cmps,
+    Stos,
+    Lods,
+    Scas,
+    Cld,
+    Std,
+    Clc,
+    Stc,
+    Cmc,
+    Cli,
+    Sti,
+    Lahf,
+    Sahf,
     Swi,
+    Loop,
+    Jecxz,
+    Hlt,
+    Nop,
+    XchgEaxR32,
+    XchgRm8R8,
+    XchgRm32R32,
+    LeaR32M,
+    Cbw,
+    Cwd,
+    Xlat,
+    Fwait,
+    FpuEscape,
+    Int3,
+    Into,
+    Icebp,
+    Clts,
+    MovR32Cr,
+    MovCrR32,
+    MovR32Dr,
+    MovDrR32,
+    Bswap,
+    Xadd,
+    Cmpxchg,
+    Wbinvd,
+    Cpuid,
+    Rdtsc,
+    Rdmsr,
+    Wrmsr,
+    Code0fC7,
+    Code0f00,
+    Code0f01,
+    LarR32Rm16,
+    LslR32Rm16,
+    ArplRm16R16,
 }
 
 pub struct Emulator {
+    model: CpuModel,
     registers: [u32; 8],
+    segments: [Segment; 6],
     eflags: u32,
+    lazy_flags: Option<LazyFlags>,
     memory: Vec<u8>,
     pub eip: u32,
+    instruction_start: u32,
+    /// Where the boot program is entered at power-on and after a reset.
+    reset_eip: u32,
+    reset_esp: u32,
+    prefixes: Prefixes,
+    halted: bool,
+    cr0: u32,
+    /// The linear address of the last page fault.
+    cr2: u32,
+    /// The page directory base register.
+    cr3: u32,
+    /// The linear addresses of breakpoints 0 to 3 (DR0-DR3).
+    debug_address: [u32; 4],
+    /// The debug status register.
+    dr6: u32,
+    /// The debug control register.
+    dr7: u32,
+    /// DR6 bits collected while the current instruction runs, for the #DB
+    /// trap raised once it completes: data breakpoints it hit and BT.
+    debug_trap: u32,
+    gdtr: DescriptorTable,
+    idtr: DescriptorTable,
+    /// The selector and descriptor cache of the current LDT.
+    ldtr: Segment,
+    /// The task register: the selector and descriptor cache of the TSS of
+    /// the current task.
+    tr: Segment,
+    /// Set once a task switch has saved the old task and started loading
+    /// the new one. Faults after that point belong to the new task, so
+    /// the instruction is not rolled back.
+    task_switched: bool,
+    tlb: Tlb,
+    /// The clocks taken by the instructions executed since power-on.
+    cycles: u64,
+    /// What the current instruction has done so far, for its clock count.
+    execution: Execution,
+    /// The time-stamp counter is the cycle counter plus this, which WRMSR
+    /// sets.
+    tsc_offset: u64,
+    /// The other model-specific registers of the Pentium, by number.
+    msrs: HashMap<u32, u64>,
+    /// The first code byte of the instruction being decoded that was beyond
+    /// the CS limit or missed the TLB. The instruction is abandoned and
+    /// #GP(0) raised, or the page walk repeated to raise the #PF.
+    fetch_fault: Cell<Option<FetchFault>>,
+    fpu: Fpu,
     pub instructions: HashMap<u8, Instruction>,
+    pub two_byte_instructions: HashMap<u8, Instruction>,
 }
 
 impl Emulator {
-    pub fn new(memory_size: usize, eip: u32, esp: u32) -> Self {
+    /// Creates an emulator of the given processor model in 16-bit
+    /// real-address mode with all segment bases at 0, as a boot sector is
+    /// entered at 0000:7C00.
+    pub fn new(model: CpuModel, memory_size: usize, eip: u32, esp: u32) -> Self {
+        let mut segments = [Segment::reset(ACCESS_DATA); 6];
+        segments[SegmentRegister::Cs as usize] = Segment::reset(ACCESS_CODE);
         let mut emu = Emulator {
+            model,
             registers: [0; 8],
-            eflags: 0,
+            segments,
+            eflags: EFLAGS_RESERVED,
+            lazy_flags: None,
             memory: vec![0; memory_size],
             eip,
+            instruction_start: eip,
+            reset_eip: eip,
+            reset_esp: esp,
+            prefixes: Prefixes::default(),
+            halted: false,
+            cr0: CR0_ET,
+            cr2: 0,
+            cr3: 0,
+            debug_address: [0; 4],
+            dr6: DR6_RESERVED,
+            dr7: 0,
+            debug_trap: 0,
+            gdtr: DescriptorTable::default(),
+            idtr: DescriptorTable {
+                base: 0,
+                limit: 0x3ff,
+            },
+            ldtr: Segment::null(0),
+            tr: Segment::null(0),
+            task_switched: false,
+            tlb: Tlb::default(),
+            cycles: 0,
+            execution: Execution::default(),
+            tsc_offset: 0,
+            msrs: [MSR_P5_MC_ADDR, MSR_P5_MC_TYPE, MSR_CESR, MSR_CTR0, MSR_CTR1]
+                .into_iter()
+                .map(|msr| (msr, 0))
+                .collect(),
+            fetch_fault: Cell::new(None),
+            fpu: Fpu::default(),
             instructions: HashMap::new(),
+            two_byte_instructions: HashMap::new(),
         };
         emu.registers[Register::Esp as usize] = esp;
         emu
     }
 
+    /// Puts the processor back in its power-on state, keeping memory and
+    /// the instruction tables, as the 386 does after a shutdown. Time goes
+    /// on, so the cycle counter is kept as well.
+    fn reset(&mut self) {
+        let memory = std::mem::take(&mut self.memory);
+        let instructions = std::mem::take(&mut self.instructions);
+        let two_byte_instructions = std::mem::take(&mut self.two_byte_instructions);
+        *self = Emulator {
+            memory,
+            instructions,
+            two_byte_instructions,
+            cycles: self.cycles,
+            ..Emulator::new(self.model, 0, self.reset_eip, self.reset_esp)
+        };
+    }
+
+    /// Moves EIP past `length` code bytes. IP wraps at 64 KiB in a 16-bit
+    /// code segment.
+    #[inline]
+    fn advance_eip(&mut self, length: u32) {
+        let eip = self.eip.wrapping_add(length);
+        self.eip = if self.segment(SegmentRegister::Cs).big {
+            eip
+        } else {
+            eip & 0xffff
+        };
+    }
+
+    /// Fetches a code byte. A byte beyond the CS limit reads as 0 and
+    /// records the fault. With paging enabled the byte is translated
+    /// through the TLB, which `prefetch` has filled for the pages the
+    /// instruction can span.
+    // Called several times for every instruction; keep the unpaged path
+    // inlined.
+    #[inline]
     pub fn get_code8(&self, index: usize) -> u8 {
-        self.memory[self.eip as usize + index]
+        let offset = self.eip.wrapping_add(index as u32);
+        if offset > self.segment(SegmentRegister::Cs).limit {
+            self.record_fetch_fault(FetchFault::Limit);
+            return 0;
+        }
+        let linear = self.linear_address(SegmentRegister::Cs, offset);
+        if self.cr0 & CR0_PG == 0 {
+            return self.get_memory8(linear);
+        }
+        self.get_paged_code8(linear)
+    }
+
+    fn get_paged_code8(&self, linear: u32) -> u8 {
+        match self.tlb.lookup(linear) {
+            Some(entry) if entry.allows(false, self.cpl() == 3, false) => {
+                self.get_memory8(entry.frame | (linear & !PAGE_FRAME))
+            }
+            _ => {
+                self.record_fetch_fault(FetchFault::Page(linear));
+                0
+            }
+        }
+    }
+
+    /// Keeps the first byte that could not be fetched; it is the one that
+    /// faults, and the one CR2 reports for a #PF.
+    fn record_fetch_fault(&self, fault: FetchFault) {
+        if self.fetch_fault.get().is_none() {
+            self.fetch_fault.set(Some(fault));
+        }
     }
 
     pub fn get_sign_code8(&self, index: usize) -> i8 {
-        self.memory[self.eip as usize + index] as i8
+        self.get_code8(index) as i8
+    }
+
+    fn get_code16(&self, index: usize) -> u16 {
+        self.get_code8(index) as u16 | (self.get_code8(index + 1) as u16) << 8
     }
 
     fn get_code32(&self, index: usize) -> u32 {
@@ -130,6 +559,14 @@ impl Emulator {
         self.get_code32(index) as i32
     }
 
+    /// Fetches a word or dword immediate depending on `size`.
+    fn get_code_sized(&self, index: usize, size: u32) -> u32 {
+        match size {
+            16 => self.get_code16(index) as u32,
+            _ => self.get_code32(index),
+        }
+    }
+
     pub fn get_register8(&self, reg: Register8) -> u8 {
         match reg {
             Register8::Al => self.registers[Register::Eax as usize] as u8,
@@ -143,6 +580,10 @@ impl Emulator {
         }
     }
 
+    pub fn get_register16(&self, index: usize) -> u16 {
+        self.registers[index] as u16
+    }
+
     pub fn get_register32(&self, index: usize) -> u32 {
         self.registers[index]
     }
@@ -162,565 +603,4663 @@ impl Emulator {
         }
     }
 
-    pub fn set_register32(&mut self, index: usize, value: u32) {
-        self.registers[index] = value;
+    pub fn set_register16(&mut self, index: usize, value: u16) {
+        self.registers[index] = (self.registers[index] & 0xffff0000) | u32::from(value);
     }
 
-    pub fn set_memory8(&mut self, address: u32, value: u8) {
-        self.memory[address as usize] = value;
+    pub fn set_register32(&mut self, index: usize, value: u32) {
+        self.registers[index] = value;
     }
 
-    pub fn set_memory32(&mut self, address: u32, value: u32) {
-        for i in 0..4 {
-            self.set_memory8(address + i, ((value >> (i * 8)) & 0xff) as u8);
+    fn get_register_sized(&self, index: usize, size: u32) -> u32 {
+        match size {
+            16 => self.get_register16(index) as u32,
+            _ => self.get_register32(index),
         }
     }
 
-    pub fn get_memory8(&self, address: u32) -> u8 {
-        self.memory[address as usize]
-    }
-
-    pub fn get_memory32(&self, address: u32) -> u32 {
-        let mut ret = 0;
-        for i in 0..4 {
-            ret |= (self.get_memory8(address + i) as u32) << (8 * i);
+    fn set_register_sized(&mut self, index: usize, value: u32, size: u32) {
+        match size {
+            16 => self.set_register16(index, value as u16),
+            _ => self.set_register32(index, value),
         }
-        ret
-    }
-
-    fn push32(&mut self, value: u32) {
-        let address = self.get_register32(4) - 4;
-        self.set_register32(4, address);
-        self.set_memory32(address, value);
-    }
-
-    fn pop32(&mut self) -> u32 {
-        let address = self.get_register32(4);
-        let ret = self.get_memory32(address);
-        self.set_register32(4, address + 4);
-        ret
     }
 
-    fn set_carry(&mut self, is_carry: bool) {
-        if is_carry {
-            self.eflags |= CARRY_FLAG;
+    /// The operand size of the current instruction in bits. The default
+    /// comes from the D bit of CS and 0x66 selects the other size.
+    fn operand_size(&self) -> u32 {
+        if self.segment(SegmentRegister::Cs).big != self.prefixes.operand_size {
+            32
         } else {
-            self.eflags &= !CARRY_FLAG;
+            16
         }
     }
 
-    fn set_zero(&mut self, is_zero: bool) {
-        if is_zero {
-            self.eflags |= ZERO_FLAG;
+    /// The address size of the current instruction in bits, likewise the
+    /// D bit of CS toggled by the 0x67 prefix.
+    fn address_size(&self) -> u32 {
+        if self.segment(SegmentRegister::Cs).big != self.prefixes.address_size {
+            32
         } else {
-            self.eflags &= !ZERO_FLAG;
+            16
         }
     }
 
-    fn set_sign(&mut self, is_sign: bool) {
-        if is_sign {
-            self.eflags |= SIGN_FLAG;
-        } else {
-            self.eflags &= !SIGN_FLAG;
-        }
+    fn segment(&self, reg: SegmentRegister) -> &Segment {
+        &self.segments[reg as usize]
     }
 
-    fn set_overflow(&mut self, is_overflow: bool) {
-        if is_overflow {
-            self.eflags |= OVERFLOW_FLAG;
-        } else {
-            self.eflags &= !OVERFLOW_FLAG;
-        }
+    pub fn get_segment_register(&self, reg: SegmentRegister) -> u16 {
+        self.segment(reg).selector
     }
 
-    fn is_carry(&self) -> bool {
-        self.eflags & CARRY_FLAG != 0
+    fn is_protected_mode(&self) -> bool {
+        self.cr0 & CR0_PE != 0
     }
 
-    fn is_zero(&self) -> bool {
-        self.eflags & ZERO_FLAG != 0
+    fn is_virtual_8086_mode(&self) -> bool {
+        self.eflags & VIRTUAL_8086_FLAG != 0
     }
 
-    fn is_sign(&self) -> bool {
-        self.eflags & SIGN_FLAG != 0
+    /// Whether segment registers are loaded as in real mode, with the base
+    /// at selector * 16 and no descriptors: in real mode and in
+    /// virtual-8086 mode.
+    fn is_real_mode_addressing(&self) -> bool {
+        !self.is_protected_mode() || self.is_virtual_8086_mode()
     }
 
-    fn is_overflow(&self) -> bool {
-        self.eflags & OVERFLOW_FLAG != 0
+    /// The current privilege level: the RPL of CS in protected mode, and
+    /// always 3 in virtual-8086 mode.
+    fn cpl(&self) -> u16 {
+        if self.is_virtual_8086_mode() {
+            3
+        } else if self.is_protected_mode() {
+            self.get_segment_register(SegmentRegister::Cs) & 3
+        } else {
+            0
+        }
     }
 
-    fn update_eflags_sub(&mut self, v1: u32, v2: u32, result: u64) {
-        let sign1 = v1 >> 31;
-        let sign2 = v2 >> 31;
-        let signr = (result >> 31) & 1;
-
-        self.set_carry((result >> 32) != 0);
-        self.set_zero((result & 0xFFFFFFFF) == 0);
-        self.set_sign(signr != 0);
-        self.set_overflow((sign1 != sign2) && (u64::from(sign1) != signr));
+    /// The I/O privilege level from EFLAGS.
+    fn iopl(&self) -> u16 {
+        ((self.eflags & IOPL_MASK) >> 12) as u16
     }
 
-    pub fn read_binary(&mut self, filename: &str) {
-        let mut file = File::open(filename).expect(&format!("Failed to open file: {}", filename));
-        file.read(&mut self.memory[0x7c00..0x7c00 + 0x200])
-            .expect("Failed to read file into memory");
+    /// Whether CLI, STI, IN and OUT may run without further checks: always
+    /// in real mode, and in protected mode when CPL <= IOPL.
+    fn is_io_privileged(&self) -> bool {
+        self.cpl() <= self.iopl()
     }
 
-    pub fn dump_registers(&self) {
-        let register_names = ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"];
-        for (i, &name) in register_names.iter().enumerate() {
-            println!("{} = {:08X}", name, self.registers[i]);
+    /// In virtual-8086 mode PUSHF, POPF, INT n and IRET raise #GP(0) unless
+    /// IOPL is 3, so that the monitor can emulate them.
+    fn check_virtual_8086_iopl(&self) -> Result<(), Exception> {
+        if self.is_virtual_8086_mode() && self.iopl() < 3 {
+            return Err(Exception::GeneralProtection(0));
         }
-        println!("EIP = {:08X}", self.eip);
+        Ok(())
     }
 
-    fn parse_modrm(&mut self) -> ModRM {
-        let code = self.get_code8(0);
-        let mod_val = (code & 0xC0) >> 6;
-        let opecode = (code & 0x38) >> 3;
-        let rm = code & 0x07;
-
-        self.eip += 1;
-
-        let mut modrm = ModRM {
-            mod_val,
-            opecode,
-            rm,
-            sib: None,
-            disp8: None,
-            disp32: None,
-        };
-
-        if modrm.mod_val != 3 && modrm.rm == 4 {
-            modrm.sib = Some(self.get_code8(0));
-            self.eip += 1;
+    /// Instructions a later processor added raise #UD on earlier models.
+    fn require_model(&self, model: CpuModel) -> Result<(), Exception> {
+        if self.model < model {
+            return Err(Exception::InvalidOpcode);
         }
+        Ok(())
+    }
 
-        match modrm.mod_val {
-            0 => {
-                if modrm.rm == 5 {
-                    modrm.disp32 = Some(self.get_sign_code32(0));
-                    self.eip += 4;
-                }
-            }
-            1 => {
-                modrm.disp8 = Some(self.get_sign_code8(0));
-                self.eip += 1;
-            }
-            2 => {
-                modrm.disp32 = Some(self.get_sign_code32(0));
-                self.eip += 4;
-            }
-            _ => {}
+    /// Privileged instructions raise #GP(0) outside CPL 0.
+    fn require_cpl0(&self) -> Result<(), Exception> {
+        if self.cpl() != 0 {
+            return Err(Exception::GeneralProtection(0));
         }
+        Ok(())
+    }
 
-        modrm
+    /// Loads a data or stack segment register; CS is only loaded by far
+    /// transfers. In protected mode the descriptor is read from the GDT or
+    /// LDT and checked: SS needs a writable data segment with RPL and DPL
+    /// equal to CPL and raises #SS if it is not present, and the other
+    /// registers take readable segments whose DPL is at least CPL and RPL,
+    /// unless they are conforming code, or a null selector.
+    pub fn set_segment_register(
+        &mut self,
+        reg: SegmentRegister,
+        selector: u16,
+    ) -> Result<(), Exception> {
+        self.segments[reg as usize] = self.load_segment(reg, selector)?;
+        Ok(())
     }
 
-    pub fn execute_instruction(&mut self, instruction: &Instruction) {
-        match instruction {
-            Instruction::MovR8Imm8 => {
-                self.mov_r8_imm8();
-            }
-            Instruction::MovR32Imm32 => {
-                self.mov_r32_imm32();
-            }
-            Instruction::MovR8Rm8 => {
-                self.mov_r8_rm8();
-            }
-            Instruction::MovR32Rm32 => {
-                self.mov_r32_rm32();
-            }
-            Instruction::AddRm32R32 => {
-                self.add_rm32_r32();
-            }
-            Instruction::MovRm8R8 => {
-                self.mov_rm8_r8();
-            }
-            Instruction::MovRm32R32 => {
-                self.mov_rm32_r32();
-            }
-            Instruction::IncR32 => {
-                self.inc_r32();
-            }
-            Instruction::PushR32 => {
-                self.push_r32();
-            }
-            Instruction::PopR32 => {
-                self.pop_r32();
-            }
-            Instruction::PushImm32 => {
-                self.push_imm32();
-            }
-            Instruction::PushImm8 => {
-                self.push_imm8();
-            }
-            Instruction::Code83 => {
-                self.code_83();
-            }
-            Instruction::MovRm32Imm32 => {
-                self.mov_rm32_imm32();
-            }
-            Instruction::InAlDx => {
-                self.in_al_dx();
-            }
-            Instruction::OutDxAl => {
-                self.out_dx_al();
-            }
-            Instruction::CodeFf => {
-                self.code_ff();
-            }
-            Instruction::CallRel32 => {
-                self.call_rel32();
-            }
-            Instruction::Ret => {
-                self.ret();
-            }
-            Instruction::Leave => {
-                self.leave();
-            }
-            Instruction::ShortJump => {
-                self.short_jump();
-            }
-            Instruction::NearJump => {
-                self.near_jump();
-            }
-            Instruction::CmpAlImm8 => {
-                self.cmp_al_imm8();
-            }
-            Instruction::CmpEaxImm32 => {
-                self.cmp_eax_imm32();
-            }
-            Instruction::CmpR32Rm32 => {
-                self.cmp_r32_rm32();
-            }
-            Instruction::Jc => {
-                self.jc();
-            }
-            Instruction::Jnc => {
-                self.jnc();
-            }
-            Instruction::Jz => {
-                self.jz();
-            }
-            Instruction::Jnz => {
-                self.jnz();
-            }
-            Instruction::Js => {
-                self.js();
-            }
-            Instruction::Jns => {
-                self.jns();
-            }
-            Instruction::Jo => {
-                self.jo();
-            }
-            Instruction::Jno => {
-                self.jno();
-            }
-            Instruction::Jl => {
-                self.jl();
+    /// Builds the descriptor cache for loading `selector` into `reg`,
+    /// without changing the register itself.
+    fn load_segment(&mut self, reg: SegmentRegister, selector: u16) -> Result<Segment, Exception> {
+        if self.is_real_mode_addressing() {
+            let mut segment = *self.segment(reg);
+            segment.load_real_mode(selector);
+            return Ok(segment);
+        }
+        if selector & !3 == 0 {
+            if reg == SegmentRegister::Ss {
+                return Err(Exception::GeneralProtection(0));
             }
-            Instruction::Jle => {
-                self.jle();
+            return Ok(Segment::null(selector));
+        }
+        let mut descriptor = self.read_descriptor(selector)?;
+        let error_code = selector & 0xfffc;
+        let cpl = self.cpl();
+        let rpl = selector & 3;
+        let is_valid = match reg {
+            SegmentRegister::Ss => {
+                descriptor.is_writable() && rpl == cpl && descriptor.dpl() == cpl
             }
-            Instruction::Swi => {
-                self.swi();
+            _ => {
+                descriptor.is_readable()
+                    && (descriptor.is_conforming() || descriptor.dpl() >= cpl.max(rpl))
             }
+        };
+        if !is_valid {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(match reg {
+                SegmentRegister::Ss => Exception::StackFault(error_code),
+                _ => Exception::SegmentNotPresent(error_code),
+            });
         }
+        self.set_descriptor_accessed(selector, &mut descriptor)?;
+        Ok(descriptor.to_segment(selector))
     }
 
-    fn mov_r8_imm8(&mut self) {
-        if let Some(reg) = Register8::from_usize((self.get_code8(0) - 0xB0) as usize) {
-            let value = self.get_code8(1);
-            self.set_register8(reg, value);
-            self.eip += 2;
+    /// The linear address of the descriptor `selector` refers to in the GDT
+    /// or, with the TI bit set, the LDT. Selectors beyond the table limit
+    /// raise #GP with the selector as the error code.
+    fn descriptor_address(&self, selector: u16) -> Result<u32, Exception> {
+        let (base, limit) = if selector & 4 != 0 {
+            if !self.ldtr.is_usable() {
+                return Err(Exception::GeneralProtection(selector & 0xfffc));
+            }
+            (self.ldtr.base, self.ldtr.limit)
         } else {
-            eprintln!("Error: Invalid register index in mov_r8_imm8");
+            (self.gdtr.base, self.gdtr.limit as u32)
+        };
+        let index = (selector & !7) as u32;
+        if index + 7 > limit {
+            return Err(Exception::GeneralProtection(selector & 0xfffc));
         }
+        Ok(base.wrapping_add(index))
     }
 
-    fn mov_r32_imm32(&mut self) {
-        let reg = (self.get_code8(0) - 0xB8) as usize;
-        let value = self.get_code32(1);
-        self.set_register32(reg, value);
-        self.eip += 5;
+    /// Reads the 8 bytes of the descriptor `selector` refers to, which may
+    /// hold a segment descriptor or a gate.
+    fn read_raw_descriptor(&mut self, selector: u16) -> Result<u64, Exception> {
+        let address = self.descriptor_address(selector)?;
+        let low = self.read_linear(address, 32, false)? as u64;
+        let high = self.read_linear(address.wrapping_add(4), 32, false)? as u64;
+        Ok(high << 32 | low)
     }
 
-    fn mov_r8_rm8(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let rm8 = modrm.get_rm8(self);
-        modrm.set_r8(self, rm8);
+    fn read_descriptor(&mut self, selector: u16) -> Result<Descriptor, Exception> {
+        Ok(Descriptor::from_u64(self.read_raw_descriptor(selector)?))
     }
 
-    fn mov_r32_rm32(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let rm32 = modrm.get_rm32(self);
-        modrm.set_r32(self, rm32);
+    /// Writes back the access rights byte of a descriptor whose accessed or
+    /// busy bit has changed.
+    fn write_descriptor_access(&mut self, selector: u16, access: u8) -> Result<(), Exception> {
+        let address = self.descriptor_address(selector)?;
+        self.write_linear(address.wrapping_add(5), access as u32, 8, false)
     }
 
-    fn add_rm32_r32(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let r32 = modrm.get_r32(self);
-        let rm32 = modrm.get_rm32(self);
-        modrm.set_rm32(self, rm32 + r32);
+    /// Sets the accessed bit of a code or data segment descriptor, both in
+    /// memory and in the copy that will be cached.
+    fn set_descriptor_accessed(
+        &mut self,
+        selector: u16,
+        descriptor: &mut Descriptor,
+    ) -> Result<(), Exception> {
+        if descriptor.access & ACCESS_ACCESSED == 0 {
+            descriptor.access |= ACCESS_ACCESSED;
+            self.write_descriptor_access(selector, descriptor.access)?;
+        }
+        Ok(())
     }
 
-    fn mov_rm8_r8(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let r8 = modrm.get_r8(self);
-        modrm.set_rm8(self, r8);
+    /// Reads a descriptor for LAR, LSL, VERR and VERW, which report a bad
+    /// selector in ZF instead of faulting: null selectors and selectors
+    /// beyond the table limit give `None`.
+    fn probe_descriptor(&mut self, selector: u16) -> Result<Option<Descriptor>, Exception> {
+        if selector & !3 == 0 || self.descriptor_address(selector).is_err() {
+            return Ok(None);
+        }
+        self.read_descriptor(selector).map(Some)
     }
 
-    fn mov_rm32_r32(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let r32 = modrm.get_r32(&self);
-        modrm.set_rm32(self, r32);
+    /// Whether the descriptor can be examined at the current privilege
+    /// level: its DPL must be at least CPL and the RPL of the selector,
+    /// except for conforming code segments.
+    fn is_descriptor_visible(&self, selector: u16, descriptor: &Descriptor) -> bool {
+        descriptor.is_conforming() || descriptor.dpl() >= self.cpl().max(selector & 3)
     }
 
-    fn inc_r32(&mut self) {
-        let reg = (self.get_code8(0) - 0x40) as usize;
-        let value = self.get_register32(reg) + 1;
-        self.set_register32(reg, value);
-        self.eip += 1;
+    /// Whether the current TSS has the 386 format rather than the 286 one.
+    fn is_tss_32bit(&self) -> bool {
+        matches!(self.tr.access & 0x0f, SYSTEM_TSS | SYSTEM_TSS_BUSY)
     }
 
-    fn push_r32(&mut self) {
-        let reg = (self.get_code8(0) - 0x50) as usize;
-        let value = self.get_register32(reg);
-        self.push32(value);
-        self.eip += 1;
+    /// Loads SS:ESP for privilege level `dpl` from the current TSS, as a
+    /// transfer to an inner level does. A bad stack selector raises #TS and
+    /// a stack segment that is not present #SS; `ext` is the EXT bit for
+    /// their error codes.
+    fn load_inner_stack(&mut self, dpl: u16, ext: u16) -> Result<(), Exception> {
+        let tss_error = self.tr.selector & 0xfffc | ext;
+        if !self.tr.is_usable() {
+            return Err(Exception::InvalidTss(tss_error));
+        }
+        // ESPn and SSn follow the back link in a 32-bit TSS, SPn and SSn
+        // in a 16-bit one.
+        let (offset, size) = if self.is_tss_32bit() {
+            (4 + 8 * dpl as u32, 32)
+        } else {
+            (2 + 4 * dpl as u32, 16)
+        };
+        if offset + size / 8 + 1 > self.tr.limit {
+            return Err(Exception::InvalidTss(tss_error));
+        }
+        let address = self.tr.base.wrapping_add(offset);
+        let esp = self.read_linear(address, size, false)?;
+        let selector = self.read_linear(address.wrapping_add(size / 8), 16, false)? as u16;
+        let error_code = selector & 0xfffc | ext;
+        if selector & !3 == 0 {
+            return Err(Exception::InvalidTss(ext));
+        }
+        if selector & 3 != dpl || self.descriptor_address(selector).is_err() {
+            return Err(Exception::InvalidTss(error_code));
+        }
+        let mut descriptor = self.read_descriptor(selector)?;
+        if !descriptor.is_writable() || descriptor.dpl() != dpl {
+            return Err(Exception::InvalidTss(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::StackFault(error_code));
+        }
+        self.set_descriptor_accessed(selector, &mut descriptor)?;
+        self.segments[SegmentRegister::Ss as usize] = descriptor.to_segment(selector);
+        self.set_register32(Register::Esp as usize, esp);
+        Ok(())
     }
 
-    fn pop_r32(&mut self) {
-        let reg = (self.get_code8(0) - 0x58) as usize;
-        let value = self.pop32();
-        self.set_register32(reg, value);
-        self.eip += 1;
+    /// After a return to an outer privilege level, data segment registers
+    /// the new CPL may not use are loaded with null selectors.
+    fn invalidate_data_segments(&mut self) {
+        let cpl = self.cpl();
+        for reg in [
+            SegmentRegister::Es,
+            SegmentRegister::Ds,
+            SegmentRegister::Fs,
+            SegmentRegister::Gs,
+        ] {
+            let segment = self.segment(reg);
+            if segment.is_usable() && !segment.is_conforming() && segment.dpl() < cpl {
+                self.segments[reg as usize] = Segment::null(0);
+            }
+        }
     }
 
-    fn push_imm32(&mut self) {
-        let value = self.get_code32(1);
-        self.push32(value);
-        self.eip += 5;
+    /// The segment a data access goes through: `default`, unless the
+    /// instruction has a segment override prefix.
+    pub fn segment_override(&self, default: SegmentRegister) -> SegmentRegister {
+        self.prefixes.segment.unwrap_or(default)
     }
 
-    fn push_imm8(&mut self) {
-        let value = self.get_code8(1) as u32;
-        self.push32(value);
-        self.eip += 2;
+    /// Translates `segment:offset` into a linear address.
+    pub fn linear_address(&self, reg: SegmentRegister, offset: u32) -> u32 {
+        self.segment(reg).base.wrapping_add(offset)
     }
 
-    fn add_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = modrm.get_rm32(self);
-        let imm8 = self.get_sign_code8(0) as i32 as u32;
-        self.eip += 1;
-        modrm.set_rm32(self, rm32.wrapping_add(imm8));
+    /// Checks an access of `length` bytes at `offset` against the limit of
+    /// a segment and, in protected mode outside virtual-8086 mode, its type,
+    /// and returns the linear address. Violations raise #SS(0) for the stack
+    /// segment and #GP(0) otherwise.
+    fn segment_address(
+        &self,
+        reg: SegmentRegister,
+        offset: u32,
+        length: u32,
+        is_write: bool,
+    ) -> Result<u32, Exception> {
+        let segment = self.segment(reg);
+        let fault = match reg {
+            SegmentRegister::Ss => Exception::StackFault(0),
+            _ => Exception::GeneralProtection(0),
+        };
+        if !self.is_real_mode_addressing() {
+            if !segment.is_usable() {
+                return Err(fault);
+            }
+            let is_allowed = if is_write {
+                segment.is_writable()
+            } else {
+                segment.is_readable()
+            };
+            if !is_allowed {
+                return Err(Exception::GeneralProtection(0));
+            }
+        }
+        if !segment.contains(offset, length) {
+            return Err(fault);
+        }
+        Ok(segment.base.wrapping_add(offset))
     }
 
-    fn cmp_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = modrm.get_rm32(self);
-        let imm8 = self.get_sign_code8(0) as i32 as u32;
-        self.eip += 1;
-        let result = (rm32 as u64).wrapping_sub(imm8 as u64);
-        self.update_eflags_sub(rm32, imm8, result);
+    /// Reads a byte, word or dword at `segment:offset`.
+    pub fn read_memory(
+        &mut self,
+        reg: SegmentRegister,
+        offset: u32,
+        size: u32,
+    ) -> Result<u32, Exception> {
+        let address = self.segment_address(reg, offset, size / 8, false)?;
+        self.read_linear(address, size, self.cpl() == 3)
     }
 
-    fn sub_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = modrm.get_rm32(self);
-        let imm8 = self.get_sign_code8(0) as i32 as u32;
-        self.eip += 1;
-        let result = (rm32 as u64).wrapping_sub(imm8 as u64);
-        modrm.set_rm32(self, rm32.wrapping_sub(imm8));
-        self.update_eflags_sub(rm32, imm8, result);
+    /// Writes a byte, word or dword at `segment:offset`.
+    pub fn write_memory(
+        &mut self,
+        reg: SegmentRegister,
+        offset: u32,
+        value: u32,
+        size: u32,
+    ) -> Result<(), Exception> {
+        let address = self.segment_address(reg, offset, size / 8, true)?;
+        self.write_linear(address, value, size, self.cpl() == 3)
     }
 
-    fn code_83(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        match modrm.opecode {
-            0 => self.add_rm32_imm8(&modrm),
-            5 => self.sub_rm32_imm8(&modrm),
-            7 => self.cmp_rm32_imm8(&modrm),
+    /// Translates a linear address to a physical one. Without paging the
+    /// two are the same; otherwise the TLB is consulted first and the page
+    /// tables walked on a miss. `is_user` selects the CPL 3 checks, which
+    /// accesses to descriptor tables never get.
+    fn translate(&mut self, linear: u32, is_write: bool, is_user: bool) -> Result<u32, Exception> {
+        if self.cr0 & CR0_PG == 0 {
+            return Ok(linear);
+        }
+        let entry = match self.tlb.lookup(linear) {
+            Some(entry) if entry.allows(is_write, is_user, self.cr0 & CR0_WP != 0) => entry,
             _ => {
-                println!("not implemented: 83 /{}", modrm.opecode);
-                std::process::exit(1);
+                let entry = self.walk_page_tables(linear, is_write, is_user)?;
+                self.tlb.insert(linear, entry);
+                entry
             }
+        };
+        Ok(entry.frame | (linear & !PAGE_FRAME))
+    }
+
+    /// Walks the page directory and page table for `linear`, checking the
+    /// P, R/W and U/S bits of both levels and setting the accessed bits and,
+    /// for writes, the dirty bit. Supervisor writes ignore R/W unless CR0.WP
+    /// is set, which the 386 does not have.
+    fn walk_page_tables(
+        &mut self,
+        linear: u32,
+        is_write: bool,
+        is_user: bool,
+    ) -> Result<TlbEntry, Exception> {
+        let directory_address = (self.cr3 & PAGE_FRAME) | (linear >> 22) << 2;
+        let directory = self.get_memory32(directory_address);
+        if directory & PTE_PRESENT == 0 {
+            return Err(self.page_fault(linear, 0, is_write, is_user));
         }
+        let table_address = (directory & PAGE_FRAME) | (linear >> 12 & 0x3ff) << 2;
+        let table = self.get_memory32(table_address);
+        if table & PTE_PRESENT == 0 {
+            return Err(self.page_fault(linear, 0, is_write, is_user));
+        }
+        let user = directory & table & PTE_USER != 0;
+        let writable = directory & table & PTE_WRITABLE != 0;
+        let write_protect = is_user || self.cr0 & CR0_WP != 0;
+        if (is_user && !user) || (is_write && write_protect && !writable) {
+            return Err(self.page_fault(linear, PF_PROTECTION, is_write, is_user));
+        }
+        if directory & PTE_ACCESSED == 0 {
+            self.set_memory32(directory_address, directory | PTE_ACCESSED);
+        }
+        let updated = table | PTE_ACCESSED | if is_write { PTE_DIRTY } else { 0 };
+        if updated != table {
+            self.set_memory32(table_address, updated);
+        }
+        Ok(TlbEntry {
+            frame: table & PAGE_FRAME,
+            writable,
+            user,
+            dirty: updated & PTE_DIRTY != 0,
+        })
     }
 
-    fn mov_rm32_imm32(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        let value = self.get_code32(0);
-        self.eip += 4;
-        modrm.set_rm32(self, value);
+    /// Records the faulting address in CR2 and builds the #PF.
+    fn page_fault(&mut self, linear: u32, code: u16, is_write: bool, is_user: bool) -> Exception {
+        self.cr2 = linear;
+        let mut code = code;
+        if is_write {
+            code |= PF_WRITE;
+        }
+        if is_user {
+            code |= PF_USER;
+        }
+        Exception::PageFault(code)
     }
 
-    fn in_al_dx(&mut self) {
-        let address = self.get_register32(Register::Edx as usize) & 0xffff;
-        let value = io_in8(address as u16);
-        self.set_register8(Register8::Al, value);
-        self.eip += 1;
+    /// Whether an access of `length` bytes at `linear` spills into the next
+    /// page.
+    fn crosses_page(linear: u32, length: u32) -> bool {
+        (linear & !PAGE_FRAME) + length > PAGE_SIZE
     }
 
-    fn out_dx_al(&mut self) {
-        let address = self.get_register32(Register::Edx as usize) & 0xffff;
-        let value = self.get_register8(Register8::Al);
-        io_out8(address as u16, value);
-        self.eip += 1;
+    /// Raises #AC for a misaligned word or dword access at CPL 3 when both
+    /// CR0.AM and EFLAGS.AC are set, which only a 486 or later allows.
+    fn check_alignment(&self, linear: u32, size: u32, is_user: bool) -> Result<(), Exception> {
+        if is_user
+            && linear & (size / 8 - 1) != 0
+            && self.cr0 & CR0_AM != 0
+            && self.eflags & ALIGNMENT_CHECK_FLAG != 0
+        {
+            return Err(Exception::AlignmentCheck);
+        }
+        Ok(())
     }
 
-    fn inc_rm32(&mut self, modrm: &ModRM) {
-        let value = modrm.get_rm32(self);
-        modrm.set_rm32(self, value.wrapping_add(1));
+    /// Reads a byte, word or dword at a linear address.
+    fn read_linear(&mut self, linear: u32, size: u32, is_user: bool) -> Result<u32, Exception> {
+        self.check_alignment(linear, size, is_user)?;
+        let physical = self.translate(linear, false, is_user)?;
+        if self.dr7 & DR7_ENABLE != 0 {
+            self.check_data_breakpoints(linear, size / 8, false);
+        }
+        if !Self::crosses_page(linear, size / 8) {
+            return Ok(self.get_memory_sized(physical, size));
+        }
+        let bytes = self.read_linear_bytes(linear, size as usize / 8, is_user)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as u32))
     }
 
-    fn code_ff(&mut self) {
-        self.eip += 1;
-        let modrm = self.parse_modrm();
-        match modrm.opecode {
-            0 => self.inc_rm32(&modrm),
-            _ => {
-                println!("not implemented: FF /{}", modrm.opecode);
-                std::process::exit(1);
-            }
+    /// Writes a byte, word or dword at a linear address. Both pages of a
+    /// split access are checked before anything is written.
+    fn write_linear(
+        &mut self,
+        linear: u32,
+        value: u32,
+        size: u32,
+        is_user: bool,
+    ) -> Result<(), Exception> {
+        self.check_fetch_fault()?;
+        self.check_alignment(linear, size, is_user)?;
+        let physical = self.translate(linear, true, is_user)?;
+        if self.dr7 & DR7_ENABLE != 0 {
+            self.check_data_breakpoints(linear, size / 8, true);
         }
+        if !Self::crosses_page(linear, size / 8) {
+            self.set_memory_sized(physical, value, size);
+            return Ok(());
+        }
+        let bytes = value.to_le_bytes();
+        self.write_linear_bytes(linear, &bytes[..size as usize / 8], is_user)
     }
 
-    fn call_rel32(&mut self) {
-        let diff = self.get_sign_code32(1) as i32;
-        self.push32(self.eip + 5);
-        self.eip = self.eip.wrapping_add(diff as u32 + 5);
+    /// Translates both pages of an access that may cross a page boundary,
+    /// so that it can fault before any of it is carried out. A fault on the
+    /// second page reports the first byte of that page in CR2.
+    fn translate_range(
+        &mut self,
+        linear: u32,
+        length: u32,
+        is_write: bool,
+        is_user: bool,
+    ) -> Result<(), Exception> {
+        self.translate(linear, is_write, is_user)?;
+        if Self::crosses_page(linear, length) {
+            let next = (linear & PAGE_FRAME).wrapping_add(PAGE_SIZE);
+            self.translate(next, is_write, is_user)?;
+        }
+        Ok(())
     }
 
-    fn ret(&mut self) {
-        self.eip = self.pop32();
+    fn read_linear_bytes(
+        &mut self,
+        linear: u32,
+        length: usize,
+        is_user: bool,
+    ) -> Result<Vec<u8>, Exception> {
+        if self.dr7 & DR7_ENABLE != 0 {
+            self.check_data_breakpoints(linear, length as u32, false);
+        }
+        (0..length)
+            .map(|i| {
+                let physical = self.translate(linear.wrapping_add(i as u32), false, is_user)?;
+                Ok(self.get_memory8(physical))
+            })
+            .collect()
     }
 
-    fn leave(&mut self) {
-        let ebp = self.get_register32(Register::Ebp as usize);
-        let popped_value = self.pop32();
-        self.set_register32(Register::Esp as usize, ebp);
-        self.set_register32(Register::Ebp as usize, popped_value);
-        self.eip += 1;
+    fn write_linear_bytes(
+        &mut self,
+        linear: u32,
+        bytes: &[u8],
+        is_user: bool,
+    ) -> Result<(), Exception> {
+        self.check_fetch_fault()?;
+        self.translate_range(linear, bytes.len() as u32, true, is_user)?;
+        if self.dr7 & DR7_ENABLE != 0 {
+            self.check_data_breakpoints(linear, bytes.len() as u32, true);
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            let physical = self.translate(linear.wrapping_add(i as u32), true, is_user)?;
+            self.set_memory8(physical, byte);
+        }
+        Ok(())
     }
 
-    fn short_jump(&mut self) {
-        let diff = self.get_sign_code8(1) as i8;
-        self.eip = self.eip.wrapping_add(diff as u32 + 2);
+    /// Fills the TLB for the code the next instruction can occupy: the page
+    /// at EIP, which must be present, and the following page if an
+    /// instruction of up to 15 bytes could reach it. A fault on the second
+    /// page is only raised if a byte of it is actually fetched.
+    fn prefetch(&mut self) -> Result<(), Exception> {
+        let is_user = self.cpl() == 3;
+        let linear = self.linear_address(SegmentRegister::Cs, self.eip);
+        self.translate(linear, false, is_user)?;
+        let cr2 = self.cr2;
+        if self.translate_range(linear, 15, false, is_user).is_err() {
+            self.cr2 = cr2;
+        }
+        Ok(())
     }
 
-    fn near_jump(&mut self) {
-        let diff = self.get_sign_code32(1) as i32;
-        self.eip = self.eip.wrapping_add(diff as u32 + 5);
+    /// Raises the #GP or #PF for a code byte that could not be fetched.
+    fn check_fetch_fault(&mut self) -> Result<(), Exception> {
+        match self.fetch_fault.take() {
+            Some(FetchFault::Limit) => Err(Exception::GeneralProtection(0)),
+            Some(FetchFault::Page(linear)) => {
+                self.translate(linear, false, self.cpl() == 3)?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
-    fn cmp_al_imm8(&mut self) {
+    /// The R/W field and the length in bytes of breakpoint `n`, if it is
+    /// enabled. LEN 2 is undefined on the 386 and taken as 4 bytes.
+    fn breakpoint(&self, n: usize) -> Option<(u32, u32)> {
+        if self.dr7 >> (n * 2) & 3 == 0 {
+            return None;
+        }
+        let control = self.dr7 >> (16 + n * 4);
+        let length = match control >> 2 & 3 {
+            0 => 1,
+            1 => 2,
+            _ => 4,
+        };
+        Some((control & 3, length))
+    }
+
+    /// Raises #DB if an instruction breakpoint is set on the linear address
+    /// of the next instruction, including its prefixes. This is a fault, so
+    /// the handler returns with RF set in the pushed EFLAGS to let the
+    /// instruction run.
+    fn check_instruction_breakpoints(&mut self) -> Result<(), Exception> {
+        if self.dr7 & DR7_ENABLE == 0 || self.eflags & RESUME_FLAG != 0 {
+            return Ok(());
+        }
+        let linear = self.linear_address(SegmentRegister::Cs, self.eip);
+        let mut hits = 0;
+        for n in 0..4 {
+            if let Some((BREAK_EXECUTE, _)) = self.breakpoint(n) {
+                if self.debug_address[n] == linear {
+                    hits |= 1 << n;
+                }
+            }
+        }
+        if hits != 0 {
+            self.dr6 |= hits;
+            return Err(Exception::Debug);
+        }
+        Ok(())
+    }
+
+    /// Records the data breakpoints that an access of `length` bytes at
+    /// `linear` overlaps. Write breakpoints only match writes. A breakpoint
+    /// covers the `LEN` bytes its address falls in, aligned to the length.
+    fn check_data_breakpoints(&mut self, linear: u32, length: u32, is_write: bool) {
+        for n in 0..4 {
+            let size = match self.breakpoint(n) {
+                Some((BREAK_WRITE, size)) if is_write => size,
+                Some((BREAK_READ_WRITE, size)) => size,
+                _ => continue,
+            };
+            let start = self.debug_address[n] & !(size - 1);
+            if linear.wrapping_sub(start) < size || start.wrapping_sub(linear) < length {
+                self.debug_trap |= 1 << n;
+            }
+        }
+    }
+
+    /// Writes beyond the end of physical memory are dropped.
+    pub fn set_memory8(&mut self, address: u32, value: u8) {
+        if let Some(byte) = self.memory.get_mut(address as usize) {
+            *byte = value;
+        }
+    }
+
+    pub fn set_memory32(&mut self, address: u32, value: u32) {
+        for i in 0..4 {
+            self.set_memory8(address + i, ((value >> (i * 8)) & 0xff) as u8);
+        }
+    }
+
+    /// Reads beyond the end of physical memory return 0xFF, as from an
+    /// empty bus.
+    pub fn get_memory8(&self, address: u32) -> u8 {
+        self.memory.get(address as usize).copied().unwrap_or(0xff)
+    }
+
+    pub fn get_memory16(&self, address: u32) -> u16 {
+        self.get_memory8(address) as u16 | (self.get_memory8(address + 1) as u16) << 8
+    }
+
+    pub fn set_memory16(&mut self, address: u32, value: u16) {
+        self.set_memory8(address, value as u8);
+        self.set_memory8(address + 1, (value >> 8) as u8);
+    }
+
+    pub fn get_memory32(&self, address: u32) -> u32 {
+        let mut ret = 0;
+        for i in 0..4 {
+            ret |= (self.get_memory8(address + i) as u32) << (8 * i);
+        }
+        ret
+    }
+
+    fn get_memory_sized(&self, address: u32, size: u32) -> u32 {
+        match size {
+            8 => self.get_memory8(address) as u32,
+            16 => self.get_memory16(address) as u32,
+            _ => self.get_memory32(address),
+        }
+    }
+
+    fn set_memory_sized(&mut self, address: u32, value: u32, size: u32) {
+        match size {
+            8 => self.set_memory8(address, value as u8),
+            16 => self.set_memory16(address, value as u16),
+            _ => self.set_memory32(address, value),
+        }
+    }
+
+    fn get_accumulator(&self, size: u32) -> u32 {
+        let eax = self.get_register32(Register::Eax as usize);
+        match size {
+            8 => eax & 0xff,
+            16 => eax & 0xffff,
+            _ => eax,
+        }
+    }
+
+    fn set_accumulator(&mut self, value: u32, size: u32) {
+        let eax = self.get_register32(Register::Eax as usize);
+        let value = match size {
+            8 => (eax & 0xffffff00) | (value & 0xff),
+            16 => (eax & 0xffff0000) | (value & 0xffff),
+            _ => value,
+        };
+        self.set_register32(Register::Eax as usize, value);
+    }
+
+    /// The stack address size: 32 (ESP) if the B bit of SS is set, 16 (SP)
+    /// otherwise.
+    fn stack_size(&self) -> u32 {
+        if self.segment(SegmentRegister::Ss).big {
+            32
+        } else {
+            16
+        }
+    }
+
+    fn get_stack_pointer(&self) -> u32 {
+        self.get_register_sized(Register::Esp as usize, self.stack_size())
+    }
+
+    fn set_stack_pointer(&mut self, value: u32) {
+        self.set_register_sized(Register::Esp as usize, value, self.stack_size());
+    }
+
+    /// Pushes a word or dword. eSP is only updated once the write has
+    /// succeeded.
+    fn push_sized(&mut self, value: u32, size: u32) -> Result<(), Exception> {
+        let mask = ((1u64 << self.stack_size()) - 1) as u32;
+        let sp = self.get_stack_pointer().wrapping_sub(size / 8) & mask;
+        self.write_memory(SegmentRegister::Ss, sp, value, size)?;
+        self.set_stack_pointer(sp);
+        Ok(())
+    }
+
+    fn pop_sized(&mut self, size: u32) -> Result<u32, Exception> {
+        let sp = self.get_stack_pointer();
+        let value = self.read_memory(SegmentRegister::Ss, sp, size)?;
+        self.set_stack_pointer(sp.wrapping_add(size / 8));
+        Ok(value)
+    }
+
+    /// Returns EFLAGS with any pending arithmetic flags computed.
+    pub fn get_eflags(&self) -> u32 {
+        match &self.lazy_flags {
+            Some(lazy) => {
+                let mut eflags = self.eflags & !ARITHMETIC_FLAGS;
+                for (mask, is_set) in [
+                    (CARRY_FLAG, lazy.carry()),
+                    (PARITY_FLAG, lazy.parity()),
+                    (AUX_CARRY_FLAG, lazy.aux_carry()),
+                    (ZERO_FLAG, lazy.zero()),
+                    (SIGN_FLAG, lazy.sign()),
+                    (OVERFLOW_FLAG, lazy.overflow()),
+                ] {
+                    if is_set {
+                        eflags |= mask;
+                    }
+                }
+                eflags
+            }
+            None => self.eflags,
+        }
+    }
+
+    /// Loads EFLAGS as a whole, as POPF does. Reserved bits keep their fixed
+    /// values and any pending arithmetic flags are discarded.
+    fn set_eflags(&mut self, value: u32) {
+        self.lazy_flags = None;
+        let writable = match self.model {
+            CpuModel::I386 => EFLAGS_WRITABLE,
+            CpuModel::I486 => EFLAGS_WRITABLE_486,
+            CpuModel::Pentium => EFLAGS_WRITABLE_PENTIUM,
+        };
+        self.eflags = (value & writable) | EFLAGS_RESERVED;
+    }
+
+    /// Loads EFLAGS for POPF and IRET. In protected mode IOPL only changes
+    /// at CPL 0 and IF only when CPL <= IOPL; otherwise those bits keep
+    /// their values without a fault.
+    fn set_eflags_privileged(&mut self, value: u32) {
+        let mut kept = 0;
+        if self.cpl() > 0 {
+            kept |= IOPL_MASK;
+        }
+        if !self.is_io_privileged() {
+            kept |= INTERRUPT_FLAG;
+        }
+        let eflags = self.get_eflags();
+        self.set_eflags(value & !kept | eflags & kept);
+    }
+
+    /// Folds pending arithmetic flags into `eflags` so that individual bits
+    /// can be modified directly.
+    fn materialize_flags(&mut self) {
+        if self.lazy_flags.is_some() {
+            self.eflags = self.get_eflags();
+            self.lazy_flags = None;
+        }
+    }
+
+    fn set_flag(&mut self, mask: u32, is_set: bool) {
+        if mask & ARITHMETIC_FLAGS != 0 {
+            self.materialize_flags();
+        }
+        if is_set {
+            self.eflags |= mask;
+        } else {
+            self.eflags &= !mask;
+        }
+    }
+
+    fn set_carry(&mut self, is_carry: bool) {
+        self.set_flag(CARRY_FLAG, is_carry);
+    }
+
+    fn set_zero(&mut self, is_zero: bool) {
+        self.set_flag(ZERO_FLAG, is_zero);
+    }
+
+    fn set_sign(&mut self, is_sign: bool) {
+        self.set_flag(SIGN_FLAG, is_sign);
+    }
+
+    fn set_overflow(&mut self, is_overflow: bool) {
+        self.set_flag(OVERFLOW_FLAG, is_overflow);
+    }
+
+    fn set_parity(&mut self, is_parity: bool) {
+        self.set_flag(PARITY_FLAG, is_parity);
+    }
+
+    fn set_aux_carry(&mut self, is_aux_carry: bool) {
+        self.set_flag(AUX_CARRY_FLAG, is_aux_carry);
+    }
+
+    fn set_direction(&mut self, is_direction: bool) {
+        self.set_flag(DIRECTION_FLAG, is_direction);
+    }
+
+    fn is_carry(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.carry(),
+            None => self.eflags & CARRY_FLAG != 0,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.zero(),
+            None => self.eflags & ZERO_FLAG != 0,
+        }
+    }
+
+    fn is_sign(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.sign(),
+            None => self.eflags & SIGN_FLAG != 0,
+        }
+    }
+
+    fn is_overflow(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.overflow(),
+            None => self.eflags & OVERFLOW_FLAG != 0,
+        }
+    }
+
+    fn is_parity(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.parity(),
+            None => self.eflags & PARITY_FLAG != 0,
+        }
+    }
+
+    fn is_aux_carry(&self) -> bool {
+        match &self.lazy_flags {
+            Some(lazy) => lazy.aux_carry(),
+            None => self.eflags & AUX_CARRY_FLAG != 0,
+        }
+    }
+
+    fn is_direction(&self) -> bool {
+        self.eflags & DIRECTION_FLAG != 0
+    }
+
+    /// Evaluates the condition encoded in the low nibble of a Jcc or SETcc
+    /// opcode. Odd encodings are the negation of the preceding even one.
+    fn check_condition(&self, cc: u8) -> bool {
+        let condition = match (cc & 0x0f) >> 1 {
+            0 => self.is_overflow(),
+            1 => self.is_carry(),
+            2 => self.is_zero(),
+            3 => self.is_carry() || self.is_zero(),
+            4 => self.is_sign(),
+            5 => self.is_parity(),
+            6 => self.is_sign() != self.is_overflow(),
+            _ => self.is_zero() || (self.is_sign() != self.is_overflow()),
+        };
+        condition != (cc & 1 != 0)
+    }
+
+    fn update_eflags_result(&mut self, result: u32, size: u32) {
+        let mask = (1u64 << size) - 1;
+        let result = u64::from(result) & mask;
+        self.set_zero(result == 0);
+        self.set_sign((result >> (size - 1)) & 1 != 0);
+        self.set_parity((result as u8).count_ones().is_multiple_of(2));
+    }
+
+    /// Records `op` as the source of the arithmetic flags and returns its
+    /// result truncated to `size` bits.
+    fn record_flags(
+        &mut self,
+        op: FlagsOp,
+        v1: u32,
+        v2: u32,
+        result: u32,
+        carry: bool,
+        size: u32,
+    ) -> u32 {
+        let mask = ((1u64 << size) - 1) as u32;
+        self.lazy_flags = Some(LazyFlags {
+            op,
+            v1: v1 & mask,
+            v2: v2 & mask,
+            result: result & mask,
+            size,
+            carry,
+        });
+        result & mask
+    }
+
+    /// INC and DEC update the arithmetic flags like ADD and SUB by one,
+    /// except that CF is preserved.
+    fn update_eflags_inc_dec(&mut self, value: u32, is_dec: bool, size: u32) -> u32 {
+        let carry = self.is_carry();
+        if is_dec {
+            self.record_flags(FlagsOp::Dec, value, 1, value.wrapping_sub(1), carry, size)
+        } else {
+            self.record_flags(FlagsOp::Inc, value, 1, value.wrapping_add(1), carry, size)
+        }
+    }
+
+    fn update_eflags_add(&mut self, v1: u32, v2: u32, carry: u32, size: u32) -> u32 {
+        let result = v1.wrapping_add(v2).wrapping_add(carry);
+        self.record_flags(FlagsOp::Add, v1, v2, result, carry != 0, size)
+    }
+
+    fn update_eflags_sub(&mut self, v1: u32, v2: u32, borrow: u32, size: u32) -> u32 {
+        let result = v1.wrapping_sub(v2).wrapping_sub(borrow);
+        self.record_flags(FlagsOp::Sub, v1, v2, result, borrow != 0, size)
+    }
+
+    fn update_eflags_logic(&mut self, result: u32, size: u32) -> u32 {
+        self.record_flags(FlagsOp::Logic, 0, 0, result, false, size)
+    }
+
+    /// Performs one of the eight group 1 operations on `size`-bit operands,
+    /// updating CF, PF, AF, ZF, SF and OF, and returns the truncated result.
+    fn alu(&mut self, op: AluOp, v1: u32, v2: u32, size: u32) -> u32 {
+        match op {
+            AluOp::Add => self.update_eflags_add(v1, v2, 0, size),
+            AluOp::Or => self.update_eflags_logic(v1 | v2, size),
+            AluOp::Adc => self.update_eflags_add(v1, v2, self.is_carry() as u32, size),
+            AluOp::Sbb => self.update_eflags_sub(v1, v2, self.is_carry() as u32, size),
+            AluOp::And => self.update_eflags_logic(v1 & v2, size),
+            AluOp::Sub | AluOp::Cmp => self.update_eflags_sub(v1, v2, 0, size),
+            AluOp::Xor => self.update_eflags_logic(v1 ^ v2, size),
+        }
+    }
+
+    /// Performs one of the group 2 shifts or rotates (ROL, ROR, RCL, RCR,
+    /// SHL, SHR, SAL, SAR selected by `op`) on a `size`-bit operand. The count
+    /// is masked to five bits as on the i386, and a masked count of zero
+    /// leaves both the operand and EFLAGS untouched.
+    fn shift_rotate(&mut self, op: u8, value: u32, count: u8, size: u32) -> u32 {
+        let count = u32::from(count & 0x1f);
+        if count == 0 {
+            return value;
+        }
+        let mask = (1u64 << size) - 1;
+        let value = u64::from(value) & mask;
+        let msb = |v: u64| (v >> (size - 1)) & 1 != 0;
+
+        match op & 0x07 {
+            0 => {
+                let n = count % size;
+                let result = ((value << n) | (value >> ((size - n) % size))) & mask;
+                let carry = result & 1 != 0;
+                self.set_carry(carry);
+                self.set_overflow(msb(result) != carry);
+                result as u32
+            }
+            1 => {
+                let n = count % size;
+                let result = ((value >> n) | (value << ((size - n) % size))) & mask;
+                self.set_carry(msb(result));
+                self.set_overflow(msb(result) != msb(result << 1));
+                result as u32
+            }
+            2 => {
+                let mut result = value;
+                let mut carry = self.is_carry();
+                for _ in 0..count % (size + 1) {
+                    let out = msb(result);
+                    result = ((result << 1) | carry as u64) & mask;
+                    carry = out;
+                }
+                self.set_carry(carry);
+                self.set_overflow(msb(result) != carry);
+                result as u32
+            }
+            3 => {
+                let mut result = value;
+                let mut carry = self.is_carry();
+                self.set_overflow(msb(result) != carry);
+                for _ in 0..count % (size + 1) {
+                    let out = result & 1 != 0;
+                    result = (result >> 1) | ((carry as u64) << (size - 1));
+                    carry = out;
+                }
+                self.set_carry(carry);
+                result as u32
+            }
+            4 | 6 => {
+                let result = (value << count) & mask;
+                let carry = count <= size && (value >> (size - count)) & 1 != 0;
+                self.set_carry(carry);
+                self.set_overflow(msb(result) != carry);
+                self.set_aux_carry(false);
+                self.update_eflags_result(result as u32, size);
+                result as u32
+            }
+            5 => {
+                let result = value >> count;
+                self.set_carry(count <= size && (value >> (count - 1)) & 1 != 0);
+                self.set_overflow(msb(value));
+                self.set_aux_carry(false);
+                self.update_eflags_result(result as u32, size);
+                result as u32
+            }
+            _ => {
+                let signed = ((value << (64 - size)) as i64) >> (64 - size);
+                let shift = count.min(size);
+                let result = (signed >> shift) as u64 & mask;
+                self.set_carry((signed >> (shift - 1)) & 1 != 0);
+                self.set_overflow(false);
+                self.set_aux_carry(false);
+                self.update_eflags_result(result as u32, size);
+                result as u32
+            }
+        }
+    }
+
+    /// Performs BT, BTS, BTR or BTC (`op` 0-3, the low two bits of the /r
+    /// field of 0F BA) on a `size`-bit `value`, copying the selected bit into
+    /// CF. Returns the modified operand, or `None` for BT which does not
+    /// write back.
+    fn bit_test(&mut self, op: u8, value: u32, bit: u32, size: u32) -> Option<u32> {
+        let mask = 1 << (bit & (size - 1));
+        self.set_carry(value & mask != 0);
+        match op & 0x03 {
+            0 => None,
+            1 => Some(value | mask),
+            2 => Some(value & !mask),
+            _ => Some(value ^ mask),
+        }
+    }
+
+    /// Computes SHLD (`left`) or SHRD on `size`-bit operands, shifting bits
+    /// of `source` into `dest`. A masked count of zero changes nothing.
+    fn double_shift(&mut self, left: bool, dest: u32, source: u32, count: u8, size: u32) -> u32 {
+        let count = u32::from(count & 0x1f);
+        if count == 0 {
+            return dest;
+        }
+        let mask = (1u128 << size) - 1;
+        let (dest, source) = (u128::from(dest) & mask, u128::from(source) & mask);
+        let (result, carry) = if left {
+            let wide = (dest << size | source) << count;
+            ((wide >> size) & mask, (wide >> (2 * size)) & 1 != 0)
+        } else {
+            let wide = source << size | dest;
+            ((wide >> count) & mask, (wide >> (count - 1)) & 1 != 0)
+        };
+        self.set_carry(carry);
+        self.set_overflow(((dest ^ result) >> (size - 1)) & 1 != 0);
+        self.set_aux_carry(false);
+        self.update_eflags_result(result as u32, size);
+        result as u32
+    }
+
+    /// Loads the program image at 0x7C00. An image that does not fit in
+    /// memory above that address is rejected.
+    pub fn read_binary(&mut self, filename: &str) -> Result<(), String> {
+        let mut file =
+            File::open(filename).map_err(|_| format!("Failed to open file: {}", filename))?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)
+            .map_err(|_| format!("Failed to read file: {}", filename))?;
+        let available = self.memory.len() - 0x7c00;
+        if buffer.len() > available {
+            return Err(format!(
+                "{} is too large: {} bytes, but only {} fit above 0x7C00",
+                filename,
+                buffer.len(),
+                available
+            ));
+        }
+        self.memory[0x7c00..0x7c00 + buffer.len()].copy_from_slice(&buffer);
+        Ok(())
+    }
+
+    pub fn dump_registers(&self) {
+        let register_names = ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"];
+        for (i, &name) in register_names.iter().enumerate() {
+            println!("{} = {:08X}", name, self.registers[i]);
+        }
+        println!("EIP = {:08X}", self.eip);
+
+        let segment_names = ["ES", "CS", "SS", "DS", "FS", "GS"];
+        for (segment, &name) in self.segments.iter().zip(segment_names.iter()) {
+            println!(
+                "{} = {:04X} (base {:08X}, limit {:08X}, access {:02X}{})",
+                name,
+                segment.selector,
+                segment.base,
+                segment.limit,
+                segment.access,
+                if segment.big { ", 32-bit" } else { "" }
+            );
+        }
+
+        let eflags = self.get_eflags();
+        let flags: Vec<&str> = EFLAGS_NAMES
+            .iter()
+            .filter(|&&(mask, _)| eflags & mask != 0)
+            .map(|&(_, name)| name)
+            .collect();
+        println!(
+            "EFLAGS = {:08X} [{}] IOPL = {}",
+            eflags,
+            flags.join(" "),
+            (eflags & IOPL_MASK) >> 12
+        );
+        println!(
+            "CR0 = {:08X} CR2 = {:08X} CR3 = {:08X}",
+            self.cr0, self.cr2, self.cr3
+        );
+        println!("CYCLES = {}", self.cycles);
+        println!(
+            "GDTR = {:08X}:{:04X} IDTR = {:08X}:{:04X} LDTR = {:04X} TR = {:04X}",
+            self.gdtr.base,
+            self.gdtr.limit,
+            self.idtr.base,
+            self.idtr.limit,
+            self.ldtr.selector,
+            self.tr.selector
+        );
+    }
+
+    fn parse_modrm(&mut self) -> ModRM {
+        let code = self.get_code8(0);
+        let mod_val = (code & 0xC0) >> 6;
+        let opecode = (code & 0x38) >> 3;
+        let rm = code & 0x07;
+
+        self.advance_eip(1);
+        self.execution.group = opecode;
+        self.execution.memory_operand = mod_val != 3;
+
+        let mut modrm = ModRM {
+            mod_val,
+            opecode,
+            rm,
+            sib: None,
+            disp8: None,
+            disp16: None,
+            disp32: None,
+            address_size: self.address_size(),
+        };
+
+        if modrm.address_size == 16 {
+            match modrm.mod_val {
+                0 if modrm.rm == 6 => {
+                    modrm.disp16 = Some(self.get_code16(0) as i16);
+                    self.advance_eip(2);
+                }
+                1 => {
+                    modrm.disp8 = Some(self.get_sign_code8(0));
+                    self.advance_eip(1);
+                }
+                2 => {
+                    modrm.disp16 = Some(self.get_code16(0) as i16);
+                    self.advance_eip(2);
+                }
+                _ => {}
+            }
+            return modrm;
+        }
+
+        if modrm.mod_val != 3 && modrm.rm == 4 {
+            modrm.sib = Some(self.get_code8(0));
+            self.advance_eip(1);
+        }
+        let is_sib_without_base = modrm.sib.is_some_and(|sib| sib & 0x07 == 5);
+
+        match modrm.mod_val {
+            0 if modrm.rm == 5 || is_sib_without_base => {
+                modrm.disp32 = Some(self.get_sign_code32(0));
+                self.advance_eip(4);
+            }
+            1 => {
+                modrm.disp8 = Some(self.get_sign_code8(0));
+                self.advance_eip(1);
+            }
+            2 => {
+                modrm.disp32 = Some(self.get_sign_code32(0));
+                self.advance_eip(4);
+            }
+            _ => {}
+        }
+
+        modrm
+    }
+
+    /// Consumes the prefix bytes in front of the next opcode and looks the
+    /// opcode up in the one- or two-byte table. EIP is left on the opcode.
+    /// Fails with #DB on an instruction breakpoint, with #GP(0) or #PF if
+    /// the code cannot be fetched, or with #UD for a two-byte opcode the
+    /// model does not define. Defined opcodes that are not implemented give
+    /// `None`.
+    pub fn fetch_instruction(&mut self) -> Result<Option<Instruction>, Exception> {
+        self.instruction_start = self.eip;
+        self.prefixes = Prefixes::default();
+        self.debug_trap = 0;
+        self.fetch_fault.set(None);
+        self.check_instruction_breakpoints()?;
+        if self.cr0 & CR0_PG != 0 {
+            self.prefetch()?;
+        }
+        loop {
+            match self.get_code8(0) {
+                0xF2 => self.prefixes.repeat = Some(RepeatPrefix::Repne),
+                0xF3 => self.prefixes.repeat = Some(RepeatPrefix::Rep),
+                0x66 => self.prefixes.operand_size = true,
+                0x67 => self.prefixes.address_size = true,
+                0x26 => self.prefixes.segment = Some(SegmentRegister::Es),
+                0x2E => self.prefixes.segment = Some(SegmentRegister::Cs),
+                0x36 => self.prefixes.segment = Some(SegmentRegister::Ss),
+                0x3E => self.prefixes.segment = Some(SegmentRegister::Ds),
+                0x64 => self.prefixes.segment = Some(SegmentRegister::Fs),
+                0x65 => self.prefixes.segment = Some(SegmentRegister::Gs),
+                _ => break,
+            }
+            self.advance_eip(1);
+        }
+
+        let code = self.get_code8(0);
+        let (opcode, instruction) = if code == 0x0F {
+            let opcode = self.get_code8(1);
+            (opcode, self.two_byte_instructions.get(&opcode).cloned())
+        } else {
+            (code, self.instructions.get(&code).cloned())
+        };
+        self.execution = Execution {
+            opcode,
+            ..Execution::default()
+        };
+        if let Err(exception) = self.check_fetch_fault() {
+            self.eip = self.instruction_start;
+            return Err(exception);
+        }
+        if instruction.is_none() && code == 0x0F && !self.model.defines_two_byte_opcode(opcode) {
+            self.eip = self.instruction_start;
+            return Err(Exception::InvalidOpcode);
+        }
+        Ok(instruction)
+    }
+
+    /// Executes one decoded instruction. If it raises an exception, EIP is
+    /// rolled back to the start of the instruction (including its prefixes)
+    /// so the fault can be reported (or restarted) at the right place, and
+    /// the general and segment registers and EFLAGS are restored to their
+    /// values before the instruction. Faults raised by a task switch after
+    /// the old task has been saved are left in the new task.
+    ///
+    /// Once the instruction has completed, its clocks are added to the
+    /// cycle counter, RF is cleared and #DB is raised as a trap, with EIP
+    /// after the instruction, if TF was set when it started or if it hit a
+    /// data breakpoint.
+    pub fn execute_instruction(&mut self, instruction: &Instruction) -> Result<(), Exception> {
+        let eflags = self.eflags;
+        let cpl = self.cpl();
+        let result = self.roll_back_on_error(|emu| {
+            emu.dispatch_instruction(instruction)?;
+            emu.check_fetch_fault()
+        });
+        if result.is_err() && !self.task_switched {
+            self.eip = self.instruction_start;
+        }
+        result?;
+        self.count_clocks(instruction, cpl);
+        // IRET and task switches load RF for the next instruction.
+        if eflags & RESUME_FLAG != 0
+            && !self.task_switched
+            && !matches!(instruction, Instruction::Iret)
+        {
+            self.eflags &= !RESUME_FLAG;
+        }
+        let mut status = self.debug_trap;
+        if eflags & TRAP_FLAG != 0 {
+            status |= DR6_BS;
+        }
+        if status != 0 {
+            self.dr6 |= status;
+            return Err(Exception::Debug);
+        }
+        Ok(())
+    }
+
+    /// Adds the clocks of a completed instruction to the cycle counter.
+    /// `cpl` is the CPL it started at.
+    fn count_clocks(&mut self, instruction: &Instruction, cpl: u16) {
+        let mut execution = self.execution;
+        execution.protected_mode = self.is_protected_mode();
+        execution.operand_size = self.operand_size();
+        execution.last_iteration = self
+            .prefixes
+            .repeat
+            .map(|_| self.eip != self.instruction_start);
+        execution.level_change = self.cpl() != cpl;
+        execution.task_switched = self.task_switched;
+        self.cycles += u64::from(instruction.clocks(&execution));
+    }
+
+    /// The time since power-on in ticks of a device clock running at
+    /// `rate` Hz, such as the PIT input clock, derived from the cycle
+    /// counter rather than the host's wall clock.
+    pub fn device_ticks(&self, rate: u64) -> u64 {
+        timing::device_ticks(self.cycles, rate)
+    }
+
+    /// Runs `f`, restoring the general and segment registers and EFLAGS if
+    /// it fails before switching tasks.
+    fn roll_back_on_error(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), Exception>,
+    ) -> Result<(), Exception> {
+        let registers = self.registers;
+        let segments = self.segments;
+        let (eflags, lazy_flags) = (self.eflags, self.lazy_flags);
+        self.task_switched = false;
+        let result = f(self);
+        if result.is_err() && !self.task_switched {
+            self.registers = registers;
+            self.segments = segments;
+            self.eflags = eflags;
+            self.lazy_flags = lazy_flags;
+        }
+        result
+    }
+
+    /// Delivers an exception raised by an instruction that has been rolled
+    /// back to its start, or a debug trap after one that completed. A
+    /// contributory exception or page fault raised while delivering another
+    /// one becomes a double fault, other faults are delivered in turn, and
+    /// a fault while delivering #DF shuts the processor down. It is then
+    /// reset and `false` is returned. Entering the #DB handler clears GD,
+    /// and a task gate to a task with the T bit set raises #DB in the new
+    /// task before its first instruction.
+    pub fn raise_exception(&mut self, exception: Exception) -> bool {
+        let mut exception = exception;
+        // Breakpoints hit by a rolled-back instruction are not reported.
+        self.debug_trap = 0;
+        loop {
+            if exception == Exception::Debug {
+                // Lets the handler use the debug registers.
+                self.dr7 &= !DR7_GD;
+            }
+            let vector = exception.vector();
+            let error_code = exception.error_code();
+            let fault =
+                match self.roll_back_on_error(|emu| emu.interrupt(vector, error_code, false)) {
+                    Ok(()) if self.debug_trap & DR6_BT != 0 => {
+                        self.dr6 |= DR6_BT;
+                        self.debug_trap = 0;
+                        exception = Exception::Debug;
+                        continue;
+                    }
+                    Ok(()) => return true,
+                    Err(fault) => fault,
+                };
+            if exception == Exception::DoubleFault {
+                self.reset();
+                return false;
+            }
+            exception = if exception.is_double_fault(fault) {
+                Exception::DoubleFault
+            } else {
+                fault
+            };
+        }
+    }
+
+    /// Transfers control to the handler for `vector`, through the IVT in
+    /// real mode or the IDT in protected mode. `error_code` is pushed after
+    /// the return address in protected mode, or on the new task's stack
+    /// for a task gate. A handler in a more privileged
+    /// non-conforming segment runs on the stack for its DPL from the TSS,
+    /// with the interrupted SS:ESP pushed first. Virtual-8086 mode can only
+    /// be interrupted by a ring 0 handler, which also finds GS, FS, DS and
+    /// ES saved on its stack and those registers nulled. INT n is checked
+    /// against the gate DPL; for exceptions the EXT bit is set in the error
+    /// code of any fault raised on the way.
+    fn interrupt(
+        &mut self,
+        vector: u8,
+        error_code: Option<u16>,
+        is_software: bool,
+    ) -> Result<(), Exception> {
+        if !self.is_protected_mode() {
+            return self.interrupt_real_mode(vector);
+        }
+        let ext = if is_software { 0 } else { 1 };
+        let gate_error = (vector as u16) << 3 | 2 | ext;
+        let offset = vector as u32 * 8;
+        if offset + 7 > self.idtr.limit as u32 {
+            return Err(Exception::GeneralProtection(gate_error));
+        }
+        let address = self.idtr.base.wrapping_add(offset);
+        let low = self.read_linear(address, 32, false)? as u64;
+        let high = self.read_linear(address.wrapping_add(4), 32, false)? as u64;
+        let gate = Gate::from_u64(high << 32 | low);
+        let cpl = self.cpl();
+        match gate.system_type() {
+            SYSTEM_INTERRUPT_GATE_16
+            | SYSTEM_TRAP_GATE_16
+            | SYSTEM_INTERRUPT_GATE
+            | SYSTEM_TRAP_GATE
+            | SYSTEM_TASK_GATE => {}
+            _ => return Err(Exception::GeneralProtection(gate_error)),
+        }
+        if is_software && gate.dpl() < cpl {
+            return Err(Exception::GeneralProtection(gate_error));
+        }
+        if !gate.is_present() {
+            return Err(Exception::SegmentNotPresent(gate_error));
+        }
+        if gate.system_type() == SYSTEM_TASK_GATE {
+            let descriptor =
+                self.read_tss_descriptor(gate.selector, false, Exception::GeneralProtection)?;
+            self.task_switch(gate.selector, descriptor, TaskSwitch::Interrupt)?;
+            if let Some(code) = error_code {
+                let size = if self.is_tss_32bit() { 32 } else { 16 };
+                self.push_sized(code as u32, size)?;
+            }
+            return Ok(());
+        }
+
+        let selector = gate.selector;
+        if selector & !3 == 0 {
+            return Err(Exception::GeneralProtection(ext));
+        }
+        let selector_error = selector & 0xfffc | ext;
+        let mut descriptor = self.read_descriptor(selector)?;
+        if !descriptor.is_code() || descriptor.dpl() > cpl {
+            return Err(Exception::GeneralProtection(selector_error));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(selector_error));
+        }
+        let new_cpl = if descriptor.is_conforming() {
+            cpl
+        } else {
+            descriptor.dpl()
+        };
+        let is_virtual_8086 = self.is_virtual_8086_mode();
+        if is_virtual_8086 && new_cpl != 0 {
+            return Err(Exception::GeneralProtection(selector_error));
+        }
+        let size = if gate.is_32bit() { 32 } else { 16 };
+        let target = if size == 16 {
+            gate.offset & 0xffff
+        } else {
+            gate.offset
+        };
+        if target > descriptor.limit {
+            return Err(Exception::GeneralProtection(0));
+        }
+        self.set_descriptor_accessed(selector, &mut descriptor)?;
+
+        let eflags = self.get_eflags();
+        let cs = self.get_segment_register(SegmentRegister::Cs) as u32;
+        let ss = self.get_segment_register(SegmentRegister::Ss) as u32;
+        let esp = self.get_register32(Register::Esp as usize);
+        // The new CPL applies to the pushes, which go to a supervisor stack
+        // for a handler at an inner level.
+        self.segments[SegmentRegister::Cs as usize] =
+            descriptor.to_segment(selector & !3 | new_cpl);
+        let data_segments = [
+            SegmentRegister::Gs,
+            SegmentRegister::Fs,
+            SegmentRegister::Ds,
+            SegmentRegister::Es,
+        ];
+        if is_virtual_8086 {
+            self.eflags &= !VIRTUAL_8086_FLAG;
+        }
+        if new_cpl < cpl {
+            self.load_inner_stack(new_cpl, ext)?;
+            if is_virtual_8086 {
+                for reg in data_segments {
+                    self.push_sized(self.get_segment_register(reg) as u32, size)?;
+                    self.segments[reg as usize] = Segment::null(0);
+                }
+            }
+            self.push_sized(ss, size)?;
+            self.push_sized(esp, size)?;
+        }
+        self.push_sized(eflags, size)?;
+        self.push_sized(cs, size)?;
+        self.push_sized(self.eip, size)?;
+        if let Some(code) = error_code {
+            self.push_sized(code as u32, size)?;
+        }
+        self.eip = target;
+        let mut cleared = TRAP_FLAG | NESTED_TASK_FLAG | VIRTUAL_8086_FLAG | RESUME_FLAG;
+        if gate.is_interrupt_gate() {
+            cleared |= INTERRUPT_FLAG;
+        }
+        self.set_eflags(eflags & !cleared);
+        Ok(())
+    }
+
+    /// Pushes FLAGS, CS and IP and loads CS:IP from the 4-byte IVT entry.
+    /// Real mode pushes words whatever the operand size and has no error
+    /// codes.
+    fn interrupt_real_mode(&mut self, vector: u8) -> Result<(), Exception> {
+        let offset = vector as u32 * 4;
+        if offset + 3 > self.idtr.limit as u32 {
+            return Err(Exception::GeneralProtection(0));
+        }
+        let address = self.idtr.base.wrapping_add(offset);
+        let ip = self.read_linear(address, 16, false)?;
+        let cs = self.read_linear(address.wrapping_add(2), 16, false)? as u16;
+        let eflags = self.get_eflags();
+        self.push_sized(eflags & 0xffff, 16)?;
+        self.push_sized(self.get_segment_register(SegmentRegister::Cs) as u32, 16)?;
+        self.push_sized(self.eip & 0xffff, 16)?;
+        self.segments[SegmentRegister::Cs as usize].load_real_mode(cs);
+        self.eip = ip;
+        self.set_eflags(eflags & !(INTERRUPT_FLAG | TRAP_FLAG));
+        Ok(())
+    }
+
+    fn dispatch_instruction(&mut self, instruction: &Instruction) -> Result<(), Exception> {
+        match instruction {
+            Instruction::MovR8Imm8 => {
+                self.mov_r8_imm8();
+            }
+            Instruction::MovR32Imm32 => {
+                self.mov_r32_imm32();
+            }
+            Instruction::MovR8Rm8 => {
+                self.mov_r8_rm8()?;
+            }
+            Instruction::MovR32Rm32 => {
+                self.mov_r32_rm32()?;
+            }
+            Instruction::AluRm8R8 => {
+                self.alu_rm8_r8()?;
+            }
+            Instruction::AluRm32R32 => {
+                self.alu_rm32_r32()?;
+            }
+            Instruction::AluR8Rm8 => {
+                self.alu_r8_rm8()?;
+            }
+            Instruction::AluR32Rm32 => {
+                self.alu_r32_rm32()?;
+            }
+            Instruction::AluAlImm8 => {
+                self.alu_al_imm8();
+            }
+            Instruction::AluEaxImm32 => {
+                self.alu_eax_imm32();
+            }
+            Instruction::MovRm8R8 => {
+                self.mov_rm8_r8()?;
+            }
+            Instruction::MovRm32R32 => {
+                self.mov_rm32_r32()?;
+            }
+            Instruction::IncR32 => {
+                self.inc_r32();
+            }
+            Instruction::PushR32 => {
+                self.push_r32()?;
+            }
+            Instruction::PopR32 => {
+                self.pop_r32()?;
+            }
+            Instruction::PushImm32 => {
+                self.push_imm32()?;
+            }
+            Instruction::PushImm8 => {
+                self.push_imm8()?;
+            }
+            Instruction::Code80 => {
+                self.code_80()?;
+            }
+            Instruction::Code81 => {
+                self.code_81()?;
+            }
+            Instruction::Code83 => {
+                self.code_83()?;
+            }
+            Instruction::CodeC0 => {
+                self.code_c0()?;
+            }
+            Instruction::CodeC1 => {
+                self.code_c1()?;
+            }
+            Instruction::CodeD0 => {
+                self.code_d0()?;
+            }
+            Instruction::CodeD1 => {
+                self.code_d1()?;
+            }
+            Instruction::CodeD2 => {
+                self.code_d2()?;
+            }
+            Instruction::CodeD3 => {
+                self.code_d3()?;
+            }
+            Instruction::CodeF6 => {
+                self.code_f6()?;
+            }
+            Instruction::CodeF7 => {
+                self.code_f7()?;
+            }
+            Instruction::MovRm32Imm32 => {
+                self.mov_rm32_imm32()?;
+            }
+            Instruction::InAlDx => {
+                self.in_al_dx()?;
+            }
+            Instruction::OutDxAl => {
+                self.out_dx_al()?;
+            }
+            Instruction::CodeFf => {
+                self.code_ff()?;
+            }
+            Instruction::CallRel32 => {
+                self.call_rel32()?;
+            }
+            Instruction::Ret => {
+                self.ret()?;
+            }
+            Instruction::RetImm16 => {
+                self.ret_imm16()?;
+            }
+            Instruction::Retf => {
+                self.retf()?;
+            }
+            Instruction::RetfImm16 => {
+                self.retf_imm16()?;
+            }
+            Instruction::CallFarPtr => {
+                self.call_far_ptr()?;
+            }
+            Instruction::JmpFarPtr => {
+                self.jmp_far_ptr()?;
+            }
+            Instruction::Iret => {
+                self.iret()?;
+            }
+            Instruction::Leave => {
+                self.leave()?;
+            }
+            Instruction::Enter => {
+                self.enter()?;
+            }
+            Instruction::Pusha => {
+                self.pusha()?;
+            }
+            Instruction::Popa => {
+                self.popa()?;
+            }
+            Instruction::Pushf => {
+                self.pushf()?;
+            }
+            Instruction::Popf => {
+                self.popf()?;
+            }
+            Instruction::PushSreg => {
+                self.push_sreg()?;
+            }
+            Instruction::PopSreg => {
+                self.pop_sreg()?;
+            }
+            Instruction::MovRm16Sreg => {
+                self.mov_rm16_sreg()?;
+            }
+            Instruction::MovSregRm16 => {
+                self.mov_sreg_rm16()?;
+            }
+            Instruction::ShortJump => {
+                self.short_jump()?;
+            }
+            Instruction::NearJump => {
+                self.near_jump()?;
+            }
+            Instruction::ShortJcc => {
+                self.short_jcc()?;
+            }
+            Instruction::NearJcc => {
+                self.near_jcc()?;
+            }
+            Instruction::Setcc => {
+                self.setcc()?;
+            }
+            Instruction::MovzxR32Rm8 => {
+                self.movzx_r32_rm8()?;
+            }
+            Instruction::MovzxR32Rm16 => {
+                self.movzx_r32_rm16()?;
+            }
+            Instruction::MovsxR32Rm8 => {
+                self.movsx_r32_rm8()?;
+            }
+            Instruction::MovsxR32Rm16 => {
+                self.movsx_r32_rm16()?;
+            }
+            Instruction::ImulR32Rm32 => {
+                self.imul_r32_rm32()?;
+            }
+            Instruction::BitTestRm32R32 => {
+                self.bit_test_rm32_r32()?;
+            }
+            Instruction::Code0fBa => {
+                self.code_0f_ba()?;
+            }
+            Instruction::BsfR32Rm32 => {
+                self.bsf_r32_rm32()?;
+            }
+            Instruction::BsrR32Rm32 => {
+                self.bsr_r32_rm32()?;
+            }
+            Instruction::ShldRm32R32Imm8 => {
+                self.shld_rm32_r32_imm8()?;
+            }
+            Instruction::ShldRm32R32Cl => {
+                self.shld_rm32_r32_cl()?;
+            }
+            Instruction::ShrdRm32R32Imm8 => {
+                self.shrd_rm32_r32_imm8()?;
+            }
+            Instruction::ShrdRm32R32Cl => {
+                self.shrd_rm32_r32_cl()?;
+            }
+            Instruction::Daa => {
+                self.daa();
+            }
+            Instruction::Das => {
+                self.das();
+            }
+            Instruction::Aaa => {
+                self.aaa();
+            }
+            Instruction::Aas => {
+                self.aas();
+            }
+            Instruction::Aam => {
+                self.aam()?;
+            }
+            Instruction::Aad => {
+                self.aad();
+            }
+            Instruction::Movs => {
+                self.movs()?;
+            }
+            Instruction::Cmps => {
+                self.cmps()?;
+            }
+            Instruction::Stos => {
+                self.stos()?;
+            }
+            Instruction::Lods => {
+                self.lods()?;
+            }
+            Instruction::Scas => {
+                self.scas()?;
+            }
+            Instruction::Cld => {
+                self.cld();
+            }
+            Instruction::Std => {
+                self.std();
+            }
+            Instruction::Clc => {
+                self.clc();
+            }
+            Instruction::Stc => {
+                self.stc();
+            }
+            Instruction::Cmc => {
+                self.cmc();
+            }
+            Instruction::Cli => {
+                self.cli()?;
+            }
+            Instruction::Sti => {
+                self.sti()?;
+            }
+            Instruction::Lahf => {
+                self.lahf();
+            }
+            Instruction::Sahf => {
+                self.sahf();
+            }
+            Instruction::Int3 => {
+                self.int3()?;
+            }
+            Instruction::Into => {
+                self.interrupt_on_overflow()?;
+            }
+            Instruction::Icebp => {
+                self.icebp()?;
+            }
+            Instruction::Swi => {
+                self.swi()?;
+            }
+            Instruction::Loop => {
+                self.loop_rel8()?;
+            }
+            Instruction::Jecxz => {
+                self.jecxz()?;
+            }
+            Instruction::Hlt => {
+                self.hlt()?;
+            }
+            Instruction::Nop => {
+                self.nop();
+            }
+            Instruction::XchgEaxR32 => {
+                self.xchg_eax_r32();
+            }
+            Instruction::XchgRm8R8 => {
+                self.xchg_rm8_r8()?;
+            }
+            Instruction::XchgRm32R32 => {
+                self.xchg_rm32_r32()?;
+            }
+            Instruction::LeaR32M => {
+                self.lea_r32_m()?;
+            }
+            Instruction::Cbw => {
+                self.cbw();
+            }
+            Instruction::Cwd => {
+                self.cwd();
+            }
+            Instruction::Xlat => {
+                self.xlat()?;
+            }
+            Instruction::Fwait => {
+                self.fwait()?;
+            }
+            Instruction::FpuEscape => {
+                self.fpu_escape()?;
+            }
+            Instruction::Clts => {
+                self.clts()?;
+            }
+            Instruction::MovR32Cr => {
+                self.mov_r32_cr()?;
+            }
+            Instruction::MovCrR32 => {
+                self.mov_cr_r32()?;
+            }
+            Instruction::MovR32Dr => {
+                self.mov_r32_dr()?;
+            }
+            Instruction::MovDrR32 => {
+                self.mov_dr_r32()?;
+            }
+            Instruction::Bswap => {
+                self.bswap()?;
+            }
+            Instruction::Xadd => {
+                self.xadd()?;
+            }
+            Instruction::Cmpxchg => {
+                self.cmpxchg()?;
+            }
+            Instruction::Wbinvd => {
+                self.wbinvd()?;
+            }
+            Instruction::Cpuid => {
+                self.cpuid()?;
+            }
+            Instruction::Rdtsc => {
+                self.rdtsc()?;
+            }
+            Instruction::Rdmsr => {
+                self.rdmsr()?;
+            }
+            Instruction::Wrmsr => {
+                self.wrmsr()?;
+            }
+            Instruction::Code0fC7 => {
+                self.code_0f_c7()?;
+            }
+            Instruction::Code0f00 => {
+                self.code_0f_00()?;
+            }
+            Instruction::Code0f01 => {
+                self.code_0f_01()?;
+            }
+            Instruction::LarR32Rm16 => {
+                self.lar_r32_rm16()?;
+            }
+            Instruction::LslR32Rm16 => {
+                self.lsl_r32_rm16()?;
+            }
+            Instruction::ArplRm16R16 => {
+                self.arpl_rm16_r16()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mov_r8_imm8(&mut self) {
+        let reg = Register8::from_u8(self.get_code8(0));
+        let value = self.get_code8(1);
+        self.set_register8(reg, value);
+        self.advance_eip(2);
+    }
+
+    fn mov_r32_imm32(&mut self) {
+        let size = self.operand_size();
+        let reg = (self.get_code8(0) - 0xB8) as usize;
+        let value = self.get_code_sized(1, size);
+        self.set_register_sized(reg, value, size);
+        self.advance_eip(1 + size / 8);
+    }
+
+    fn mov_r8_rm8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let rm8 = modrm.get_rm8(self)?;
+        modrm.set_r8(self, rm8);
+        Ok(())
+    }
+
+    fn mov_r32_rm32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let rm = modrm.get_rm_sized(self, size)?;
+        modrm.set_r_sized(self, rm, size);
+        Ok(())
+    }
+
+    fn alu_rm8_r8(&mut self) -> Result<(), Exception> {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let r8 = modrm.get_r8(self);
+        let rm8 = modrm.get_rm8(self)?;
+        let result = self.alu(op, rm8 as u32, r8 as u32, 8);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_rm8(self, result as u8)?;
+        }
+        Ok(())
+    }
+
+    fn alu_rm32_r32(&mut self) -> Result<(), Exception> {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let r = modrm.get_r_sized(self, size);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let result = self.alu(op, rm, r, size);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_rm_sized(self, result, size)?;
+        }
+        Ok(())
+    }
+
+    fn alu_r8_rm8(&mut self) -> Result<(), Exception> {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let r8 = modrm.get_r8(self);
+        let rm8 = modrm.get_rm8(self)?;
+        let result = self.alu(op, r8 as u32, rm8 as u32, 8);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_r8(self, result as u8);
+        }
+        Ok(())
+    }
+
+    fn alu_r32_rm32(&mut self) -> Result<(), Exception> {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let r = modrm.get_r_sized(self, size);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let result = self.alu(op, r, rm, size);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_r_sized(self, result, size);
+        }
+        Ok(())
+    }
+
+    fn alu_al_imm8(&mut self) {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
         let value = self.get_code8(1);
         let al = self.get_register8(Register8::Al);
-        let result = (al as u64).wrapping_sub(value as u64);
-        self.update_eflags_sub(al as u32, value as u32, result);
-        self.eip += 2;
+        let result = self.alu(op, al as u32, value as u32, 8);
+        if !matches!(op, AluOp::Cmp) {
+            self.set_register8(Register8::Al, result as u8);
+        }
+        self.advance_eip(2);
+    }
+
+    fn alu_eax_imm32(&mut self) {
+        let op = AluOp::from_u8(self.get_code8(0) >> 3);
+        let size = self.operand_size();
+        let value = self.get_code_sized(1, size);
+        let accumulator = self.get_accumulator(size);
+        let result = self.alu(op, accumulator, value, size);
+        if !matches!(op, AluOp::Cmp) {
+            self.set_accumulator(result, size);
+        }
+        self.advance_eip(1 + size / 8);
+    }
+
+    fn mov_rm8_r8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let r8 = modrm.get_r8(self);
+        modrm.set_rm8(self, r8)
+    }
+
+    fn mov_rm32_r32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let r = modrm.get_r_sized(self, size);
+        modrm.set_rm_sized(self, r, size)
+    }
+
+    fn inc_r32(&mut self) {
+        let size = self.operand_size();
+        let reg = (self.get_code8(0) - 0x40) as usize;
+        let value = self.get_register_sized(reg, size);
+        let result = self.update_eflags_inc_dec(value, false, size);
+        self.set_register_sized(reg, result, size);
+        self.advance_eip(1);
+    }
+
+    fn push_r32(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let reg = (self.get_code8(0) - 0x50) as usize;
+        let value = self.get_register_sized(reg, size);
+        self.push_sized(value, size)?;
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn pop_r32(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let reg = (self.get_code8(0) - 0x58) as usize;
+        let value = self.pop_sized(size)?;
+        self.set_register_sized(reg, value, size);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn push_imm32(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let value = self.get_code_sized(1, size);
+        self.push_sized(value, size)?;
+        self.advance_eip(1 + size / 8);
+        Ok(())
+    }
+
+    fn push_imm8(&mut self) -> Result<(), Exception> {
+        let value = self.get_sign_code8(1) as i32 as u32;
+        self.push_sized(value, self.operand_size())?;
+        self.advance_eip(2);
+        Ok(())
+    }
+
+    fn code_80(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let op = AluOp::from_u8(modrm.opecode);
+        let rm8 = modrm.get_rm8(self)?;
+        let imm8 = self.get_code8(0);
+        self.advance_eip(1);
+        let result = self.alu(op, rm8 as u32, imm8 as u32, 8);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_rm8(self, result as u8)?;
+        }
+        Ok(())
+    }
+
+    fn code_81(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let op = AluOp::from_u8(modrm.opecode);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let imm = self.get_code_sized(0, size);
+        self.advance_eip(size / 8);
+        let result = self.alu(op, rm, imm, size);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_rm_sized(self, result, size)?;
+        }
+        Ok(())
+    }
+
+    fn code_83(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let op = AluOp::from_u8(modrm.opecode);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let imm8 = self.get_sign_code8(0) as i32 as u32;
+        self.advance_eip(1);
+        let result = self.alu(op, rm, imm8, size);
+        if !matches!(op, AluOp::Cmp) {
+            modrm.set_rm_sized(self, result, size)?;
+        }
+        Ok(())
+    }
+
+    fn shift_rm8(&mut self, modrm: &ModRM, count: u8) -> Result<(), Exception> {
+        let rm8 = modrm.get_rm8(self)?;
+        let result = self.shift_rotate(modrm.opecode, rm8 as u32, count, 8);
+        modrm.set_rm8(self, result as u8)
+    }
+
+    fn shift_rm32(&mut self, modrm: &ModRM, count: u8) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let rm = modrm.get_rm_sized(self, size)?;
+        let result = self.shift_rotate(modrm.opecode, rm, count, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn code_c0(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let imm8 = self.get_code8(0);
+        self.advance_eip(1);
+        self.shift_rm8(&modrm, imm8)
+    }
+
+    fn code_c1(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let imm8 = self.get_code8(0);
+        self.advance_eip(1);
+        self.shift_rm32(&modrm, imm8)
+    }
+
+    fn code_d0(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        self.shift_rm8(&modrm, 1)
+    }
+
+    fn code_d1(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        self.shift_rm32(&modrm, 1)
+    }
+
+    fn code_d2(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let cl = self.get_register8(Register8::Cl);
+        self.shift_rm8(&modrm, cl)
+    }
+
+    fn code_d3(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let cl = self.get_register8(Register8::Cl);
+        self.shift_rm32(&modrm, cl)
+    }
+
+    fn mul_rm8(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let al = self.get_register8(Register8::Al) as u16;
+        let result = al * modrm.get_rm8(self)? as u16;
+        self.set_register8(Register8::Al, result as u8);
+        self.set_register8(Register8::Ah, (result >> 8) as u8);
+        self.set_carry(result > 0xff);
+        self.set_overflow(result > 0xff);
+        Ok(())
+    }
+
+    fn imul_rm8(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let al = self.get_register8(Register8::Al) as i8 as i16;
+        let result = al * modrm.get_rm8(self)? as i8 as i16;
+        self.set_register8(Register8::Al, result as u8);
+        self.set_register8(Register8::Ah, (result >> 8) as u8);
+        self.set_carry(result != result as i8 as i16);
+        self.set_overflow(result != result as i8 as i16);
+        Ok(())
+    }
+
+    fn div_rm8(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let divisor = modrm.get_rm8(self)? as u16;
+        if divisor == 0 {
+            return Err(Exception::DivideError);
+        }
+        let dividend = self.get_register32(Register::Eax as usize) as u16;
+        let quotient = dividend / divisor;
+        if quotient > 0xff {
+            return Err(Exception::DivideError);
+        }
+        self.set_register8(Register8::Al, quotient as u8);
+        self.set_register8(Register8::Ah, (dividend % divisor) as u8);
+        Ok(())
+    }
+
+    fn idiv_rm8(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let divisor = modrm.get_rm8(self)? as i8 as i16;
+        if divisor == 0 {
+            return Err(Exception::DivideError);
+        }
+        let dividend = self.get_register32(Register::Eax as usize) as u16 as i16;
+        let quotient = dividend.wrapping_div(divisor);
+        if quotient != quotient as i8 as i16 || (dividend == i16::MIN && divisor == -1) {
+            return Err(Exception::DivideError);
+        }
+        self.set_register8(Register8::Al, quotient as u8);
+        self.set_register8(Register8::Ah, dividend.wrapping_rem(divisor) as u8);
+        Ok(())
+    }
+
+    fn code_f6(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        match modrm.opecode {
+            0 | 1 => {
+                let rm8 = modrm.get_rm8(self)?;
+                let imm8 = self.get_code8(0);
+                self.advance_eip(1);
+                self.update_eflags_logic((rm8 & imm8) as u32, 8);
+            }
+            2 => {
+                let rm8 = modrm.get_rm8(self)?;
+                modrm.set_rm8(self, !rm8)?;
+            }
+            3 => {
+                let rm8 = modrm.get_rm8(self)?;
+                let result = self.update_eflags_sub(0, rm8 as u32, 0, 8);
+                modrm.set_rm8(self, result as u8)?;
+            }
+            4 => self.mul_rm8(&modrm)?,
+            5 => self.imul_rm8(&modrm)?,
+            6 => self.div_rm8(&modrm)?,
+            _ => self.idiv_rm8(&modrm)?,
+        }
+        Ok(())
+    }
+
+    fn mul_rm(&mut self, modrm: &ModRM, size: u32) -> Result<(), Exception> {
+        let mask = (1u64 << size) - 1;
+        let accumulator = self.get_accumulator(size) as u64;
+        let result = accumulator * modrm.get_rm_sized(self, size)? as u64;
+        self.set_accumulator(result as u32, size);
+        self.set_register_sized(Register::Edx as usize, (result >> size) as u32, size);
+        self.set_carry(result > mask);
+        self.set_overflow(result > mask);
+        Ok(())
+    }
+
+    fn imul_rm(&mut self, modrm: &ModRM, size: u32) -> Result<(), Exception> {
+        let accumulator = sign_extend(self.get_accumulator(size), size);
+        let result = accumulator * sign_extend(modrm.get_rm_sized(self, size)?, size);
+        self.set_accumulator(result as u32, size);
+        self.set_register_sized(Register::Edx as usize, (result >> size) as u32, size);
+        let is_overflow = result != sign_extend(result as u32, size);
+        self.set_carry(is_overflow);
+        self.set_overflow(is_overflow);
+        Ok(())
+    }
+
+    fn div_rm(&mut self, modrm: &ModRM, size: u32) -> Result<(), Exception> {
+        let divisor = modrm.get_rm_sized(self, size)? as u64;
+        if divisor == 0 {
+            return Err(Exception::DivideError);
+        }
+        let high = self.get_register_sized(Register::Edx as usize, size) as u64;
+        let dividend = high << size | self.get_accumulator(size) as u64;
+        let quotient = dividend / divisor;
+        if quotient >> size != 0 {
+            return Err(Exception::DivideError);
+        }
+        self.set_accumulator(quotient as u32, size);
+        let remainder = (dividend % divisor) as u32;
+        self.set_register_sized(Register::Edx as usize, remainder, size);
+        Ok(())
+    }
+
+    fn idiv_rm(&mut self, modrm: &ModRM, size: u32) -> Result<(), Exception> {
+        let divisor = sign_extend(modrm.get_rm_sized(self, size)?, size);
+        if divisor == 0 {
+            return Err(Exception::DivideError);
+        }
+        let high = self.get_register_sized(Register::Edx as usize, size) as u64;
+        let dividend = high << size | self.get_accumulator(size) as u64;
+        let dividend = ((dividend << (64 - 2 * size)) as i64) >> (64 - 2 * size);
+        let quotient = dividend.wrapping_div(divisor);
+        if quotient != sign_extend(quotient as u32, size) {
+            return Err(Exception::DivideError);
+        }
+        self.set_accumulator(quotient as u32, size);
+        let remainder = dividend.wrapping_rem(divisor) as u32;
+        self.set_register_sized(Register::Edx as usize, remainder, size);
+        Ok(())
+    }
+
+    fn code_f7(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        match modrm.opecode {
+            0 | 1 => {
+                let rm = modrm.get_rm_sized(self, size)?;
+                let imm = self.get_code_sized(0, size);
+                self.advance_eip(size / 8);
+                self.update_eflags_logic(rm & imm, size);
+            }
+            2 => {
+                let rm = modrm.get_rm_sized(self, size)?;
+                modrm.set_rm_sized(self, !rm, size)?;
+            }
+            3 => {
+                let rm = modrm.get_rm_sized(self, size)?;
+                let result = self.update_eflags_sub(0, rm, 0, size);
+                modrm.set_rm_sized(self, result, size)?;
+            }
+            4 => self.mul_rm(&modrm, size)?,
+            5 => self.imul_rm(&modrm, size)?,
+            6 => self.div_rm(&modrm, size)?,
+            _ => self.idiv_rm(&modrm, size)?,
+        }
+        Ok(())
+    }
+
+    fn mov_rm32_imm32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let value = self.get_code_sized(0, size);
+        self.advance_eip(size / 8);
+        modrm.set_rm_sized(self, value, size)
+    }
+
+    /// Checks that the program may access `length` ports from `port`. When
+    /// CPL > IOPL, and always in virtual-8086 mode, every one of them needs
+    /// a clear bit in the I/O permission bitmap of a 32-bit TSS, found at
+    /// the offset stored at 66h; ports whose bits lie beyond the TSS limit
+    /// are denied.
+    fn check_io_permission(&mut self, port: u16, length: u32) -> Result<(), Exception> {
+        if self.is_io_privileged() && !self.is_virtual_8086_mode() {
+            return Ok(());
+        }
+        let fault = Exception::GeneralProtection(0);
+        if !self.tr.is_usable() || !self.is_tss_32bit() || self.tr.limit < 0x67 {
+            return Err(fault);
+        }
+        let bitmap = self.read_linear(self.tr.base.wrapping_add(0x66), 16, false)?;
+        // Two bytes are read so that ports straddling a byte are covered.
+        let offset = bitmap + port as u32 / 8;
+        if offset + 1 > self.tr.limit {
+            return Err(fault);
+        }
+        let bits = self.read_linear(self.tr.base.wrapping_add(offset), 16, false)?;
+        let mask = ((1 << length) - 1) << (port & 7);
+        if bits & mask != 0 {
+            return Err(fault);
+        }
+        Ok(())
+    }
+
+    fn in_al_dx(&mut self) -> Result<(), Exception> {
+        let address = self.get_register32(Register::Edx as usize) as u16;
+        self.check_io_permission(address, 1)?;
+        let value = io_in8(address);
+        self.set_register8(Register8::Al, value);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn out_dx_al(&mut self) -> Result<(), Exception> {
+        let address = self.get_register32(Register::Edx as usize) as u16;
+        self.check_io_permission(address, 1)?;
+        let value = self.get_register8(Register8::Al);
+        io_out8(address, value);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn inc_rm32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let value = modrm.get_rm_sized(self, size)?;
+        let result = self.update_eflags_inc_dec(value, false, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn dec_rm32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let value = modrm.get_rm_sized(self, size)?;
+        let result = self.update_eflags_inc_dec(value, true, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn call_rm32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let target = modrm.get_rm_sized(self, size)?;
+        self.push_sized(self.eip, size)?;
+        self.jump_near(target)
+    }
+
+    /// Reads an m16:16 or m16:32 far pointer (offset first, then selector).
+    fn get_far_pointer(&mut self, modrm: &ModRM, size: u32) -> Result<(u16, u32), Exception> {
+        if modrm.mod_val == 3 {
+            return Err(Exception::InvalidOpcode);
+        }
+        let segment = modrm.segment(self);
+        let address = modrm.calc_effective_address(self);
+        let offset = self.read_memory(segment, address, size)?;
+        let selector = self.read_memory(segment, address.wrapping_add(size / 8), 16)? as u16;
+        Ok((selector, offset))
+    }
+
+    fn call_far_m32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let (selector, offset) = self.get_far_pointer(modrm, size)?;
+        self.far_transfer(selector, offset, Some(size))
+    }
+
+    fn jmp_rm32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let target = modrm.get_rm_sized(self, self.operand_size())?;
+        self.jump_near(target)
+    }
+
+    fn jmp_far_m32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let (selector, offset) = self.get_far_pointer(modrm, self.operand_size())?;
+        self.far_transfer(selector, offset, None)
+    }
+
+    fn push_rm32(&mut self, modrm: &ModRM) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let value = modrm.get_rm_sized(self, size)?;
+        self.push_sized(value, size)
+    }
+
+    fn code_ff(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        match modrm.opecode {
+            0 => self.inc_rm32(&modrm)?,
+            1 => self.dec_rm32(&modrm)?,
+            2 => self.call_rm32(&modrm)?,
+            3 => self.call_far_m32(&modrm)?,
+            4 => self.jmp_rm32(&modrm)?,
+            5 => self.jmp_far_m32(&modrm)?,
+            6 => self.push_rm32(&modrm)?,
+            _ => return Err(Exception::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    fn call_rel32(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let length = 1 + size / 8;
+        let diff = sign_extend(self.get_code_sized(1, size), size) as u32;
+        let return_address = self.eip.wrapping_add(length);
+        self.push_sized(return_address, size)?;
+        self.jump_near(return_address.wrapping_add(diff))
+    }
+
+    fn ret(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let target = self.pop_sized(size)?;
+        self.jump_near(target)
+    }
+
+    fn ret_imm16(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let release = self.get_code16(1) as u32;
+        let target = self.pop_sized(size)?;
+        self.jump_near(target)?;
+        self.set_stack_pointer(self.get_stack_pointer().wrapping_add(release));
+        Ok(())
+    }
+
+    /// A far JMP or, with the operand size in `call_size`, a far CALL, whose
+    /// return address is pushed (EIP already points past the instruction).
+    /// In protected mode the selector names either a code segment, which
+    /// is entered at the current privilege level, a call gate, or a TSS or
+    /// task gate to switch tasks, ignoring the offset.
+    fn far_transfer(
+        &mut self,
+        selector: u16,
+        offset: u32,
+        call_size: Option<u32>,
+    ) -> Result<(), Exception> {
+        if self.is_real_mode_addressing() {
+            let mut segment = *self.segment(SegmentRegister::Cs);
+            segment.load_real_mode(selector);
+            return self.enter_code_segment(segment, offset, call_size);
+        }
+        if selector & !3 == 0 {
+            return Err(Exception::GeneralProtection(0));
+        }
+        let error_code = selector & 0xfffc;
+        let raw = self.read_raw_descriptor(selector)?;
+        let mut descriptor = Descriptor::from_u64(raw);
+        let cpl = self.cpl();
+        let kind = match call_size {
+            Some(_) => TaskSwitch::Call,
+            None => TaskSwitch::Jump,
+        };
+        if !descriptor.is_segment() {
+            return match descriptor.system_type() {
+                SYSTEM_CALL_GATE_16 | SYSTEM_CALL_GATE => {
+                    self.call_gate(selector, Gate::from_u64(raw), call_size)
+                }
+                SYSTEM_TSS_16 | SYSTEM_TSS => {
+                    if descriptor.dpl() < cpl.max(selector & 3) {
+                        return Err(Exception::GeneralProtection(error_code));
+                    }
+                    if !descriptor.is_present() {
+                        return Err(Exception::SegmentNotPresent(error_code));
+                    }
+                    self.task_switch(selector, descriptor, kind)
+                }
+                SYSTEM_TASK_GATE => {
+                    let gate = Gate::from_u64(raw);
+                    if gate.dpl() < cpl.max(selector & 3) {
+                        return Err(Exception::GeneralProtection(error_code));
+                    }
+                    if !gate.is_present() {
+                        return Err(Exception::SegmentNotPresent(error_code));
+                    }
+                    let descriptor = self.read_tss_descriptor(
+                        gate.selector,
+                        false,
+                        Exception::GeneralProtection,
+                    )?;
+                    self.task_switch(gate.selector, descriptor, kind)
+                }
+                _ => Err(Exception::GeneralProtection(error_code)),
+            };
+        }
+        let is_allowed = if descriptor.is_conforming() {
+            descriptor.dpl() <= cpl
+        } else {
+            descriptor.is_code() && selector & 3 <= cpl && descriptor.dpl() == cpl
+        };
+        if !is_allowed {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        self.set_descriptor_accessed(selector, &mut descriptor)?;
+        let segment = descriptor.to_segment(selector & !3 | cpl);
+        self.enter_code_segment(segment, offset, call_size)
+    }
+
+    /// A far JMP or CALL through a call gate to the code segment and offset
+    /// it holds. A CALL to a more privileged non-conforming segment
+    /// switches to the stack for the new CPL from the TSS, pushes the old
+    /// SS:ESP and copies the gate's count of parameters from the old stack;
+    /// a JMP can only reach the current privilege level. The gate size
+    /// decides whether words or dwords are pushed.
+    fn call_gate(
+        &mut self,
+        selector: u16,
+        gate: Gate,
+        call_size: Option<u32>,
+    ) -> Result<(), Exception> {
+        let cpl = self.cpl();
+        let error_code = selector & 0xfffc;
+        if gate.dpl() < cpl.max(selector & 3) {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !gate.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        let target = gate.selector;
+        if target & !3 == 0 {
+            return Err(Exception::GeneralProtection(0));
+        }
+        let target_error = target & 0xfffc;
+        let mut descriptor = self.read_descriptor(target)?;
+        let new_cpl = if descriptor.is_conforming() {
+            cpl
+        } else {
+            descriptor.dpl()
+        };
+        if !descriptor.is_code() || new_cpl > cpl || (call_size.is_none() && new_cpl != cpl) {
+            return Err(Exception::GeneralProtection(target_error));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(target_error));
+        }
+        let size = if gate.is_32bit() { 32 } else { 16 };
+        let offset = if size == 16 {
+            gate.offset & 0xffff
+        } else {
+            gate.offset
+        };
+        self.set_descriptor_accessed(target, &mut descriptor)?;
+        let segment = descriptor.to_segment(target & !3 | new_cpl);
+        if new_cpl == cpl {
+            return self.enter_code_segment(segment, offset, call_size.map(|_| size));
+        }
+        if !segment.contains(offset, 1) {
+            return Err(Exception::GeneralProtection(0));
+        }
+
+        let mask = ((1u64 << self.stack_size()) - 1) as u32;
+        let esp = self.get_register32(Register::Esp as usize);
+        let mut parameters = Vec::new();
+        for i in 0..gate.parameter_count as u32 {
+            let offset = esp.wrapping_add(i * size / 8) & mask;
+            parameters.push(self.read_memory(SegmentRegister::Ss, offset, size)?);
+        }
+        let ss = self.get_segment_register(SegmentRegister::Ss) as u32;
+        let cs = self.get_segment_register(SegmentRegister::Cs) as u32;
+        self.segments[SegmentRegister::Cs as usize] = segment;
+        self.load_inner_stack(new_cpl, 0)?;
+        self.push_sized(ss, size)?;
+        self.push_sized(esp, size)?;
+        for &value in parameters.iter().rev() {
+            self.push_sized(value, size)?;
+        }
+        self.push_sized(cs, size)?;
+        self.push_sized(self.eip, size)?;
+        self.eip = offset;
+        Ok(())
+    }
+
+    /// Loads CS:EIP, first pushing the return address if `call_size` is
+    /// given. The offset must lie within the new code segment.
+    fn enter_code_segment(
+        &mut self,
+        segment: Segment,
+        offset: u32,
+        call_size: Option<u32>,
+    ) -> Result<(), Exception> {
+        if !segment.contains(offset, 1) {
+            return Err(Exception::GeneralProtection(0));
+        }
+        if let Some(size) = call_size {
+            let cs = self.get_segment_register(SegmentRegister::Cs) as u32;
+            self.push_sized(cs, size)?;
+            self.push_sized(self.eip, size)?;
+        }
+        self.segments[SegmentRegister::Cs as usize] = segment;
+        self.eip = offset;
+        Ok(())
+    }
+
+    fn call_far_ptr(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let offset = self.get_code_sized(1, size);
+        let selector = self.get_code16(1 + (size / 8) as usize);
+        self.advance_eip(3 + size / 8);
+        self.far_transfer(selector, offset, Some(size))
+    }
+
+    fn jmp_far_ptr(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let offset = self.get_code_sized(1, size);
+        let selector = self.get_code16(1 + (size / 8) as usize);
+        // A task switch saves the address of the next instruction.
+        self.advance_eip(3 + size / 8);
+        self.far_transfer(selector, offset, None)
+    }
+
+    /// Checks the code segment selector popped by RETF or IRET and builds
+    /// its descriptor cache. In protected mode its RPL is the privilege
+    /// level being returned to, which may be the current one or an outer
+    /// one.
+    fn return_segment(&mut self, selector: u16) -> Result<Segment, Exception> {
+        if self.is_real_mode_addressing() {
+            let mut segment = *self.segment(SegmentRegister::Cs);
+            segment.load_real_mode(selector);
+            return Ok(segment);
+        }
+        if selector & !3 == 0 {
+            return Err(Exception::GeneralProtection(0));
+        }
+        let error_code = selector & 0xfffc;
+        let rpl = selector & 3;
+        if rpl < self.cpl() {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        let mut descriptor = self.read_descriptor(selector)?;
+        let is_allowed = if descriptor.is_conforming() {
+            descriptor.dpl() <= rpl
+        } else {
+            descriptor.is_code() && descriptor.dpl() == rpl
+        };
+        if !is_allowed {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        self.set_descriptor_accessed(selector, &mut descriptor)?;
+        Ok(descriptor.to_segment(selector))
+    }
+
+    /// Finishes a RETF or IRET by loading CS:EIP. A return to an outer
+    /// privilege level also pops the caller's ESP and SS, which are checked
+    /// against the new CPL, releases `release` bytes of parameters from
+    /// that stack as well, and nulls the data segment registers the outer
+    /// level may not use.
+    fn complete_far_return(
+        &mut self,
+        segment: Segment,
+        offset: u32,
+        size: u32,
+        release: u32,
+    ) -> Result<(), Exception> {
+        if self.is_real_mode_addressing() || segment.selector & 3 == self.cpl() {
+            return self.enter_code_segment(segment, offset, None);
+        }
+        let esp = self.pop_sized(size)?;
+        let ss = self.pop_sized(size)? as u16;
+        self.enter_code_segment(segment, offset, None)?;
+        self.set_segment_register(SegmentRegister::Ss, ss)?;
+        self.set_stack_pointer(esp.wrapping_add(release));
+        self.invalidate_data_segments();
+        Ok(())
+    }
+
+    fn retf(&mut self) -> Result<(), Exception> {
+        self.return_far(0)
+    }
+
+    fn retf_imm16(&mut self) -> Result<(), Exception> {
+        let release = self.get_code16(1) as u32;
+        self.return_far(release)
+    }
+
+    /// Pops the return address of a far CALL and releases `release` bytes
+    /// of parameters.
+    fn return_far(&mut self, release: u32) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let offset = self.pop_sized(size)?;
+        let selector = self.pop_sized(size)? as u16;
+        let segment = self.return_segment(selector)?;
+        self.set_stack_pointer(self.get_stack_pointer().wrapping_add(release));
+        self.complete_far_return(segment, offset, size, release)
+    }
+
+    /// IRET pops EIP, CS and EFLAGS, and ESP and SS when returning to an
+    /// outer privilege level. As with POPF, VM is left unchanged and IOPL
+    /// and IF are only loaded with enough privilege, except that a 32-bit
+    /// IRET at CPL 0 can set VM to enter virtual-8086 mode. With NT set in
+    /// protected mode it instead switches back to the task named by the
+    /// back link of the current TSS.
+    fn iret(&mut self) -> Result<(), Exception> {
+        self.check_virtual_8086_iopl()?;
+        if !self.is_real_mode_addressing() && self.get_eflags() & NESTED_TASK_FLAG != 0 {
+            self.advance_eip(1);
+            let back_link = self.read_linear(self.tr.base, 16, false)? as u16;
+            let descriptor = self.read_tss_descriptor(back_link, true, Exception::InvalidTss)?;
+            return self.task_switch(back_link, descriptor, TaskSwitch::Iret);
+        }
+        let size = self.operand_size();
+        let offset = self.pop_sized(size)?;
+        let selector = self.pop_sized(size)? as u16;
+        let value = self.pop_sized(size)?;
+        if size == 32
+            && value & VIRTUAL_8086_FLAG != 0
+            && self.is_protected_mode()
+            && self.cpl() == 0
+        {
+            return self.enter_virtual_8086_mode(offset, selector, value);
+        }
+        let eflags = self.get_eflags();
+        let value = if size == 16 {
+            (eflags & 0xffff0000) | value
+        } else {
+            (value & !VIRTUAL_8086_FLAG) | (eflags & VIRTUAL_8086_FLAG)
+        };
+        let segment = self.return_segment(selector)?;
+        self.set_eflags_privileged(value);
+        self.complete_far_return(segment, offset, size, 0)
+    }
+
+    /// Finishes an IRET to virtual-8086 mode: ESP, SS, ES, DS, FS and GS
+    /// follow EFLAGS on the stack as dwords, and all segment registers are
+    /// loaded as in real mode.
+    fn enter_virtual_8086_mode(&mut self, eip: u32, cs: u16, eflags: u32) -> Result<(), Exception> {
+        let esp = self.pop_sized(32)?;
+        let mut selectors = [0; 6];
+        selectors[SegmentRegister::Cs as usize] = cs;
+        for reg in [
+            SegmentRegister::Ss,
+            SegmentRegister::Es,
+            SegmentRegister::Ds,
+            SegmentRegister::Fs,
+            SegmentRegister::Gs,
+        ] {
+            selectors[reg as usize] = self.pop_sized(32)? as u16;
+        }
+        self.set_eflags(eflags);
+        for (segment, &selector) in self.segments.iter_mut().zip(selectors.iter()) {
+            *segment = Segment::virtual_8086(selector);
+        }
+        self.set_register32(Register::Esp as usize, esp);
+        self.eip = eip & 0xffff;
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Result<(), Exception> {
+        let ebp = self.get_register_sized(Register::Ebp as usize, self.stack_size());
+        self.set_stack_pointer(ebp);
+        let size = self.operand_size();
+        let popped_value = self.pop_sized(size)?;
+        self.set_register_sized(Register::Ebp as usize, popped_value, size);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    /// ENTER imm16, imm8: builds a stack frame of imm16 bytes, copying
+    /// imm8 - 1 enclosing frame pointers for nested procedures.
+    fn enter(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let frame_size = self.get_code16(1) as u32;
+        let level = self.get_code8(3) & 0x1f;
+
+        let ebp = self.get_register_sized(Register::Ebp as usize, size);
+        self.push_sized(ebp, size)?;
+        let frame_temp = self.get_stack_pointer();
+        if level > 0 {
+            let stack_size = self.stack_size();
+            let mut ebp = self.get_register_sized(Register::Ebp as usize, stack_size);
+            for _ in 1..level {
+                ebp = ebp.wrapping_sub(size / 8) & ((1u64 << stack_size) - 1) as u32;
+                let value = self.read_memory(SegmentRegister::Ss, ebp, size)?;
+                self.push_sized(value, size)?;
+            }
+            self.push_sized(frame_temp, size)?;
+        }
+        self.set_register_sized(Register::Ebp as usize, frame_temp, size);
+        self.set_stack_pointer(self.get_stack_pointer().wrapping_sub(frame_size));
+        self.advance_eip(4);
+        Ok(())
+    }
+
+    /// PUSHA pushes the value ESP had before the first push.
+    fn pusha(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let esp = self.get_register_sized(Register::Esp as usize, size);
+        for reg in 0..8 {
+            let value = if reg == Register::Esp as usize {
+                esp
+            } else {
+                self.get_register_sized(reg, size)
+            };
+            self.push_sized(value, size)?;
+        }
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    /// POPA discards the saved ESP instead of loading it.
+    fn popa(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        for reg in (0..8).rev() {
+            let value = self.pop_sized(size)?;
+            if reg != Register::Esp as usize {
+                self.set_register_sized(reg, value, size);
+            }
+        }
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn pushf(&mut self) -> Result<(), Exception> {
+        self.check_virtual_8086_iopl()?;
+        let size = self.operand_size();
+        let eflags = self.get_eflags() & !(VIRTUAL_8086_FLAG | RESUME_FLAG);
+        self.push_sized(eflags, size)?;
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    /// POPF leaves VM alone and clears RF; the 16-bit form only replaces
+    /// the low word of EFLAGS. IOPL and IF need privilege to change.
+    fn popf(&mut self) -> Result<(), Exception> {
+        self.check_virtual_8086_iopl()?;
+        let size = self.operand_size();
+        let value = self.pop_sized(size)?;
+        let eflags = self.get_eflags();
+        let value = if size == 16 {
+            (eflags & 0xffff0000) | value
+        } else {
+            (value & !(VIRTUAL_8086_FLAG | RESUME_FLAG)) | (eflags & VIRTUAL_8086_FLAG)
+        };
+        self.set_eflags_privileged(value);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    /// Decodes the segment register of PUSH/POP Sreg from bits 3-5 of the
+    /// opcode, which is the second byte for the 0F-prefixed FS and GS forms.
+    fn decode_sreg_opcode(&self) -> (SegmentRegister, u32) {
+        let (code, length) = if self.get_code8(0) == 0x0F {
+            (self.get_code8(1), 2)
+        } else {
+            (self.get_code8(0), 1)
+        };
+        let reg = SegmentRegister::from_usize(((code >> 3) & 0x07) as usize)
+            .expect("Invalid segment register in opcode");
+        (reg, length)
+    }
+
+    fn push_sreg(&mut self) -> Result<(), Exception> {
+        let (reg, length) = self.decode_sreg_opcode();
+        let value = self.get_segment_register(reg) as u32;
+        self.push_sized(value, self.operand_size())?;
+        self.advance_eip(length);
+        Ok(())
+    }
+
+    fn pop_sreg(&mut self) -> Result<(), Exception> {
+        let (reg, length) = self.decode_sreg_opcode();
+        let value = self.pop_sized(self.operand_size())?;
+        self.set_segment_register(reg, value as u16)?;
+        self.advance_eip(length);
+        Ok(())
+    }
+
+    /// MOV r/m16, Sreg. A memory destination is always a word; a 32-bit
+    /// register destination gets the selector zero-extended.
+    fn mov_rm16_sreg(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let reg =
+            SegmentRegister::from_usize(modrm.opecode as usize).ok_or(Exception::InvalidOpcode)?;
+        let value = self.get_segment_register(reg);
+        if modrm.mod_val == 3 {
+            modrm.set_rm_sized(self, value as u32, self.operand_size())?;
+        } else {
+            modrm.set_rm16(self, value)?;
+        }
+        Ok(())
+    }
+
+    /// MOV Sreg, r/m16. CS can only be loaded by far transfers.
+    fn mov_sreg_rm16(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let reg = match SegmentRegister::from_usize(modrm.opecode as usize) {
+            Some(SegmentRegister::Cs) | None => return Err(Exception::InvalidOpcode),
+            Some(reg) => reg,
+        };
+        let value = modrm.get_rm16(self)?;
+        self.set_segment_register(reg, value)
+    }
+
+    fn short_jump(&mut self) -> Result<(), Exception> {
+        let diff = self.get_sign_code8(1);
+        self.jump_near(self.eip.wrapping_add(2).wrapping_add(diff as u32))
+    }
+
+    fn near_jump(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let length = 1 + size / 8;
+        let diff = sign_extend(self.get_code_sized(1, size), size) as u32;
+        self.jump_near(self.eip.wrapping_add(length).wrapping_add(diff))
+    }
+
+    /// Transfers control within the current code segment. With a 16-bit
+    /// operand size the upper half of EIP is cleared, as on the i386. A
+    /// target beyond the CS limit raises #GP(0).
+    fn jump_near(&mut self, target: u32) -> Result<(), Exception> {
+        let target = if self.operand_size() == 16 {
+            target & 0xffff
+        } else {
+            target
+        };
+        if !self.segment(SegmentRegister::Cs).contains(target, 1) {
+            return Err(Exception::GeneralProtection(0));
+        }
+        self.eip = target;
+        Ok(())
+    }
+
+    fn conditional_jump(&mut self, condition: bool) -> Result<(), Exception> {
+        self.execution.branch_taken = condition;
+        let diff = if condition { self.get_sign_code8(1) } else { 0 };
+        self.jump_near(self.eip.wrapping_add(2).wrapping_add(diff as u32))
+    }
+
+    fn short_jcc(&mut self) -> Result<(), Exception> {
+        let condition = self.check_condition(self.get_code8(0));
+        self.conditional_jump(condition)
+    }
+
+    fn near_jcc(&mut self) -> Result<(), Exception> {
+        let size = self.operand_size();
+        let length = 2 + size / 8;
+        self.execution.branch_taken = self.check_condition(self.get_code8(1));
+        let diff = if self.execution.branch_taken {
+            sign_extend(self.get_code_sized(2, size), size) as u32
+        } else {
+            0
+        };
+        self.jump_near(self.eip.wrapping_add(length).wrapping_add(diff))
+    }
+
+    fn setcc(&mut self) -> Result<(), Exception> {
+        let condition = self.check_condition(self.get_code8(1));
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        modrm.set_rm8(self, condition as u8)
+    }
+
+    fn movzx_r32_rm8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let value = modrm.get_rm8(self)? as u32;
+        modrm.set_r_sized(self, value, size);
+        Ok(())
+    }
+
+    fn movzx_r32_rm16(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let value = modrm.get_rm16(self)? as u32;
+        modrm.set_r_sized(self, value, size);
+        Ok(())
+    }
+
+    fn movsx_r32_rm8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let value = modrm.get_rm8(self)? as i8 as i32 as u32;
+        modrm.set_r_sized(self, value, size);
+        Ok(())
+    }
+
+    fn movsx_r32_rm16(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let value = modrm.get_rm16(self)? as i16 as i32 as u32;
+        modrm.set_r_sized(self, value, size);
+        Ok(())
+    }
+
+    fn imul_r32_rm32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let r = sign_extend(modrm.get_r_sized(self, size), size);
+        let result = r * sign_extend(modrm.get_rm_sized(self, size)?, size);
+        modrm.set_r_sized(self, result as u32, size);
+        let is_overflow = result != sign_extend(result as u32, size);
+        self.set_carry(is_overflow);
+        self.set_overflow(is_overflow);
+        Ok(())
+    }
+
+    fn bit_test_rm32_r32(&mut self) -> Result<(), Exception> {
+        let op = self.get_code8(1) >> 3;
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let bit = modrm.get_r_sized(self, size);
+        if modrm.mod_val == 3 {
+            let rm = modrm.get_rm_sized(self, size)?;
+            if let Some(result) = self.bit_test(op, rm, bit, size) {
+                modrm.set_rm_sized(self, result, size)?;
+            }
+        } else {
+            // With a register bit offset the memory operand is a bit string:
+            // the signed offset may select a word or dword outside the operand.
+            let index = sign_extend(bit, size) >> size.trailing_zeros();
+            let offset = index.wrapping_mul(size as i64 / 8) as u32;
+            let segment = modrm.segment(self);
+            let mut address = modrm.calc_effective_address(self).wrapping_add(offset);
+            if modrm.address_size == 16 {
+                address &= 0xffff;
+            }
+            let value = self.read_memory(segment, address, size)?;
+            if let Some(result) = self.bit_test(op, value, bit, size) {
+                self.write_memory(segment, address, result, size)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn code_0f_ba(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        if modrm.opecode < 4 {
+            return Err(Exception::InvalidOpcode);
+        }
+        let rm = modrm.get_rm_sized(self, size)?;
+        let imm8 = self.get_code8(0);
+        self.advance_eip(1);
+        if let Some(result) = self.bit_test(modrm.opecode, rm, imm8 as u32, size) {
+            modrm.set_rm_sized(self, result, size)?;
+        }
+        Ok(())
+    }
+
+    fn bsf_r32_rm32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let rm = modrm.get_rm_sized(self, size)?;
+        self.set_zero(rm == 0);
+        if rm != 0 {
+            modrm.set_r_sized(self, rm.trailing_zeros(), size);
+        }
+        Ok(())
+    }
+
+    fn bsr_r32_rm32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let rm = modrm.get_rm_sized(self, size)?;
+        self.set_zero(rm == 0);
+        if rm != 0 {
+            modrm.set_r_sized(self, 31 - rm.leading_zeros(), size);
+        }
+        Ok(())
+    }
+
+    fn shld_rm32_r32_imm8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
+        self.advance_eip(1);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        let result = self.double_shift(true, rm, r, count, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn shld_rm32_r32_cl(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let count = self.get_register8(Register8::Cl);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        let result = self.double_shift(true, rm, r, count, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn shrd_rm32_r32_imm8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
+        self.advance_eip(1);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        let result = self.double_shift(false, rm, r, count, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn shrd_rm32_r32_cl(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let count = self.get_register8(Register8::Cl);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        let result = self.double_shift(false, rm, r, count, size);
+        modrm.set_rm_sized(self, result, size)
+    }
+
+    fn daa(&mut self) {
+        let old_al = self.get_register8(Register8::Al);
+        let carry = old_al > 0x99 || self.is_carry();
+        let aux_carry = (old_al & 0x0f) > 9 || self.is_aux_carry();
+        let mut al = old_al;
+        if aux_carry {
+            al = al.wrapping_add(6);
+        }
+        if carry {
+            al = al.wrapping_add(0x60);
+        }
+        self.set_register8(Register8::Al, al);
+        self.update_eflags_logic(al as u32, 8);
+        self.set_carry(carry);
+        self.set_aux_carry(aux_carry);
+        self.advance_eip(1);
+    }
+
+    fn das(&mut self) {
+        let old_al = self.get_register8(Register8::Al);
+        let old_carry = self.is_carry();
+        let mut al = old_al;
+        let mut carry = false;
+        let aux_carry = (al & 0x0f) > 9 || self.is_aux_carry();
+        if aux_carry {
+            let (difference, is_borrow) = al.overflowing_sub(6);
+            al = difference;
+            carry = old_carry || is_borrow;
+        }
+        if old_al > 0x99 || old_carry {
+            al = al.wrapping_sub(0x60);
+            carry = true;
+        }
+        self.set_register8(Register8::Al, al);
+        self.update_eflags_logic(al as u32, 8);
+        self.set_carry(carry);
+        self.set_aux_carry(aux_carry);
+        self.advance_eip(1);
+    }
+
+    /// AAA and AAS follow the i386 definition, which adjusts AL and AH
+    /// separately (no carry from AL into AH as with a 16-bit add).
+    fn aaa(&mut self) {
+        let mut al = self.get_register8(Register8::Al);
+        let is_adjust = (al & 0x0f) > 9 || self.is_aux_carry();
+        if is_adjust {
+            al = al.wrapping_add(6);
+            let ah = self.get_register8(Register8::Ah).wrapping_add(1);
+            self.set_register8(Register8::Ah, ah);
+        }
+        self.set_register8(Register8::Al, al & 0x0f);
+        self.set_aux_carry(is_adjust);
+        self.set_carry(is_adjust);
+        self.advance_eip(1);
+    }
+
+    fn aas(&mut self) {
+        let mut al = self.get_register8(Register8::Al);
+        let is_adjust = (al & 0x0f) > 9 || self.is_aux_carry();
+        if is_adjust {
+            al = al.wrapping_sub(6);
+            let ah = self.get_register8(Register8::Ah).wrapping_sub(1);
+            self.set_register8(Register8::Ah, ah);
+        }
+        self.set_register8(Register8::Al, al & 0x0f);
+        self.set_aux_carry(is_adjust);
+        self.set_carry(is_adjust);
+        self.advance_eip(1);
+    }
+
+    fn aam(&mut self) -> Result<(), Exception> {
+        let base = self.get_code8(1);
+        if base == 0 {
+            return Err(Exception::DivideError);
+        }
+        let al = self.get_register8(Register8::Al);
+        self.set_register8(Register8::Ah, al / base);
+        self.set_register8(Register8::Al, al % base);
+        self.update_eflags_logic((al % base) as u32, 8);
+        self.advance_eip(2);
+        Ok(())
+    }
+
+    fn aad(&mut self) {
+        let base = self.get_code8(1);
+        let al = self.get_register8(Register8::Al);
+        let ah = self.get_register8(Register8::Ah);
+        let result = al.wrapping_add(ah.wrapping_mul(base));
+        self.set_register8(Register8::Al, result);
+        self.set_register8(Register8::Ah, 0);
+        self.update_eflags_logic(result as u32, 8);
+        self.advance_eip(2);
+    }
+
+    fn string_operand_size(&self) -> u32 {
+        if self.get_code8(0) & 1 == 0 {
+            8
+        } else {
+            self.operand_size()
+        }
+    }
+
+    /// String instructions address through SI/DI and count with CX when the
+    /// address size is 16 bits, and through ESI/EDI/ECX otherwise.
+    fn get_string_register(&self, reg: Register) -> u32 {
+        self.get_register_sized(reg as usize, self.address_size())
+    }
+
+    fn set_string_register(&mut self, reg: Register, value: u32) {
+        self.set_register_sized(reg as usize, value, self.address_size());
+    }
+
+    /// A REP-prefixed string instruction with a zero count does nothing.
+    fn is_string_count_exhausted(&self) -> bool {
+        self.prefixes.repeat.is_some() && self.get_string_register(Register::Ecx) == 0
+    }
+
+    fn advance_string_index(&mut self, reg: Register, size: u32) {
+        let delta = size / 8;
+        let index = self.get_string_register(reg);
+        let index = if self.is_direction() {
+            index.wrapping_sub(delta)
+        } else {
+            index.wrapping_add(delta)
+        };
+        self.set_string_register(reg, index);
+    }
+
+    /// The source operand of a string instruction, DS:eSI. The segment can
+    /// be overridden.
+    fn read_string_source(&mut self, size: u32) -> Result<u32, Exception> {
+        let esi = self.get_string_register(Register::Esi);
+        self.read_memory(self.segment_override(SegmentRegister::Ds), esi, size)
+    }
+
+    /// The destination operand of a string instruction, always ES:eDI.
+    fn read_string_destination(&mut self, size: u32) -> Result<u32, Exception> {
+        let edi = self.get_string_register(Register::Edi);
+        self.read_memory(SegmentRegister::Es, edi, size)
+    }
+
+    fn write_string_destination(&mut self, value: u32, size: u32) -> Result<(), Exception> {
+        let edi = self.get_string_register(Register::Edi);
+        self.write_memory(SegmentRegister::Es, edi, value, size)
+    }
+
+    /// Finishes one iteration of a string instruction. Under a REP prefix,
+    /// the count is decremented and, unless the repetition has terminated,
+    /// EIP is rewound onto the prefix so the next step runs the following
+    /// iteration. This keeps long copies interruptible between elements.
+    fn repeat_string(&mut self, is_compare: bool) {
+        let Some(repeat) = self.prefixes.repeat else {
+            return;
+        };
+        let count = self.get_string_register(Register::Ecx).wrapping_sub(1);
+        self.set_string_register(Register::Ecx, count);
+        let is_terminated = self.get_string_register(Register::Ecx) == 0
+            || (is_compare
+                && match repeat {
+                    RepeatPrefix::Rep => !self.is_zero(),
+                    RepeatPrefix::Repne => self.is_zero(),
+                });
+        if !is_terminated {
+            self.eip = self.instruction_start;
+        }
+    }
+
+    fn movs(&mut self) -> Result<(), Exception> {
+        let size = self.string_operand_size();
+        self.advance_eip(1);
+        if self.is_string_count_exhausted() {
+            return Ok(());
+        }
+        let value = self.read_string_source(size)?;
+        self.write_string_destination(value, size)?;
+        self.advance_string_index(Register::Esi, size);
+        self.advance_string_index(Register::Edi, size);
+        self.repeat_string(false);
+        Ok(())
+    }
+
+    fn cmps(&mut self) -> Result<(), Exception> {
+        let size = self.string_operand_size();
+        self.advance_eip(1);
+        if self.is_string_count_exhausted() {
+            return Ok(());
+        }
+        let v1 = self.read_string_source(size)?;
+        let v2 = self.read_string_destination(size)?;
+        self.update_eflags_sub(v1, v2, 0, size);
+        self.advance_string_index(Register::Esi, size);
+        self.advance_string_index(Register::Edi, size);
+        self.repeat_string(true);
+        Ok(())
+    }
+
+    fn stos(&mut self) -> Result<(), Exception> {
+        let size = self.string_operand_size();
+        self.advance_eip(1);
+        if self.is_string_count_exhausted() {
+            return Ok(());
+        }
+        let value = self.get_accumulator(size);
+        self.write_string_destination(value, size)?;
+        self.advance_string_index(Register::Edi, size);
+        self.repeat_string(false);
+        Ok(())
+    }
+
+    fn lods(&mut self) -> Result<(), Exception> {
+        let size = self.string_operand_size();
+        self.advance_eip(1);
+        if self.is_string_count_exhausted() {
+            return Ok(());
+        }
+        let value = self.read_string_source(size)?;
+        self.set_accumulator(value, size);
+        self.advance_string_index(Register::Esi, size);
+        self.repeat_string(false);
+        Ok(())
+    }
+
+    fn scas(&mut self) -> Result<(), Exception> {
+        let size = self.string_operand_size();
+        self.advance_eip(1);
+        if self.is_string_count_exhausted() {
+            return Ok(());
+        }
+        let value = self.read_string_destination(size)?;
+        let accumulator = self.get_accumulator(size);
+        self.update_eflags_sub(accumulator, value, 0, size);
+        self.advance_string_index(Register::Edi, size);
+        self.repeat_string(true);
+        Ok(())
+    }
+
+    fn cld(&mut self) {
+        self.set_direction(false);
+        self.advance_eip(1);
+    }
+
+    fn std(&mut self) {
+        self.set_direction(true);
+        self.advance_eip(1);
+    }
+
+    fn clc(&mut self) {
+        self.set_carry(false);
+        self.advance_eip(1);
+    }
+
+    fn stc(&mut self) {
+        self.set_carry(true);
+        self.advance_eip(1);
+    }
+
+    fn cmc(&mut self) {
+        let is_carry = self.is_carry();
+        self.set_carry(!is_carry);
+        self.advance_eip(1);
+    }
+
+    /// CLI and STI raise #GP(0) when CPL > IOPL.
+    fn cli(&mut self) -> Result<(), Exception> {
+        if !self.is_io_privileged() {
+            return Err(Exception::GeneralProtection(0));
+        }
+        self.set_flag(INTERRUPT_FLAG, false);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn sti(&mut self) -> Result<(), Exception> {
+        if !self.is_io_privileged() {
+            return Err(Exception::GeneralProtection(0));
+        }
+        self.set_flag(INTERRUPT_FLAG, true);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn lahf(&mut self) {
+        let value = self.get_eflags() as u8;
+        self.set_register8(Register8::Ah, value);
+        self.advance_eip(1);
+    }
+
+    fn sahf(&mut self) {
+        let ah = self.get_register8(Register8::Ah) as u32;
+        let eflags = self.get_eflags();
+        self.set_eflags((eflags & !AH_FLAGS) | (ah & AH_FLAGS));
+        self.advance_eip(1);
+    }
+
+    /// INT imm8. In real mode the emulated BIOS answers INT 10h and INT 1Ah
+    /// as long as the program has not installed a handler of its own in the
+    /// IVT.
+    fn swi(&mut self) -> Result<(), Exception> {
+        self.check_virtual_8086_iopl()?;
+        let vector = self.get_code8(1);
+        self.advance_eip(2);
+        let service: Option<fn(&mut Emulator)> = match vector {
+            0x10 => Some(bios_video),
+            0x1a => Some(bios_time),
+            _ => None,
+        };
+        if let Some(service) = service.filter(|_| !self.is_protected_mode()) {
+            let address = self.idtr.base.wrapping_add(vector as u32 * 4);
+            if self.read_linear(address, 32, false)? == 0 {
+                service(self);
+                return Ok(());
+            }
+        }
+        self.interrupt(vector, None, true)
+    }
+
+    /// INT3 (CC), the one-byte breakpoint. Unlike INT n it is not
+    /// IOPL-sensitive in virtual-8086 mode, but the gate DPL is checked.
+    fn int3(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        self.interrupt(3, None, true)
+    }
+
+    /// INTO (CE) raises #OF, vector 4, if OF is set. Like INT3 it is only
+    /// checked against the gate DPL.
+    fn interrupt_on_overflow(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        self.execution.branch_taken = self.is_overflow();
+        if !self.execution.branch_taken {
+            return Ok(());
+        }
+        self.interrupt(4, None, true)
+    }
+
+    /// ICEBP (F1) raises a #DB trap the way the hardware does, without the
+    /// gate DPL check of a software interrupt.
+    fn icebp(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        self.interrupt(1, None, false)
+    }
+
+    /// LOOPNE (E0), LOOPE (E1) and LOOP (E2) decrement CX or ECX, chosen by
+    /// the address size, without touching the flags.
+    fn loop_rel8(&mut self) -> Result<(), Exception> {
+        let opcode = self.get_code8(0);
+        let diff = self.get_sign_code8(1);
+        self.advance_eip(2);
+        let count = self.get_string_register(Register::Ecx).wrapping_sub(1);
+        self.set_string_register(Register::Ecx, count);
+        let is_taken = count != 0
+            && match opcode {
+                0xE0 => !self.is_zero(),
+                0xE1 => self.is_zero(),
+                _ => true,
+            };
+        self.execution.branch_taken = is_taken;
+        if is_taken {
+            self.jump_near(self.eip.wrapping_add(diff as u32))?;
+        }
+        Ok(())
+    }
+
+    fn jecxz(&mut self) -> Result<(), Exception> {
+        let diff = self.get_sign_code8(1);
+        self.advance_eip(2);
+        self.execution.branch_taken = self.get_string_register(Register::Ecx) == 0;
+        if self.execution.branch_taken {
+            self.jump_near(self.eip.wrapping_add(diff as u32))?;
+        }
+        Ok(())
+    }
+
+    fn hlt(&mut self) -> Result<(), Exception> {
+        self.require_cpl0()?;
+        self.advance_eip(1);
+        self.halted = true;
+        Ok(())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    fn nop(&mut self) {
+        self.advance_eip(1);
+    }
+
+    fn xchg_eax_r32(&mut self) {
+        let size = self.operand_size();
+        let reg = (self.get_code8(0) - 0x90) as usize;
+        let value = self.get_register_sized(reg, size);
+        let accumulator = self.get_accumulator(size);
+        self.set_register_sized(reg, accumulator, size);
+        self.set_accumulator(value, size);
+        self.advance_eip(1);
+    }
+
+    fn xchg_rm8_r8(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let rm8 = modrm.get_rm8(self)?;
+        let r8 = modrm.get_r8(self);
+        modrm.set_rm8(self, r8)?;
+        modrm.set_r8(self, rm8);
+        Ok(())
+    }
+
+    fn xchg_rm32_r32(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let size = self.operand_size();
+        let modrm = self.parse_modrm();
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        modrm.set_rm_sized(self, r, size)?;
+        modrm.set_r_sized(self, rm, size);
+        Ok(())
+    }
+
+    /// LEA stores the effective address itself, truncated to the operand
+    /// size. A register operand has no address and is undefined.
+    fn lea_r32_m(&mut self) -> Result<(), Exception> {
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        if modrm.mod_val == 3 {
+            return Err(Exception::InvalidOpcode);
+        }
+        let address = modrm.calc_effective_address(self);
+        modrm.set_r_sized(self, address, self.operand_size());
+        Ok(())
+    }
+
+    /// CBW sign-extends AL into AX, CWDE sign-extends AX into EAX.
+    fn cbw(&mut self) {
+        let size = self.operand_size();
+        let value = self.get_accumulator(size / 2);
+        self.set_accumulator(sign_extend(value, size / 2) as u32, size);
+        self.advance_eip(1);
+    }
+
+    /// CWD and CDQ fill DX or EDX with the sign of AX or EAX.
+    fn cwd(&mut self) {
+        let size = self.operand_size();
+        let value = self.get_accumulator(size);
+        let fill = if sign_extend(value, size) < 0 {
+            u32::MAX
+        } else {
+            0
+        };
+        self.set_register_sized(Register::Edx as usize, fill, size);
+        self.advance_eip(1);
+    }
+
+    fn xlat(&mut self) -> Result<(), Exception> {
+        let al = self.get_register8(Register8::Al) as u32;
+        let address = self.get_string_register(Register::Ebx).wrapping_add(al);
+        let offset = address & ((1u64 << self.address_size()) - 1) as u32;
+        let segment = self.segment_override(SegmentRegister::Ds);
+        let value = self.read_memory(segment, offset, 8)? as u8;
+        self.set_register8(Register8::Al, value);
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    /// FWAIT reports a pending unmasked x87 exception.
+    fn fwait(&mut self) -> Result<(), Exception> {
+        if self.cr0 & (CR0_MP | CR0_TS) == CR0_MP | CR0_TS {
+            return Err(Exception::DeviceNotAvailable);
+        }
+        if self.fpu.has_pending_exception() {
+            return Err(Exception::FloatingPointError);
+        }
+        self.advance_eip(1);
+        Ok(())
+    }
+
+    fn clts(&mut self) -> Result<(), Exception> {
+        self.require_cpl0()?;
+        self.cr0 &= !CR0_TS;
+        self.advance_eip(2);
+        Ok(())
+    }
+
+    /// MOV r32, CRn. The mod field is ignored: the operand is always a
+    /// general register.
+    fn mov_r32_cr(&mut self) -> Result<(), Exception> {
+        self.require_cpl0()?;
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let value = match modrm.opecode {
+            0 => self.cr0,
+            2 => self.cr2,
+            3 => self.cr3,
+            _ => return Err(Exception::InvalidOpcode),
+        };
+        self.set_register32(modrm.rm as usize, value);
+        Ok(())
+    }
+
+    /// MOV CRn, r32. Enabling paging without protection raises #GP(0).
+    fn mov_cr_r32(&mut self) -> Result<(), Exception> {
+        self.require_cpl0()?;
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let value = self.get_register32(modrm.rm as usize);
+        match modrm.opecode {
+            0 => {
+                if value & (CR0_PG | CR0_PE) == CR0_PG {
+                    return Err(Exception::GeneralProtection(0));
+                }
+                if (self.cr0 ^ value) & (CR0_PG | CR0_WP) != 0 {
+                    self.tlb.flush();
+                }
+                let writable = if self.model >= CpuModel::I486 {
+                    CR0_WRITABLE_486
+                } else {
+                    CR0_WRITABLE
+                };
+                self.cr0 = value & writable | CR0_ET;
+            }
+            2 => self.cr2 = value,
+            3 => {
+                self.cr3 = value & PAGE_FRAME;
+                self.tlb.flush();
+            }
+            _ => return Err(Exception::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    /// Checks an access to a debug register, which needs CPL 0. With GD set
+    /// it raises #DB first, reporting BD, so that a debugger can keep the
+    /// registers to itself.
+    fn check_debug_register_access(&mut self) -> Result<(), Exception> {
+        self.require_cpl0()?;
+        if self.dr7 & DR7_GD != 0 {
+            self.dr6 |= DR6_BD;
+            return Err(Exception::Debug);
+        }
+        Ok(())
+    }
+
+    /// MOV r32, DRn. DR4 and DR5 are aliases of DR6 and DR7.
+    fn mov_r32_dr(&mut self) -> Result<(), Exception> {
+        self.check_debug_register_access()?;
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let value = match modrm.opecode {
+            n @ 0..=3 => self.debug_address[n as usize],
+            4 | 6 => self.dr6,
+            _ => self.dr7,
+        };
+        self.set_register32(modrm.rm as usize, value);
+        Ok(())
+    }
+
+    /// MOV DRn, r32. The reserved bits of DR6 stay set.
+    fn mov_dr_r32(&mut self) -> Result<(), Exception> {
+        self.check_debug_register_access()?;
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let value = self.get_register32(modrm.rm as usize);
+        match modrm.opecode {
+            n @ 0..=3 => self.debug_address[n as usize] = value,
+            4 | 6 => self.dr6 = value | DR6_RESERVED,
+            _ => self.dr7 = value,
+        }
+        Ok(())
     }
 
-    fn cmp_eax_imm32(&mut self) {
-        let value = self.get_code32(1);
-        let eax = self.get_register32(Register::Eax as usize);
-        let result = (eax as u64).wrapping_sub(value as u64);
-        self.update_eflags_sub(eax, value, result);
-        self.eip += 5;
+    /// BSWAP r32 (486) reverses the byte order of a register. With a
+    /// 16-bit operand the result is undefined; the low word is cleared.
+    fn bswap(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::I486)?;
+        let index = (self.get_code8(1) & 7) as usize;
+        self.advance_eip(2);
+        let value = self.get_register32(index).swap_bytes();
+        match self.operand_size() {
+            16 => self.set_register16(index, 0),
+            _ => self.set_register32(index, value),
+        }
+        Ok(())
+    }
+
+    /// The byte form of XADD and CMPXCHG has an even opcode.
+    fn exchange_operand_size(&self) -> u32 {
+        if self.get_code8(1) & 1 == 0 {
+            8
+        } else {
+            self.operand_size()
+        }
     }
 
-    fn cmp_r32_rm32(&mut self) {
-        self.eip += 1;
+    /// XADD r/m, r (486) loads the destination into the register and
+    /// stores the sum in the destination, setting the flags as ADD does.
+    fn xadd(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::I486)?;
+        let size = self.exchange_operand_size();
+        self.advance_eip(2);
         let modrm = self.parse_modrm();
-        let r32 = modrm.get_r32(self);
-        let rm32 = modrm.get_rm32(self);
-        let result = (r32 as u64).wrapping_sub(rm32 as u64);
-        self.update_eflags_sub(r32, rm32, result);
+        let rm = modrm.get_rm_sized(self, size)?;
+        let r = modrm.get_r_sized(self, size);
+        let result = self.update_eflags_add(rm, r, 0, size);
+        modrm.set_r_sized(self, rm, size);
+        modrm.set_rm_sized(self, result, size)
     }
 
-    fn conditional_jump(&mut self, condition: bool) {
-        let diff = if condition {
-            self.get_sign_code8(1) as i8
+    /// CMPXCHG r/m, r (486) compares the accumulator with the destination,
+    /// setting the flags as CMP does. If they are equal the register is
+    /// stored in the destination; otherwise the destination is loaded into
+    /// the accumulator. A memory destination is written either way.
+    fn cmpxchg(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::I486)?;
+        let size = self.exchange_operand_size();
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let rm = modrm.get_rm_sized(self, size)?;
+        let accumulator = self.get_accumulator(size);
+        self.update_eflags_sub(accumulator, rm, 0, size);
+        if accumulator == rm {
+            let r = modrm.get_r_sized(self, size);
+            modrm.set_rm_sized(self, r, size)
         } else {
-            0
+            modrm.set_rm_sized(self, rm, size)?;
+            self.set_accumulator(rm, size);
+            Ok(())
+        }
+    }
+
+    /// WBINVD (486) writes back and invalidates the caches, which the
+    /// emulator does not have.
+    fn wbinvd(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::I486)?;
+        self.require_cpl0()?;
+        self.advance_eip(2);
+        Ok(())
+    }
+
+    /// CPUID (Pentium). Leaf 0 returns the highest leaf, 1, and the vendor
+    /// string; leaf 1 the family, model and stepping and the feature flags.
+    /// Higher leaves return the same as the highest one.
+    fn cpuid(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::Pentium)?;
+        self.advance_eip(2);
+        let (eax, ebx, ecx, edx) = match self.get_register32(Register::Eax as usize) {
+            0 => {
+                let vendor =
+                    |i: usize| u32::from_le_bytes(CPUID_VENDOR[i..i + 4].try_into().unwrap());
+                (1, vendor(0), vendor(8), vendor(4))
+            }
+            _ => (CPUID_SIGNATURE, 0, 0, CPUID_FEATURES),
         };
-        self.eip = self.eip.wrapping_add(diff as u32 + 2);
+        self.set_register32(Register::Eax as usize, eax);
+        self.set_register32(Register::Ebx as usize, ebx);
+        self.set_register32(Register::Ecx as usize, ecx);
+        self.set_register32(Register::Edx as usize, edx);
+        Ok(())
     }
 
-    fn jc(&mut self) {
-        self.conditional_jump(self.is_carry());
+    /// The time-stamp counter counts the same clocks as the cycle counter.
+    fn time_stamp_counter(&self) -> u64 {
+        self.cycles.wrapping_add(self.tsc_offset)
     }
 
-    fn jnc(&mut self) {
-        self.conditional_jump(!self.is_carry());
+    fn set_edx_eax(&mut self, value: u64) {
+        self.set_register32(Register::Eax as usize, value as u32);
+        self.set_register32(Register::Edx as usize, (value >> 32) as u32);
     }
 
-    fn jz(&mut self) {
-        self.conditional_jump(self.is_zero());
+    fn get_edx_eax(&self) -> u64 {
+        u64::from(self.get_register32(Register::Edx as usize)) << 32
+            | u64::from(self.get_register32(Register::Eax as usize))
     }
 
-    fn jnz(&mut self) {
-        self.conditional_jump(!self.is_zero());
+    /// RDTSC (Pentium) loads the time-stamp counter into EDX:EAX.
+    fn rdtsc(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::Pentium)?;
+        self.advance_eip(2);
+        self.set_edx_eax(self.time_stamp_counter());
+        Ok(())
     }
 
-    fn js(&mut self) {
-        self.conditional_jump(self.is_sign());
+    /// RDMSR (Pentium) loads the model-specific register numbered by ECX
+    /// into EDX:EAX. An MSR the Pentium does not have raises #GP(0).
+    fn rdmsr(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::Pentium)?;
+        self.require_cpl0()?;
+        let msr = self.get_register32(Register::Ecx as usize);
+        let value = match msr {
+            MSR_TSC => self.time_stamp_counter(),
+            _ => *self.msrs.get(&msr).ok_or(Exception::GeneralProtection(0))?,
+        };
+        self.advance_eip(2);
+        self.set_edx_eax(value);
+        Ok(())
+    }
+
+    /// WRMSR (Pentium) stores EDX:EAX in the model-specific register
+    /// numbered by ECX. Writing the time-stamp counter moves it to the new
+    /// value from where it counts on.
+    fn wrmsr(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::Pentium)?;
+        self.require_cpl0()?;
+        let msr = self.get_register32(Register::Ecx as usize);
+        let value = self.get_edx_eax();
+        match msr {
+            MSR_TSC => self.tsc_offset = value.wrapping_sub(self.cycles),
+            _ => {
+                *self
+                    .msrs
+                    .get_mut(&msr)
+                    .ok_or(Exception::GeneralProtection(0))? = value
+            }
+        }
+        self.advance_eip(2);
+        Ok(())
+    }
+
+    /// Group 9: CMPXCHG8B m64 (Pentium) compares EDX:EAX with the operand
+    /// and stores ECX:EBX in it if they are equal, setting ZF; otherwise
+    /// it loads the operand into EDX:EAX and clears ZF. The operand is
+    /// written either way.
+    fn code_0f_c7(&mut self) -> Result<(), Exception> {
+        self.require_model(CpuModel::Pentium)?;
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        if modrm.opecode != 1 || modrm.mod_val == 3 {
+            return Err(Exception::InvalidOpcode);
+        }
+        let segment = modrm.segment(self);
+        let offset = modrm.calc_effective_address(self);
+        let low = self.read_memory(segment, offset, 32)?;
+        let high = self.read_memory(segment, offset.wrapping_add(4), 32)?;
+        let value = u64::from(high) << 32 | u64::from(low);
+        let is_equal = value == self.get_edx_eax();
+        let (low, high) = if is_equal {
+            (
+                self.get_register32(Register::Ebx as usize),
+                self.get_register32(Register::Ecx as usize),
+            )
+        } else {
+            (low, high)
+        };
+        self.write_memory(segment, offset, low, 32)?;
+        self.write_memory(segment, offset.wrapping_add(4), high, 32)?;
+        if !is_equal {
+            self.set_edx_eax(value);
+        }
+        self.set_zero(is_equal);
+        Ok(())
+    }
+
+    /// Group 6: SLDT, STR, LLDT, LTR, VERR and VERW. These only exist in
+    /// protected mode, and not in virtual-8086 mode.
+    fn code_0f_00(&mut self) -> Result<(), Exception> {
+        if self.is_real_mode_addressing() {
+            return Err(Exception::InvalidOpcode);
+        }
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        match modrm.opecode {
+            0 => self.store_selector(&modrm, self.ldtr.selector),
+            1 => self.store_selector(&modrm, self.tr.selector),
+            2 => {
+                self.require_cpl0()?;
+                let selector = modrm.get_rm16(self)?;
+                self.load_ldt(selector)
+            }
+            3 => {
+                self.require_cpl0()?;
+                let selector = modrm.get_rm16(self)?;
+                self.load_tr(selector)
+            }
+            4 | 5 => {
+                let selector = modrm.get_rm16(self)?;
+                let is_write = modrm.opecode == 5;
+                let is_accessible = match self.probe_descriptor(selector)? {
+                    Some(descriptor) if self.is_descriptor_visible(selector, &descriptor) => {
+                        if is_write {
+                            descriptor.is_writable()
+                        } else {
+                            descriptor.is_readable()
+                        }
+                    }
+                    _ => false,
+                };
+                self.set_zero(is_accessible);
+                Ok(())
+            }
+            _ => Err(Exception::InvalidOpcode),
+        }
     }
 
-    fn jns(&mut self) {
-        self.conditional_jump(!self.is_sign());
+    /// Stores a selector or the MSW: a word to memory, or zero-extended to
+    /// the operand size in a register.
+    fn store_selector(&mut self, modrm: &ModRM, value: u16) -> Result<(), Exception> {
+        if modrm.mod_val == 3 {
+            modrm.set_rm_sized(self, value as u32, self.operand_size())
+        } else {
+            modrm.set_rm16(self, value)
+        }
     }
 
-    fn jo(&mut self) {
-        self.conditional_jump(self.is_overflow());
+    /// LLDT. The selector must refer to an LDT descriptor in the GDT; a null
+    /// selector leaves the LDT unusable.
+    fn load_ldt(&mut self, selector: u16) -> Result<(), Exception> {
+        if selector & !3 == 0 {
+            self.ldtr = Segment::null(selector);
+            return Ok(());
+        }
+        let error_code = selector & 0xfffc;
+        if selector & 4 != 0 {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        let descriptor = self.read_descriptor(selector)?;
+        if descriptor.is_segment() || descriptor.system_type() != SYSTEM_LDT {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        self.ldtr = descriptor.to_segment(selector);
+        Ok(())
     }
 
-    fn jno(&mut self) {
-        self.conditional_jump(!self.is_overflow());
+    /// LTR. The selector must refer to an available TSS descriptor in the
+    /// GDT, which is marked busy.
+    fn load_tr(&mut self, selector: u16) -> Result<(), Exception> {
+        if selector & !3 == 0 {
+            return Err(Exception::GeneralProtection(0));
+        }
+        let error_code = selector & 0xfffc;
+        if selector & 4 != 0 {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        let mut descriptor = self.read_descriptor(selector)?;
+        if descriptor.is_segment()
+            || !matches!(descriptor.system_type(), SYSTEM_TSS_16 | SYSTEM_TSS)
+        {
+            return Err(Exception::GeneralProtection(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        descriptor.access |= TSS_BUSY;
+        self.write_descriptor_access(selector, descriptor.access)?;
+        self.tr = descriptor.to_segment(selector);
+        Ok(())
     }
 
-    fn jl(&mut self) {
-        let diff = if self.is_sign() != self.is_overflow() {
-            self.get_sign_code8(1) as i8
+    /// Reads the descriptor of a TSS named by a task gate or a back link. It
+    /// must be in the GDT and be busy or available as `is_busy` says; other
+    /// selectors raise `fault`.
+    fn read_tss_descriptor(
+        &mut self,
+        selector: u16,
+        is_busy: bool,
+        fault: fn(u16) -> Exception,
+    ) -> Result<Descriptor, Exception> {
+        let error_code = selector & 0xfffc;
+        if selector & 4 != 0 || self.descriptor_address(selector).is_err() {
+            return Err(fault(error_code));
+        }
+        let descriptor = self.read_descriptor(selector)?;
+        let types = if is_busy {
+            [SYSTEM_TSS_16_BUSY, SYSTEM_TSS_BUSY]
         } else {
-            0
+            [SYSTEM_TSS_16, SYSTEM_TSS]
         };
-        self.eip = self.eip.wrapping_add(diff as u32 + 2);
+        if descriptor.is_segment() || !types.contains(&descriptor.system_type()) {
+            return Err(fault(error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(Exception::SegmentNotPresent(error_code));
+        }
+        Ok(descriptor)
     }
 
-    fn jle(&mut self) {
-        let diff = if self.is_zero() || (self.is_sign() != self.is_overflow()) {
-            self.get_sign_code8(1) as i8
+    fn set_tss_busy(&mut self, selector: u16, is_busy: bool) -> Result<(), Exception> {
+        let address = self.descriptor_address(selector)?.wrapping_add(5);
+        let access = self.read_linear(address, 8, false)? as u8;
+        let access = if is_busy {
+            access | TSS_BUSY
         } else {
-            0
+            access & !TSS_BUSY
+        };
+        self.write_linear(address, access as u32, 8, false)
+    }
+
+    /// Switches to the task whose TSS `selector` and `descriptor` describe.
+    /// The registers of the current task are saved in its TSS and those of
+    /// the new task loaded from its own. A CALL or interrupt stores the old
+    /// TSS selector in the back link of the new one and sets NT; a JMP or
+    /// IRET marks the old TSS available again. Faults while loading the new
+    /// task's LDT and segments are raised in the new task, as #TS for bad
+    /// selectors.
+    fn task_switch(
+        &mut self,
+        selector: u16,
+        mut descriptor: Descriptor,
+        kind: TaskSwitch,
+    ) -> Result<(), Exception> {
+        let error_code = selector & 0xfffc;
+        let is_32bit = matches!(descriptor.system_type(), SYSTEM_TSS | SYSTEM_TSS_BUSY);
+        let minimum_limit = if is_32bit { 0x67 } else { 0x2b };
+        if descriptor.limit < minimum_limit || !self.tr.is_usable() {
+            return Err(Exception::InvalidTss(error_code));
+        }
+        let state = self.read_task_state(descriptor.base, is_32bit)?;
+
+        let mut eflags = self.get_eflags();
+        if kind == TaskSwitch::Iret {
+            eflags &= !NESTED_TASK_FLAG;
+        }
+        self.save_task_state(eflags)?;
+        let old_selector = self.tr.selector;
+        if matches!(kind, TaskSwitch::Jump | TaskSwitch::Iret) {
+            self.set_tss_busy(old_selector, false)?;
+        }
+        let is_nested = matches!(kind, TaskSwitch::Call | TaskSwitch::Interrupt);
+        if is_nested {
+            self.write_linear(descriptor.base, old_selector as u32, 16, false)?;
+        }
+        if kind != TaskSwitch::Iret {
+            self.set_tss_busy(selector, true)?;
+            descriptor.access |= TSS_BUSY;
+        }
+
+        self.task_switched = true;
+        self.tr = descriptor.to_segment(selector);
+        self.cr0 |= CR0_TS;
+        self.dr7 &= !DR7_LOCAL;
+        if state.debug_trap {
+            self.debug_trap |= DR6_BT;
+        }
+        if let Some(cr3) = state.cr3 {
+            self.cr3 = cr3 & PAGE_FRAME;
+            self.tlb.flush();
+        }
+        let eflags = if is_nested {
+            state.eflags | NESTED_TASK_FLAG
+        } else {
+            state.eflags
+        };
+        self.set_eflags(eflags);
+        self.eip = state.eip;
+        self.registers = state.registers;
+        // The selectors are loaded before any descriptor is checked, so
+        // that CPL comes from the new CS.
+        let is_virtual_8086 = self.is_virtual_8086_mode();
+        for (segment, &selector) in self.segments.iter_mut().zip(state.selectors.iter()) {
+            *segment = if is_virtual_8086 {
+                Segment::virtual_8086(selector)
+            } else {
+                Segment::null(selector)
+            };
+        }
+        self.load_task_segments(&state)
+    }
+
+    /// Reads EIP, EFLAGS, the general registers, the segment selectors, the
+    /// LDT selector and, in a 32-bit TSS, CR3 and the T bit. A 16-bit TSS
+    /// has no FS, GS or upper register halves, which are cleared.
+    fn read_task_state(&mut self, base: u32, is_32bit: bool) -> Result<TaskState, Exception> {
+        let (size, eip, registers, selectors, selector_count, ldt) = if is_32bit {
+            (32, 0x20, 0x28, 0x48, 6, 0x60)
+        } else {
+            (16, 0x0e, 0x12, 0x22, 4, 0x2a)
+        };
+        let field = |offset: u32, index: usize, size: u32| {
+            base.wrapping_add(offset + index as u32 * size / 8)
+        };
+        let mut state = TaskState {
+            eip: self.read_linear(field(eip, 0, size), size, false)?,
+            eflags: self.read_linear(field(eip, 1, size), size, false)?,
+            registers: [0; 8],
+            selectors: [0; 6],
+            ldt: self.read_linear(field(ldt, 0, size), 16, false)? as u16,
+            cr3: None,
+            debug_trap: false,
+        };
+        for (i, register) in state.registers.iter_mut().enumerate() {
+            *register = self.read_linear(field(registers, i, size), size, false)?;
+        }
+        for (i, selector) in state.selectors.iter_mut().enumerate().take(selector_count) {
+            *selector = self.read_linear(field(selectors, i, size), 16, false)? as u16;
+        }
+        if is_32bit {
+            state.cr3 = Some(self.read_linear(base.wrapping_add(0x1c), 32, false)?);
+            state.debug_trap = self.read_linear(base.wrapping_add(0x64), 16, false)? & 1 != 0;
+        }
+        Ok(state)
+    }
+
+    /// Saves EIP, `eflags`, the general registers and the segment selectors
+    /// in the TSS of the current task.
+    fn save_task_state(&mut self, eflags: u32) -> Result<(), Exception> {
+        let base = self.tr.base;
+        let (size, eip, registers, selectors, selector_count) = if self.is_tss_32bit() {
+            (32, 0x20, 0x28, 0x48, 6)
+        } else {
+            (16, 0x0e, 0x12, 0x22, 4)
+        };
+        let field = |offset: u32, index: usize| base.wrapping_add(offset + index as u32 * size / 8);
+        self.write_linear(field(eip, 0), self.eip, size, false)?;
+        self.write_linear(field(eip, 1), eflags, size, false)?;
+        for (i, value) in self.registers.into_iter().enumerate() {
+            self.write_linear(field(registers, i), value, size, false)?;
+        }
+        let segments = self.segments;
+        for (i, segment) in segments.iter().enumerate().take(selector_count) {
+            self.write_linear(field(selectors, i), segment.selector as u32, 16, false)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the LDT and the segment registers of a new task from the
+    /// selectors already in place. Selectors that cannot be loaded raise #TS
+    /// instead of #GP, as does an LDT that is not present, and EIP must lie
+    /// within the new code segment. A virtual-8086 task only needs its LDT.
+    fn load_task_segments(&mut self, state: &TaskState) -> Result<(), Exception> {
+        let to_invalid_tss = |exception| match exception {
+            Exception::GeneralProtection(code) => Exception::InvalidTss(code),
+            exception => exception,
+        };
+        self.load_ldt(state.ldt)
+            .map_err(|exception| match exception {
+                Exception::SegmentNotPresent(code) => Exception::InvalidTss(code),
+                exception => to_invalid_tss(exception),
+            })?;
+        if self.is_virtual_8086_mode() {
+            return Ok(());
+        }
+        let cs = state.selectors[SegmentRegister::Cs as usize];
+        self.segments[SegmentRegister::Cs as usize] =
+            self.return_segment(cs).map_err(to_invalid_tss)?;
+        for reg in [
+            SegmentRegister::Ss,
+            SegmentRegister::Es,
+            SegmentRegister::Ds,
+            SegmentRegister::Fs,
+            SegmentRegister::Gs,
+        ] {
+            let selector = state.selectors[reg as usize];
+            self.set_segment_register(reg, selector)
+                .map_err(to_invalid_tss)?;
+        }
+        if !self.segment(SegmentRegister::Cs).contains(self.eip, 1) {
+            return Err(Exception::GeneralProtection(0));
+        }
+        Ok(())
+    }
+
+    /// Group 7: SGDT, SIDT, LGDT, LIDT, SMSW, LMSW and, from the 486 on,
+    /// INVLPG.
+    fn code_0f_01(&mut self) -> Result<(), Exception> {
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        if matches!(modrm.opecode, 2 | 3 | 6) {
+            self.require_cpl0()?;
+        }
+        match modrm.opecode {
+            0..=3 if modrm.mod_val == 3 => Err(Exception::InvalidOpcode),
+            0 => self.store_descriptor_table(&modrm, self.gdtr),
+            1 => self.store_descriptor_table(&modrm, self.idtr),
+            2 => {
+                self.gdtr = self.load_descriptor_table(&modrm)?;
+                Ok(())
+            }
+            3 => {
+                self.idtr = self.load_descriptor_table(&modrm)?;
+                Ok(())
+            }
+            4 => self.store_selector(&modrm, self.cr0 as u16),
+            7 => {
+                self.require_model(CpuModel::I486)?;
+                if modrm.mod_val == 3 {
+                    return Err(Exception::InvalidOpcode);
+                }
+                self.require_cpl0()?;
+                let offset = modrm.calc_effective_address(self);
+                let linear = self.linear_address(modrm.segment(self), offset);
+                self.tlb.invalidate(linear);
+                Ok(())
+            }
+            6 => {
+                let msw = modrm.get_rm16(self)? as u32;
+                // LMSW can set PE but never clear it.
+                let pe = (self.cr0 | msw) & CR0_PE;
+                self.cr0 = self.cr0 & !MSW_WRITABLE | msw & MSW_WRITABLE & !CR0_PE | pe;
+                Ok(())
+            }
+            _ => Err(Exception::InvalidOpcode),
+        }
+    }
+
+    /// Reads the 6-byte limit and base operand of LGDT and LIDT. With a
+    /// 16-bit operand size only 24 bits of the base are used.
+    fn load_descriptor_table(&mut self, modrm: &ModRM) -> Result<DescriptorTable, Exception> {
+        let segment = modrm.segment(self);
+        let offset = modrm.calc_effective_address(self);
+        let limit = self.read_memory(segment, offset, 16)? as u16;
+        let base = self.read_memory(segment, offset.wrapping_add(2), 32)?;
+        let base = match self.operand_size() {
+            16 => base & 0x00ff_ffff,
+            _ => base,
+        };
+        Ok(DescriptorTable { base, limit })
+    }
+
+    /// SGDT and SIDT store the limit followed by all 32 bits of the base.
+    /// The base goes first so that a limit violation leaves memory untouched.
+    fn store_descriptor_table(
+        &mut self,
+        modrm: &ModRM,
+        table: DescriptorTable,
+    ) -> Result<(), Exception> {
+        let segment = modrm.segment(self);
+        let offset = modrm.calc_effective_address(self);
+        self.write_memory(segment, offset.wrapping_add(2), table.base, 32)?;
+        self.write_memory(segment, offset, table.limit as u32, 16)
+    }
+
+    /// LAR loads the access rights of the descriptor the selector in r/m16
+    /// refers to, masked with 00FxFF00h, and sets ZF; a bad selector, a
+    /// descriptor type without access rights or one that is not visible at
+    /// the current privilege level clears ZF instead.
+    fn lar_r32_rm16(&mut self) -> Result<(), Exception> {
+        self.load_descriptor_field(|descriptor| {
+            let is_valid = descriptor.is_segment()
+                || matches!(
+                    descriptor.system_type(),
+                    SYSTEM_TSS_16
+                        | SYSTEM_LDT
+                        | SYSTEM_TSS_16_BUSY
+                        | SYSTEM_CALL_GATE_16
+                        | SYSTEM_TASK_GATE
+                        | SYSTEM_TSS
+                        | SYSTEM_TSS_BUSY
+                        | SYSTEM_CALL_GATE
+                );
+            is_valid.then(|| descriptor.access_rights() & 0x00f0_ff00)
+        })
+    }
+
+    /// LSL loads the segment limit in bytes, under the same rules as LAR;
+    /// gates have no limit.
+    fn lsl_r32_rm16(&mut self) -> Result<(), Exception> {
+        self.load_descriptor_field(|descriptor| {
+            let is_valid = descriptor.is_segment()
+                || matches!(
+                    descriptor.system_type(),
+                    SYSTEM_TSS_16 | SYSTEM_LDT | SYSTEM_TSS_16_BUSY | SYSTEM_TSS | SYSTEM_TSS_BUSY
+                );
+            is_valid.then_some(descriptor.limit)
+        })
+    }
+
+    fn load_descriptor_field(
+        &mut self,
+        field: impl FnOnce(&Descriptor) -> Option<u32>,
+    ) -> Result<(), Exception> {
+        if self.is_real_mode_addressing() {
+            return Err(Exception::InvalidOpcode);
+        }
+        self.advance_eip(2);
+        let modrm = self.parse_modrm();
+        let selector = modrm.get_rm16(self)?;
+        let value = match self.probe_descriptor(selector)? {
+            Some(descriptor) if self.is_descriptor_visible(selector, &descriptor) => {
+                field(&descriptor)
+            }
+            _ => None,
+        };
+        self.set_zero(value.is_some());
+        if let Some(value) = value {
+            modrm.set_r_sized(self, value, self.operand_size());
+        }
+        Ok(())
+    }
+
+    /// ARPL r/m16, r16 raises the RPL of the destination selector to that
+    /// of the source, setting ZF if it had to be changed.
+    fn arpl_rm16_r16(&mut self) -> Result<(), Exception> {
+        if self.is_real_mode_addressing() {
+            return Err(Exception::InvalidOpcode);
+        }
+        self.advance_eip(1);
+        let modrm = self.parse_modrm();
+        let destination = modrm.get_rm16(self)?;
+        let rpl = modrm.get_r16(self) & 3;
+        let is_adjusted = destination & 3 < rpl;
+        if is_adjusted {
+            modrm.set_rm16(self, destination & !3 | rpl)?;
+        }
+        self.set_zero(is_adjusted);
+        Ok(())
+    }
+
+    fn get_memory_bytes(&mut self, address: u32, length: usize) -> Result<Vec<u8>, Exception> {
+        self.read_linear_bytes(address, length, self.cpl() == 3)
+    }
+
+    fn set_memory_bytes(&mut self, address: u32, bytes: &[u8]) -> Result<(), Exception> {
+        self.write_linear_bytes(address, bytes, self.cpl() == 3)
+    }
+
+    fn read_fpu_operand(&mut self, address: u32, format: Format) -> Result<[u8; 10], Exception> {
+        let mut bytes = [0; 10];
+        bytes[..format.size()].copy_from_slice(&self.get_memory_bytes(address, format.size())?);
+        Ok(bytes)
+    }
+
+    /// D8-DF: x87 instructions. Control instructions (FLDCW, FSTENV, FSAVE,
+    /// ...) leave the last-instruction pointers alone, and the FN* forms
+    /// among them do not report pending exceptions.
+    fn fpu_escape(&mut self) -> Result<(), Exception> {
+        if self.cr0 & (CR0_EM | CR0_TS) != 0 {
+            return Err(Exception::DeviceNotAvailable);
+        }
+        let escape = self.get_code8(0);
+        self.advance_eip(1);
+        let modrm_byte = self.get_code8(0);
+        let modrm = self.parse_modrm();
+        let is_memory = modrm.mod_val != 3;
+        let is_no_wait = match escape {
+            0xD9 | 0xDD => is_memory && modrm.opecode >= 6,
+            0xDB => modrm_byte == 0xE2 || modrm_byte == 0xE3,
+            0xDF => modrm_byte == 0xE0,
+            _ => false,
         };
-        self.eip = self.eip.wrapping_add(diff as u32 + 2);
+        let is_control = is_no_wait
+            || (is_memory && matches!((escape, modrm.opecode), (0xD9, 4 | 5) | (0xDD, 4)));
+        if !is_no_wait && self.fpu.has_pending_exception() {
+            return Err(Exception::FloatingPointError);
+        }
+        if !is_control {
+            let cs = self.get_segment_register(SegmentRegister::Cs);
+            let opcode = ((escape as u16 & 7) << 8) | modrm_byte as u16;
+            self.fpu
+                .record_instruction(self.instruction_start, cs, opcode);
+        }
+        if !is_memory {
+            return self.fpu_register_form(escape, modrm_byte);
+        }
+        let segment = modrm.segment(self);
+        let offset = modrm.calc_effective_address(self);
+        if !is_control {
+            let selector = self.get_segment_register(segment);
+            self.fpu.record_operand(offset, selector);
+        }
+        self.fpu_memory_form(escape, modrm.opecode, segment, offset)
     }
 
-    fn swi(&mut self) {
-        let int_index = self.get_code8(1);
-        self.eip += 2;
+    /// Each form checks its whole memory operand, segment limit and pages,
+    /// before touching the FPU, so a faulting store leaves the register
+    /// stack unchanged.
+    fn fpu_memory_form(
+        &mut self,
+        escape: u8,
+        reg: u8,
+        segment: SegmentRegister,
+        offset: u32,
+    ) -> Result<(), Exception> {
+        let operand = |emu: &mut Self, length: usize, is_write: bool| {
+            let address = emu.segment_address(segment, offset, length as u32, is_write)?;
+            emu.translate_range(address, length as u32, is_write, emu.cpl() == 3)?;
+            Ok(address)
+        };
+        let size = self.operand_size();
+        let real_mode = self.cr0 & CR0_PE == 0;
+        let (load, store) = match escape {
+            0xD8 | 0xD9 => (Format::Single, Format::Single),
+            0xDA | 0xDB => (Format::Int32, Format::Int32),
+            0xDC | 0xDD => (Format::Double, Format::Double),
+            _ => (Format::Int16, Format::Int16),
+        };
+        match (escape, reg) {
+            (0xD8 | 0xDA | 0xDC | 0xDE, _) => {
+                let address = operand(self, load.size(), false)?;
+                let operand = Operand::Memory(load, self.read_fpu_operand(address, load)?);
+                self.fpu
+                    .arithmetic(ArithOp::from_u8(reg), 0, operand, false);
+            }
+            (_, 0) => {
+                let address = operand(self, load.size(), false)?;
+                let operand = Operand::Memory(load, self.read_fpu_operand(address, load)?);
+                self.fpu.load_operand(operand);
+            }
+            (_, 2 | 3) => {
+                let address = operand(self, store.size(), true)?;
+                if let Some(bytes) = self.fpu.store_memory(store, reg == 3) {
+                    self.set_memory_bytes(address, &bytes[..store.size()])?;
+                }
+            }
+            (0xD9, 4) => {
+                let address = operand(self, size as usize * 7 / 8, false)?;
+                let bytes = self.get_memory_bytes(address, size as usize * 7 / 8)?;
+                self.fpu.load_environment(&bytes, size, real_mode);
+            }
+            (0xD9, 5) => {
+                let address = operand(self, 2, false)?;
+                let value = self.read_linear(address, 16, self.cpl() == 3)? as u16;
+                self.fpu.set_control_word(value);
+            }
+            (0xD9, 6) => {
+                let address = operand(self, size as usize * 7 / 8, true)?;
+                let bytes = self.fpu.store_environment(size, real_mode);
+                self.set_memory_bytes(address, &bytes)?;
+            }
+            (0xD9, 7) => {
+                let address = operand(self, 2, true)?;
+                let value = self.fpu.control_word();
+                self.write_linear(address, value as u32, 16, self.cpl() == 3)?;
+            }
+            (0xDB, 5) | (0xDF, 4) | (0xDF, 5) => {
+                let format = match reg {
+                    5 if escape == 0xDB => Format::Extended,
+                    4 => Format::Bcd,
+                    _ => Format::Int64,
+                };
+                let address = operand(self, format.size(), false)?;
+                let operand = Operand::Memory(format, self.read_fpu_operand(address, format)?);
+                self.fpu.load_operand(operand);
+            }
+            (0xDB, 7) | (0xDF, 6) | (0xDF, 7) => {
+                let format = match reg {
+                    7 if escape == 0xDB => Format::Extended,
+                    6 => Format::Bcd,
+                    _ => Format::Int64,
+                };
+                let address = operand(self, format.size(), true)?;
+                if let Some(bytes) = self.fpu.store_memory(format, true) {
+                    self.set_memory_bytes(address, &bytes[..format.size()])?;
+                }
+            }
+            (0xDD, 4) => {
+                let address = operand(self, size as usize * 7 / 8 + 80, false)?;
+                let bytes = self.get_memory_bytes(address, size as usize * 7 / 8 + 80)?;
+                self.fpu.restore(&bytes, size, real_mode);
+            }
+            (0xDD, 6) => {
+                let address = operand(self, size as usize * 7 / 8 + 80, true)?;
+                let bytes = self.fpu.save(size, real_mode);
+                self.set_memory_bytes(address, &bytes)?;
+            }
+            (0xDD, 7) => {
+                let address = operand(self, 2, true)?;
+                let value = self.fpu.status_word();
+                self.write_linear(address, value as u32, 16, self.cpl() == 3)?;
+            }
+            _ => return Err(Exception::InvalidOpcode),
+        }
+        Ok(())
+    }
 
-        match int_index {
-            0x10 => bios_video(self),
-            _ => println!("unknown interrupt: 0x{:02x}", int_index),
+    fn fpu_register_form(&mut self, escape: u8, modrm_byte: u8) -> Result<(), Exception> {
+        let index = (modrm_byte & 7) as usize;
+        let reg = (modrm_byte >> 3) & 7;
+        let op = ArithOp::from_u8(reg);
+        match (escape, reg) {
+            (0xD8 | 0xDC | 0xDE, 2 | 3) => {
+                let pops = if escape == 0xDE && modrm_byte == 0xD9 {
+                    2
+                } else {
+                    (reg == 3) as u32
+                };
+                if escape == 0xDE && reg == 2 {
+                    return Err(Exception::InvalidOpcode);
+                }
+                self.fpu.compare(Operand::Register(index), false, pops);
+            }
+            (0xD8, _) => self.fpu.arithmetic(op, 0, Operand::Register(index), false),
+            // DC and DE write ST(i), which swaps the encodings of the
+            // reversed and plain forms of FSUB and FDIV.
+            (0xDC | 0xDE, _) => {
+                let pop = escape == 0xDE;
+                self.fpu
+                    .arithmetic(op.reversed(), index, Operand::Register(0), pop);
+            }
+            (0xD9, 0) => self.fpu.load_operand(Operand::Register(index)),
+            (0xD9 | 0xDD | 0xDF, 1) => self.fpu.exchange(index),
+            (0xD9, 3) | (0xDD | 0xDF, 2 | 3) => {
+                self.fpu.store_register(index, escape == 0xD9 || reg == 3)
+            }
+            (0xDD, 0) => self.fpu.free(index),
+            (0xDD, 4 | 5) => self
+                .fpu
+                .compare(Operand::Register(index), true, (reg == 5) as u32),
+            _ => match (escape, modrm_byte) {
+                (0xD9, 0xD0) => {}
+                (0xD9, 0xE0) => self.fpu.change_sign(),
+                (0xD9, 0xE1) => self.fpu.absolute(),
+                (0xD9, 0xE4) => self.fpu.test(),
+                (0xD9, 0xE5) => self.fpu.examine(),
+                (0xD9, 0xE8..=0xEE) => self.fpu.load_constant(modrm_byte - 0xE8),
+                (0xD9, 0xF0) => self.fpu.exp2_minus_one(),
+                (0xD9, 0xF1) => self.fpu.y_log2_x(),
+                (0xD9, 0xF2) => self.fpu.partial_tangent(),
+                (0xD9, 0xF3) => self.fpu.partial_arctangent(),
+                (0xD9, 0xF4) => self.fpu.extract(),
+                (0xD9, 0xF5) => self.fpu.partial_remainder(true),
+                (0xD9, 0xF6) => self.fpu.rotate(false),
+                (0xD9, 0xF7) => self.fpu.rotate(true),
+                (0xD9, 0xF8) => self.fpu.partial_remainder(false),
+                (0xD9, 0xF9) => self.fpu.y_log2_x_plus_one(),
+                (0xD9, 0xFA) => self.fpu.square_root(),
+                (0xD9, 0xFB) => self.fpu.sine_cosine(),
+                (0xD9, 0xFC) => self.fpu.round_to_integer(),
+                (0xD9, 0xFD) => self.fpu.scale(),
+                (0xD9, 0xFE) => self.fpu.sine_or_cosine(false),
+                (0xD9, 0xFF) => self.fpu.sine_or_cosine(true),
+                (0xDA, 0xE9) => self.fpu.compare(Operand::Register(1), true, 2),
+                // FENI, FDISI and FSETPM are no-ops on a 387.
+                (0xDB, 0xE0 | 0xE1 | 0xE4) => {}
+                (0xDB, 0xE2) => self.fpu.clear_exceptions(),
+                (0xDB, 0xE3) => self.fpu.init(),
+                (0xDF, 0xE0) => {
+                    let value = self.fpu.status_word();
+                    self.set_register16(Register::Eax as usize, value);
+                }
+                _ => return Err(Exception::InvalidOpcode),
+            },
         }
+        Ok(())
     }
 
     pub fn init_instructions(&mut self) {
-        self.instructions.insert(0x01, Instruction::AddRm32R32);
+        for i in (0x00..0x40).step_by(8) {
+            self.instructions.insert(i, Instruction::AluRm8R8);
+            self.instructions.insert(i + 1, Instruction::AluRm32R32);
+            self.instructions.insert(i + 2, Instruction::AluR8Rm8);
+            self.instructions.insert(i + 3, Instruction::AluR32Rm32);
+            self.instructions.insert(i + 4, Instruction::AluAlImm8);
+            self.instructions.insert(i + 5, Instruction::AluEaxImm32);
+        }
 
-        self.instructions.insert(0x3B, Instruction::CmpR32Rm32);
-        self.instructions.insert(0x3C, Instruction::CmpAlImm8);
-        self.instructions.insert(0x3D, Instruction::CmpEaxImm32);
+        self.instructions.insert(0x06, Instruction::PushSreg);
+        self.instructions.insert(0x07, Instruction::PopSreg);
+        self.instructions.insert(0x0E, Instruction::PushSreg);
+        self.instructions.insert(0x16, Instruction::PushSreg);
+        self.instructions.insert(0x17, Instruction::PopSreg);
+        self.instructions.insert(0x1E, Instruction::PushSreg);
+        self.instructions.insert(0x1F, Instruction::PopSreg);
+        self.instructions.insert(0x27, Instruction::Daa);
+        self.instructions.insert(0x2F, Instruction::Das);
+        self.instructions.insert(0x37, Instruction::Aaa);
+        self.instructions.insert(0x3F, Instruction::Aas);
 
         for i in 0x40..0x48 {
             self.instructions.insert(i, Instruction::IncR32);
@@ -734,26 +5273,54 @@ impl Emulator {
             self.instructions.insert(i, Instruction::PopR32);
         }
 
+        self.instructions.insert(0x60, Instruction::Pusha);
+        self.instructions.insert(0x61, Instruction::Popa);
+        self.instructions.insert(0x63, Instruction::ArplRm16R16);
         self.instructions.insert(0x68, Instruction::PushImm32);
         self.instructions.insert(0x6A, Instruction::PushImm8);
 
-        self.instructions.insert(0x70, Instruction::Jo);
-        self.instructions.insert(0x71, Instruction::Jno);
-        self.instructions.insert(0x72, Instruction::Jc);
-        self.instructions.insert(0x73, Instruction::Jnc);
-        self.instructions.insert(0x74, Instruction::Jz);
-        self.instructions.insert(0x75, Instruction::Jnz);
-        self.instructions.insert(0x78, Instruction::Js);
-        self.instructions.insert(0x79, Instruction::Jns);
-        self.instructions.insert(0x7C, Instruction::Jl);
-        self.instructions.insert(0x7E, Instruction::Jle);
+        for i in 0x70..0x80 {
+            self.instructions.insert(i, Instruction::ShortJcc);
+        }
 
+        self.instructions.insert(0x80, Instruction::Code80);
+        self.instructions.insert(0x81, Instruction::Code81);
+        self.instructions.insert(0x82, Instruction::Code80);
         self.instructions.insert(0x83, Instruction::Code83);
+        self.instructions.insert(0x86, Instruction::XchgRm8R8);
+        self.instructions.insert(0x87, Instruction::XchgRm32R32);
         self.instructions.insert(0x88, Instruction::MovRm8R8);
         self.instructions.insert(0x89, Instruction::MovRm32R32);
         self.instructions.insert(0x8A, Instruction::MovR8Rm8);
         self.instructions.insert(0x8B, Instruction::MovR32Rm32);
 
+        self.instructions.insert(0x8C, Instruction::MovRm16Sreg);
+        self.instructions.insert(0x8D, Instruction::LeaR32M);
+        self.instructions.insert(0x8E, Instruction::MovSregRm16);
+        self.instructions.insert(0x90, Instruction::Nop);
+        for i in 0x91..0x98 {
+            self.instructions.insert(i, Instruction::XchgEaxR32);
+        }
+        self.instructions.insert(0x98, Instruction::Cbw);
+        self.instructions.insert(0x99, Instruction::Cwd);
+        self.instructions.insert(0x9A, Instruction::CallFarPtr);
+        self.instructions.insert(0x9B, Instruction::Fwait);
+        self.instructions.insert(0x9C, Instruction::Pushf);
+        self.instructions.insert(0x9D, Instruction::Popf);
+        self.instructions.insert(0x9E, Instruction::Sahf);
+        self.instructions.insert(0x9F, Instruction::Lahf);
+
+        self.instructions.insert(0xA4, Instruction::Movs);
+        self.instructions.insert(0xA5, Instruction::Movs);
+        self.instructions.insert(0xA6, Instruction::Cmps);
+        self.instructions.insert(0xA7, Instruction::Cmps);
+        self.instructions.insert(0xAA, Instruction::Stos);
+        self.instructions.insert(0xAB, Instruction::Stos);
+        self.instructions.insert(0xAC, Instruction::Lods);
+        self.instructions.insert(0xAD, Instruction::Lods);
+        self.instructions.insert(0xAE, Instruction::Scas);
+        self.instructions.insert(0xAF, Instruction::Scas);
+
         for i in 0xB0..0xB8 {
             self.instructions.insert(i, Instruction::MovR8Imm8);
         }
@@ -761,17 +5328,613 @@ impl Emulator {
             self.instructions.insert(i, Instruction::MovR32Imm32);
         }
 
+        self.instructions.insert(0xC0, Instruction::CodeC0);
+        self.instructions.insert(0xC1, Instruction::CodeC1);
+        self.instructions.insert(0xC2, Instruction::RetImm16);
         self.instructions.insert(0xC3, Instruction::Ret);
         self.instructions.insert(0xC7, Instruction::MovRm32Imm32);
+        self.instructions.insert(0xC8, Instruction::Enter);
         self.instructions.insert(0xC9, Instruction::Leave);
+        self.instructions.insert(0xCA, Instruction::RetfImm16);
+        self.instructions.insert(0xCB, Instruction::Retf);
 
+        self.instructions.insert(0xCC, Instruction::Int3);
         self.instructions.insert(0xCD, Instruction::Swi);
+        self.instructions.insert(0xCE, Instruction::Into);
+        self.instructions.insert(0xCF, Instruction::Iret);
+
+        self.instructions.insert(0xD0, Instruction::CodeD0);
+        self.instructions.insert(0xD1, Instruction::CodeD1);
+        self.instructions.insert(0xD2, Instruction::CodeD2);
+        self.instructions.insert(0xD3, Instruction::CodeD3);
+        self.instructions.insert(0xD4, Instruction::Aam);
+        self.instructions.insert(0xD5, Instruction::Aad);
+        self.instructions.insert(0xD7, Instruction::Xlat);
+        for i in 0xD8..0xE0 {
+            self.instructions.insert(i, Instruction::FpuEscape);
+        }
+        for i in 0xE0..0xE3 {
+            self.instructions.insert(i, Instruction::Loop);
+        }
+        self.instructions.insert(0xE3, Instruction::Jecxz);
 
         self.instructions.insert(0xE8, Instruction::CallRel32);
         self.instructions.insert(0xE9, Instruction::NearJump);
+        self.instructions.insert(0xEA, Instruction::JmpFarPtr);
         self.instructions.insert(0xEB, Instruction::ShortJump);
         self.instructions.insert(0xEC, Instruction::InAlDx);
         self.instructions.insert(0xEE, Instruction::OutDxAl);
+        self.instructions.insert(0xF1, Instruction::Icebp);
+        self.instructions.insert(0xF4, Instruction::Hlt);
+        self.instructions.insert(0xF5, Instruction::Cmc);
+        self.instructions.insert(0xF6, Instruction::CodeF6);
+        self.instructions.insert(0xF7, Instruction::CodeF7);
+        self.instructions.insert(0xF8, Instruction::Clc);
+        self.instructions.insert(0xF9, Instruction::Stc);
+        self.instructions.insert(0xFA, Instruction::Cli);
+        self.instructions.insert(0xFB, Instruction::Sti);
+        self.instructions.insert(0xFC, Instruction::Cld);
+        self.instructions.insert(0xFD, Instruction::Std);
         self.instructions.insert(0xFF, Instruction::CodeFf);
+
+        for i in 0x80..0x90 {
+            self.two_byte_instructions.insert(i, Instruction::NearJcc);
+        }
+        for i in 0x90..0xA0 {
+            self.two_byte_instructions.insert(i, Instruction::Setcc);
+        }
+
+        self.two_byte_instructions
+            .insert(0x00, Instruction::Code0f00);
+        self.two_byte_instructions
+            .insert(0x01, Instruction::Code0f01);
+        self.two_byte_instructions
+            .insert(0x02, Instruction::LarR32Rm16);
+        self.two_byte_instructions
+            .insert(0x03, Instruction::LslR32Rm16);
+        self.two_byte_instructions.insert(0x06, Instruction::Clts);
+        self.two_byte_instructions.insert(0x09, Instruction::Wbinvd);
+        self.two_byte_instructions
+            .insert(0x20, Instruction::MovR32Cr);
+        self.two_byte_instructions
+            .insert(0x21, Instruction::MovR32Dr);
+        self.two_byte_instructions
+            .insert(0x22, Instruction::MovCrR32);
+        self.two_byte_instructions
+            .insert(0x23, Instruction::MovDrR32);
+        self.two_byte_instructions.insert(0x30, Instruction::Wrmsr);
+        self.two_byte_instructions.insert(0x31, Instruction::Rdtsc);
+        self.two_byte_instructions.insert(0x32, Instruction::Rdmsr);
+        self.two_byte_instructions
+            .insert(0xA0, Instruction::PushSreg);
+        self.two_byte_instructions
+            .insert(0xA1, Instruction::PopSreg);
+        self.two_byte_instructions.insert(0xA2, Instruction::Cpuid);
+        self.two_byte_instructions
+            .insert(0xA3, Instruction::BitTestRm32R32);
+        self.two_byte_instructions
+            .insert(0xA4, Instruction::ShldRm32R32Imm8);
+        self.two_byte_instructions
+            .insert(0xA5, Instruction::ShldRm32R32Cl);
+        self.two_byte_instructions
+            .insert(0xA8, Instruction::PushSreg);
+        self.two_byte_instructions
+            .insert(0xA9, Instruction::PopSreg);
+        self.two_byte_instructions
+            .insert(0xAB, Instruction::BitTestRm32R32);
+        self.two_byte_instructions
+            .insert(0xAC, Instruction::ShrdRm32R32Imm8);
+        self.two_byte_instructions
+            .insert(0xAD, Instruction::ShrdRm32R32Cl);
+        self.two_byte_instructions
+            .insert(0xAF, Instruction::ImulR32Rm32);
+        self.two_byte_instructions
+            .insert(0xB0, Instruction::Cmpxchg);
+        self.two_byte_instructions
+            .insert(0xB1, Instruction::Cmpxchg);
+        self.two_byte_instructions
+            .insert(0xB3, Instruction::BitTestRm32R32);
+        self.two_byte_instructions
+            .insert(0xB6, Instruction::MovzxR32Rm8);
+        self.two_byte_instructions
+            .insert(0xB7, Instruction::MovzxR32Rm16);
+        self.two_byte_instructions
+            .insert(0xBA, Instruction::Code0fBa);
+        self.two_byte_instructions
+            .insert(0xBB, Instruction::BitTestRm32R32);
+        self.two_byte_instructions
+            .insert(0xBC, Instruction::BsfR32Rm32);
+        self.two_byte_instructions
+            .insert(0xBD, Instruction::BsrR32Rm32);
+        self.two_byte_instructions
+            .insert(0xBE, Instruction::MovsxR32Rm8);
+        self.two_byte_instructions
+            .insert(0xBF, Instruction::MovsxR32Rm16);
+        self.two_byte_instructions.insert(0xC0, Instruction::Xadd);
+        self.two_byte_instructions.insert(0xC1, Instruction::Xadd);
+        self.two_byte_instructions
+            .insert(0xC7, Instruction::Code0fC7);
+        for i in 0xC8..0xD0 {
+            self.two_byte_instructions.insert(i, Instruction::Bswap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EAX: usize = Register::Eax as usize;
+    const EBX: usize = Register::Ebx as usize;
+    const ESP: usize = Register::Esp as usize;
+
+    const GDT: u32 = 0x1000;
+    const IDT: u32 = 0x2000;
+    const PAGE_DIRECTORY: u32 = 0x3000;
+    const PAGE_TABLE: u32 = 0x4000;
+    /// A HLT that exception handlers and gates point to.
+    const HANDLER: u32 = 0x7d00;
+    const CODE_SELECTOR: u16 = 0x08;
+    const DATA_SELECTOR: u16 = 0x10;
+    /// Flat 4 GiB, 32-bit, DPL 0.
+    const CODE_DESCRIPTOR: u64 = 0x00cf_9a00_0000_ffff;
+    const DATA_DESCRIPTOR: u64 = 0x00cf_9200_0000_ffff;
+    const USER_CODE_SELECTOR: u16 = 0x1b;
+    const USER_DATA_SELECTOR: u16 = 0x23;
+    /// The same segments at DPL 3.
+    const USER_CODE_DESCRIPTOR: u64 = 0x00cf_fa00_0000_ffff;
+    const USER_DATA_DESCRIPTOR: u64 = 0x00cf_f200_0000_ffff;
+    const TSS: u32 = 0x5000;
+    const TSS_SELECTOR: u16 = 0x28;
+
+    /// An 80386 in real-address mode with `code` loaded at 0000:7C00.
+    fn emulator(code: &[u8]) -> Emulator {
+        let mut emu = Emulator::new(CpuModel::I386, 0x10_0000, 0x7c00, 0x7c00);
+        emu.init_instructions();
+        emu.memory[0x7c00..0x7c00 + code.len()].copy_from_slice(code);
+        emu
+    }
+
+    /// Runs one instruction without delivering the exception it raises.
+    fn execute(emu: &mut Emulator) -> Result<(), Exception> {
+        match emu.fetch_instruction()? {
+            Some(instruction) => emu.execute_instruction(&instruction),
+            None => panic!("no instruction at {:08X}", emu.eip),
+        }
+    }
+
+    /// Runs one instruction the way the main loop does, delivering any
+    /// exception it raises. Returns `false` after a triple fault.
+    fn step(emu: &mut Emulator) -> bool {
+        match execute(emu) {
+            Ok(()) => true,
+            Err(exception) => emu.raise_exception(exception),
+        }
+    }
+
+    fn set_memory64(emu: &mut Emulator, address: u32, value: u64) {
+        emu.set_memory32(address, value as u32);
+        emu.set_memory32(address + 4, (value >> 32) as u32);
+    }
+
+    /// Switches to 32-bit protected mode at CPL 0 with flat code and data
+    /// segments in a GDT of 16 entries and an IDT of not-present gates.
+    fn protected_mode(emu: &mut Emulator) {
+        set_memory64(emu, GDT + CODE_SELECTOR as u32, CODE_DESCRIPTOR);
+        set_memory64(emu, GDT + DATA_SELECTOR as u32, DATA_DESCRIPTOR);
+        emu.gdtr = DescriptorTable {
+            base: GDT,
+            limit: 0x7f,
+        };
+        emu.idtr = DescriptorTable {
+            base: IDT,
+            limit: 0x7ff,
+        };
+        emu.cr0 |= CR0_PE;
+        emu.segments[SegmentRegister::Cs as usize] =
+            Descriptor::from_u64(CODE_DESCRIPTOR).to_segment(CODE_SELECTOR);
+        for reg in [
+            SegmentRegister::Es,
+            SegmentRegister::Ss,
+            SegmentRegister::Ds,
+            SegmentRegister::Fs,
+            SegmentRegister::Gs,
+        ] {
+            emu.segments[reg as usize] =
+                Descriptor::from_u64(DATA_DESCRIPTOR).to_segment(DATA_SELECTOR);
+        }
+        emu.memory[HANDLER as usize] = 0xf4;
+    }
+
+    /// Makes the TSS at TSS the busy TSS of the current task, with
+    /// DATA_SELECTOR:`esp0` as its CPL 0 stack.
+    fn set_current_task(emu: &mut Emulator, esp0: u32) {
+        let tss = 0x67 | (TSS as u64) << 16 | (0x80 | SYSTEM_TSS_BUSY as u64) << 40;
+        set_memory64(emu, GDT + TSS_SELECTOR as u32, tss);
+        emu.set_memory32(TSS + 4, esp0);
+        emu.set_memory32(TSS + 8, DATA_SELECTOR as u32);
+        emu.tr = Descriptor::from_u64(tss).to_segment(TSS_SELECTOR);
+    }
+
+    /// Drops to CPL 3 with the user segments loaded. The current TSS at
+    /// TSS gives the CPL 0 stack as DATA_SELECTOR:`esp0`.
+    fn user_mode(emu: &mut Emulator, esp0: u32) {
+        set_memory64(
+            emu,
+            GDT + (USER_CODE_SELECTOR & !3) as u32,
+            USER_CODE_DESCRIPTOR,
+        );
+        set_memory64(
+            emu,
+            GDT + (USER_DATA_SELECTOR & !3) as u32,
+            USER_DATA_DESCRIPTOR,
+        );
+        set_current_task(emu, esp0);
+        emu.segments[SegmentRegister::Cs as usize] =
+            Descriptor::from_u64(USER_CODE_DESCRIPTOR).to_segment(USER_CODE_SELECTOR);
+        for reg in [
+            SegmentRegister::Es,
+            SegmentRegister::Ss,
+            SegmentRegister::Ds,
+            SegmentRegister::Fs,
+            SegmentRegister::Gs,
+        ] {
+            emu.segments[reg as usize] =
+                Descriptor::from_u64(USER_DATA_DESCRIPTOR).to_segment(USER_DATA_SELECTOR);
+        }
+    }
+
+    /// A 32-bit interrupt gate to CODE_SELECTOR:HANDLER.
+    fn set_interrupt_gate(emu: &mut Emulator, vector: u32) {
+        let gate = (HANDLER & 0xffff) as u64
+            | (CODE_SELECTOR as u64) << 16
+            | (0x80 | SYSTEM_INTERRUPT_GATE as u64) << 40
+            | ((HANDLER & 0xffff_0000) as u64) << 32;
+        set_memory64(emu, IDT + vector * 8, gate);
+    }
+
+    /// Identity-maps the first 1 MiB with writable supervisor pages, except
+    /// `absent`, and enables paging.
+    fn enable_paging(emu: &mut Emulator, absent: u32) {
+        emu.set_memory32(PAGE_DIRECTORY, PAGE_TABLE | PTE_PRESENT | PTE_WRITABLE);
+        for page in 0..0x100 {
+            if page != absent >> 12 {
+                let entry = page << 12 | PTE_PRESENT | PTE_WRITABLE;
+                emu.set_memory32(PAGE_TABLE + page * 4, entry);
+            }
+        }
+        emu.cr3 = PAGE_DIRECTORY;
+        emu.cr0 |= CR0_PG;
+    }
+
+    fn arithmetic_flags(emu: &Emulator) -> u32 {
+        emu.get_eflags() & ARITHMETIC_FLAGS
+    }
+
+    #[test]
+    fn ip_wraps_in_a_16_bit_code_segment() {
+        // nop
+        let mut emu = emulator(&[]);
+        emu.memory[0xffff] = 0x90;
+        emu.eip = 0xffff;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0);
+    }
+
+    #[test]
+    fn eip_wraps_in_a_32_bit_code_segment() {
+        // nop at CS:FFFFFFFF, linear 7FFF
+        let mut emu = emulator(&[]);
+        emu.segments[SegmentRegister::Cs as usize] = Segment {
+            base: 0x8000,
+            limit: 0xffff_ffff,
+            big: true,
+            ..emu.segments[SegmentRegister::Cs as usize]
+        };
+        emu.memory[0x7fff] = 0x90;
+        emu.eip = 0xffff_ffff;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0);
+    }
+
+    #[test]
+    fn short_jump_to_itself() {
+        // jmp $
+        let mut emu = emulator(&[0xeb, 0xfe]);
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0x7c00);
+    }
+
+    #[test]
+    fn conditional_jump_to_itself() {
+        // jnz $
+        let mut emu = emulator(&[0x75, 0xfe]);
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0x7c00);
+        emu.eflags |= ZERO_FLAG;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0x7c02);
+    }
+
+    #[test]
+    fn bit_test_reaches_beyond_the_operand() {
+        // bt [bx], ax
+        let mut emu = emulator(&[0x0f, 0xa3, 0x07]);
+        emu.registers[EAX] = 33;
+        emu.registers[EBX] = 0x100;
+        emu.memory[0x104] = 0x02;
+        assert!(step(&mut emu));
+        assert_ne!(emu.get_eflags() & CARRY_FLAG, 0);
+    }
+
+    #[test]
+    fn bit_test_wraps_a_16_bit_address() {
+        // bt [bx], ax
+        let mut emu = emulator(&[0x0f, 0xa3, 0x07]);
+        emu.registers[EAX] = 0xffff;
+        emu.memory[0xffff] = 0x80;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, 0x7c03);
+        assert_ne!(emu.get_eflags() & CARRY_FLAG, 0);
+    }
+
+    #[test]
+    fn add_rm32_r32_carries_out_of_bit_31() {
+        // add eax, ebx
+        let mut emu = emulator(&[0x66, 0x01, 0xd8]);
+        emu.registers[EAX] = 0xffff_ffff;
+        emu.registers[EBX] = 1;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | ZERO_FLAG
+        );
+    }
+
+    #[test]
+    fn add_rm32_r32_sets_overflow() {
+        // add eax, ebx
+        let mut emu = emulator(&[0x66, 0x01, 0xd8]);
+        emu.registers[EAX] = 0x7fff_ffff;
+        emu.registers[EBX] = 1;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0x8000_0000);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            OVERFLOW_FLAG | SIGN_FLAG | AUX_CARRY_FLAG | PARITY_FLAG
+        );
+    }
+
+    #[test]
+    fn add_rm8_r8_carries_and_overflows() {
+        // add al, bl
+        let mut emu = emulator(&[0x00, 0xd8]);
+        emu.registers[EAX] = 0x80;
+        emu.registers[EBX] = 0x80;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | OVERFLOW_FLAG | ZERO_FLAG | PARITY_FLAG
+        );
+    }
+
+    #[test]
+    fn adc_and_sbb_use_the_carry_flag() {
+        // adc al, 0xff; sbb al, 0
+        let mut emu = emulator(&[0x80, 0xd0, 0xff, 0x80, 0xd8, 0x00]);
+        emu.eflags |= CARRY_FLAG;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | ZERO_FLAG
+        );
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0xff);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | SIGN_FLAG
+        );
+    }
+
+    #[test]
+    fn sub_rm16_imm16_sets_overflow() {
+        // sub ax, 1
+        let mut emu = emulator(&[0x81, 0xe8, 0x01, 0x00]);
+        emu.registers[EAX] = 0x8000;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0x7fff);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            OVERFLOW_FLAG | AUX_CARRY_FLAG | PARITY_FLAG
+        );
+    }
+
+    #[test]
+    fn add_rm16_imm8_sign_extends_the_immediate() {
+        // add ax, -1
+        let mut emu = emulator(&[0x83, 0xc0, 0xff]);
+        emu.registers[EAX] = 1;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | ZERO_FLAG
+        );
+    }
+
+    #[test]
+    fn cmp_al_imm8_keeps_the_operand() {
+        // cmp al, 1
+        let mut emu = emulator(&[0x3c, 0x01]);
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(
+            arithmetic_flags(&emu),
+            CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | SIGN_FLAG
+        );
+    }
+
+    #[test]
+    fn xor_clears_carry_and_overflow() {
+        // xor al, al
+        let mut emu = emulator(&[0x30, 0xc0]);
+        emu.eflags |= CARRY_FLAG | OVERFLOW_FLAG;
+        emu.registers[EAX] = 0x5a;
+        assert!(step(&mut emu));
+        assert_eq!(emu.registers[EAX], 0);
+        assert_eq!(arithmetic_flags(&emu), ZERO_FLAG | PARITY_FLAG);
+    }
+
+    #[test]
+    fn fnstsw_ax_stores_the_status_word() {
+        // fld1; fnstsw ax
+        let mut emu = emulator(&[0xd9, 0xe8, 0xdf, 0xe0]);
+        assert!(step(&mut emu));
+        assert!(step(&mut emu));
+        // TOP is 7 after one push.
+        assert_eq!(emu.registers[EAX], 0x3800);
+    }
+
+    #[test]
+    fn escape_raises_nm_with_cr0_em_or_ts() {
+        for flag in [CR0_EM, CR0_TS] {
+            // fld1
+            let mut emu = emulator(&[0xd9, 0xe8]);
+            emu.cr0 |= flag;
+            assert_eq!(execute(&mut emu), Err(Exception::DeviceNotAvailable));
+            assert_eq!(emu.eip, 0x7c00);
+        }
+    }
+
+    #[test]
+    fn wait_raises_nm_only_with_cr0_mp_and_ts() {
+        // wait
+        let mut emu = emulator(&[0x9b, 0x9b]);
+        emu.cr0 |= CR0_TS;
+        assert_eq!(execute(&mut emu), Ok(()));
+        emu.cr0 |= CR0_MP;
+        assert_eq!(execute(&mut emu), Err(Exception::DeviceNotAvailable));
+    }
+
+    #[test]
+    fn page_fault_rolls_back_eip_and_esp() {
+        // pushad, with the fifth push landing on a not-present page
+        let mut emu = emulator(&[0x60]);
+        protected_mode(&mut emu);
+        set_interrupt_gate(&mut emu, 14);
+        enable_paging(&mut emu, 0x50000);
+        emu.registers[ESP] = 0x5_1010;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, HANDLER);
+        assert_eq!(emu.cr2, 0x5_0ffc);
+        // The error code, then EIP, CS and EFLAGS of the PUSHAD, pushed
+        // from ESP as it was before the instruction.
+        assert_eq!(emu.registers[ESP], 0x5_1000);
+        assert_eq!(emu.get_memory32(0x5_1000), PF_WRITE as u32);
+        assert_eq!(emu.get_memory32(0x5_1004), 0x7c00);
+        assert_eq!(emu.get_memory32(0x5_1008), CODE_SELECTOR as u32);
+    }
+
+    #[test]
+    fn fault_delivering_a_fault_is_a_double_fault() {
+        // mov ds, ax: #GP(0x100), and the #GP gate is not present
+        let mut emu = emulator(&[0x8e, 0xd8]);
+        protected_mode(&mut emu);
+        set_interrupt_gate(&mut emu, 8);
+        emu.registers[EAX] = 0x100;
+        emu.registers[ESP] = 0x9000;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, HANDLER);
+        // #DF pushes an error code of 0.
+        assert_eq!(emu.registers[ESP], 0x9000 - 16);
+        assert_eq!(emu.get_memory32(0x9000 - 16), 0);
+        assert_eq!(emu.get_memory32(0x9000 - 12), 0x7c00);
+    }
+
+    #[test]
+    fn fault_delivering_a_double_fault_resets() {
+        // mov ds, ax: #GP(0x100), and neither gate is present
+        let mut emu = emulator(&[0x8e, 0xd8]);
+        protected_mode(&mut emu);
+        emu.registers[EAX] = 0x100;
+        assert!(!step(&mut emu));
+        assert_eq!(emu.cr0 & CR0_PE, 0);
+        assert_eq!(emu.eip, 0x7c00);
+        assert_eq!(emu.memory[0x7c00], 0x8e);
+    }
+
+    #[test]
+    fn far_call_through_a_call_gate_switches_stacks() {
+        // call 0x33:0
+        let mut emu = emulator(&[0x9a, 0x00, 0x00, 0x00, 0x00, 0x33, 0x00]);
+        protected_mode(&mut emu);
+        user_mode(&mut emu, 0x9000);
+        // A DPL 3 call gate to CODE_SELECTOR:HANDLER with no parameters.
+        let gate = (HANDLER & 0xffff) as u64
+            | (CODE_SELECTOR as u64) << 16
+            | (0xe0 | SYSTEM_CALL_GATE as u64) << 40
+            | ((HANDLER & 0xffff_0000) as u64) << 32;
+        set_memory64(&mut emu, GDT + 0x30, gate);
+        emu.registers[ESP] = 0x8000;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, HANDLER);
+        assert_eq!(emu.cpl(), 0);
+        assert_eq!(emu.get_segment_register(SegmentRegister::Ss), DATA_SELECTOR);
+        // The caller's SS:ESP, then its CS:EIP.
+        assert_eq!(emu.registers[ESP], 0x9000 - 16);
+        assert_eq!(emu.get_memory32(0x9000 - 16), 0x7c07);
+        assert_eq!(emu.get_memory32(0x9000 - 12), USER_CODE_SELECTOR as u32);
+        assert_eq!(emu.get_memory32(0x9000 - 8), 0x8000);
+        assert_eq!(emu.get_memory32(0x9000 - 4), USER_DATA_SELECTOR as u32);
+    }
+
+    #[test]
+    fn far_call_through_a_task_gate_switches_tasks() {
+        const NEW_TSS: u32 = 0x6000;
+        const NEW_TSS_SELECTOR: u16 = 0x30;
+        // call 0x38:0
+        let mut emu = emulator(&[0x9a, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00]);
+        protected_mode(&mut emu);
+        set_current_task(&mut emu, 0x9000);
+        let tss = 0x67 | (NEW_TSS as u64) << 16 | (0x80 | SYSTEM_TSS as u64) << 40;
+        set_memory64(&mut emu, GDT + NEW_TSS_SELECTOR as u32, tss);
+        let gate = (NEW_TSS_SELECTOR as u64) << 16 | (0x80 | SYSTEM_TASK_GATE as u64) << 40;
+        set_memory64(&mut emu, GDT + 0x38, gate);
+        emu.set_memory32(NEW_TSS + 0x20, HANDLER);
+        emu.set_memory32(NEW_TSS + 0x24, EFLAGS_RESERVED);
+        emu.set_memory32(NEW_TSS + 0x28, 0x1234_5678);
+        emu.set_memory32(NEW_TSS + 0x38, 0xa000);
+        let selectors = [
+            DATA_SELECTOR,
+            CODE_SELECTOR,
+            DATA_SELECTOR,
+            DATA_SELECTOR,
+            DATA_SELECTOR,
+            DATA_SELECTOR,
+        ];
+        for (i, selector) in selectors.into_iter().enumerate() {
+            emu.set_memory32(NEW_TSS + 0x48 + i as u32 * 4, selector as u32);
+        }
+        emu.registers[EAX] = 0xaaaa_aaaa;
+        assert!(step(&mut emu));
+        assert_eq!(emu.eip, HANDLER);
+        assert_eq!(emu.registers[EAX], 0x1234_5678);
+        assert_eq!(emu.registers[ESP], 0xa000);
+        assert_eq!(emu.tr.selector, NEW_TSS_SELECTOR);
+        assert_ne!(emu.get_eflags() & NESTED_TASK_FLAG, 0);
+        assert_ne!(emu.cr0 & CR0_TS, 0);
+        // The new TSS links back to the old one, which holds the state to
+        // return to.
+        assert_eq!(emu.get_memory32(NEW_TSS), TSS_SELECTOR as u32);
+        assert_eq!(emu.get_memory32(TSS + 0x20), 0x7c07);
+        assert_eq!(emu.get_memory32(TSS + 0x28), 0xaaaa_aaaa);
+        // Both tasks are busy.
+        for selector in [TSS_SELECTOR, NEW_TSS_SELECTOR] {
+            let access = emu.memory[(GDT + selector as u32 + 5) as usize];
+            assert_eq!(access & 0x0f, SYSTEM_TSS_BUSY);
+        }
     }
 }
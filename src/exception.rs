@@ -0,0 +1,159 @@
+use std::fmt;
+
+/// A CPU-detected exception raised while executing an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    DivideError,
+    /// #DB, a fault for instruction breakpoints and general detection or a
+    /// trap for data breakpoints and single steps. DR6 tells them apart.
+    Debug,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    /// #DF, raised when delivering an exception causes another one. Its
+    /// error code is always 0.
+    DoubleFault,
+    FloatingPointError,
+    /// #TS, with the selector of the TSS or of the segment that could not be
+    /// loaded from it as the error code.
+    InvalidTss(u16),
+    /// #NP, with the selector of the segment as the error code.
+    SegmentNotPresent(u16),
+    /// #SS, with a selector or 0 for a limit violation as the error code.
+    StackFault(u16),
+    /// #GP, with a selector or 0 as the error code.
+    GeneralProtection(u16),
+    /// #PF, with the error code built from the PF_* bits. CR2 holds the
+    /// faulting linear address.
+    PageFault(u16),
+    /// #AC, a misaligned access at CPL 3 with CR0.AM and EFLAGS.AC set (486
+    /// and later). Its error code is always 0.
+    AlignmentCheck,
+}
+
+impl Exception {
+    /// The interrupt vector the exception is delivered through.
+    pub fn vector(&self) -> u8 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::Debug => 1,
+            Exception::InvalidOpcode => 6,
+            Exception::DeviceNotAvailable => 7,
+            Exception::DoubleFault => 8,
+            Exception::InvalidTss(_) => 10,
+            Exception::SegmentNotPresent(_) => 11,
+            Exception::StackFault(_) => 12,
+            Exception::GeneralProtection(_) => 13,
+            Exception::PageFault(_) => 14,
+            Exception::FloatingPointError => 16,
+            Exception::AlignmentCheck => 17,
+        }
+    }
+
+    /// The error code pushed for the handler in protected mode, if the
+    /// exception has one.
+    pub fn error_code(&self) -> Option<u16> {
+        match *self {
+            Exception::DoubleFault | Exception::AlignmentCheck => Some(0),
+            Exception::InvalidTss(code)
+            | Exception::SegmentNotPresent(code)
+            | Exception::StackFault(code)
+            | Exception::GeneralProtection(code)
+            | Exception::PageFault(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    fn is_contributory(&self) -> bool {
+        matches!(
+            self,
+            Exception::DivideError
+                | Exception::InvalidTss(_)
+                | Exception::SegmentNotPresent(_)
+                | Exception::StackFault(_)
+                | Exception::GeneralProtection(_)
+        )
+    }
+
+    /// Whether `fault`, raised while delivering this exception, makes a
+    /// double fault: a contributory exception on top of a contributory
+    /// exception or a page fault, or a page fault on top of a page fault.
+    /// Any other combination is handled serially.
+    pub fn is_double_fault(&self, fault: Exception) -> bool {
+        let is_page_fault = matches!(self, Exception::PageFault(_));
+        match fault {
+            Exception::PageFault(_) => is_page_fault,
+            _ => fault.is_contributory() && (self.is_contributory() || is_page_fault),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exception::DivideError => write!(f, "#DE (divide error)"),
+            Exception::Debug => write!(f, "#DB (debug)"),
+            Exception::InvalidOpcode => write!(f, "#UD (invalid opcode)"),
+            Exception::DeviceNotAvailable => write!(f, "#NM (device not available)"),
+            Exception::DoubleFault => write!(f, "#DF (double fault)"),
+            Exception::FloatingPointError => write!(f, "#MF (x87 floating-point error)"),
+            Exception::InvalidTss(code) => write!(f, "#TS({:04X}) (invalid TSS)", code),
+            Exception::SegmentNotPresent(code) => {
+                write!(f, "#NP({:04X}) (segment not present)", code)
+            }
+            Exception::StackFault(code) => write!(f, "#SS({:04X}) (stack fault)", code),
+            Exception::GeneralProtection(code) => {
+                write!(f, "#GP({:04X}) (general protection)", code)
+            }
+            Exception::PageFault(code) => write!(f, "#PF({:04X}) (page fault)", code),
+            Exception::AlignmentCheck => write!(f, "#AC (alignment check)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contributory_on_contributory() {
+        let first = Exception::GeneralProtection(0x10);
+        assert!(first.is_double_fault(Exception::SegmentNotPresent(0x08)));
+        assert!(first.is_double_fault(Exception::DivideError));
+        assert!(Exception::InvalidTss(0x28).is_double_fault(Exception::StackFault(0)));
+    }
+
+    #[test]
+    fn page_fault_combinations() {
+        let page_fault = Exception::PageFault(2);
+        assert!(page_fault.is_double_fault(Exception::PageFault(0)));
+        assert!(page_fault.is_double_fault(Exception::GeneralProtection(0)));
+        // A page fault while delivering a contributory exception is
+        // handled serially.
+        assert!(!Exception::GeneralProtection(0).is_double_fault(page_fault));
+    }
+
+    #[test]
+    fn benign_exceptions_are_serial() {
+        for benign in [
+            Exception::Debug,
+            Exception::InvalidOpcode,
+            Exception::DeviceNotAvailable,
+            Exception::FloatingPointError,
+            Exception::AlignmentCheck,
+        ] {
+            assert!(!benign.is_double_fault(Exception::GeneralProtection(0)));
+            assert!(!benign.is_double_fault(Exception::PageFault(0)));
+            assert!(!Exception::GeneralProtection(0).is_double_fault(benign));
+            assert!(!Exception::PageFault(0).is_double_fault(benign));
+        }
+    }
+
+    #[test]
+    fn vectors_and_error_codes() {
+        assert_eq!(Exception::DoubleFault.vector(), 8);
+        assert_eq!(Exception::DoubleFault.error_code(), Some(0));
+        assert_eq!(Exception::PageFault(6).error_code(), Some(6));
+        assert_eq!(Exception::InvalidOpcode.error_code(), None);
+        assert_eq!(Exception::AlignmentCheck.vector(), 17);
+    }
+}
@@ -0,0 +1,186 @@
+/// The kind of operation that produced a pending set of arithmetic flags.
+#[derive(Clone, Copy)]
+pub enum FlagsOp {
+    Add,
+    Sub,
+    Logic,
+    Inc,
+    Dec,
+}
+
+/// The operands and result of the last flag-setting arithmetic instruction.
+///
+/// Instead of computing CF, PF, AF, ZF, SF and OF after every ADD or CMP, the
+/// emulator records this and derives a flag only when something (a Jcc,
+/// SETcc, ADC, PUSHF, ...) actually reads it. Operands and result are already
+/// truncated to `size` bits.
+#[derive(Clone, Copy)]
+pub struct LazyFlags {
+    pub op: FlagsOp,
+    pub v1: u32,
+    pub v2: u32,
+    pub result: u32,
+    pub size: u32,
+    /// The carry-in of ADC/SBB, or the preserved CF for INC/DEC.
+    pub carry: bool,
+}
+
+impl LazyFlags {
+    fn sign_bit(&self) -> u32 {
+        1 << (self.size - 1)
+    }
+
+    pub fn carry(&self) -> bool {
+        match self.op {
+            FlagsOp::Add if self.carry => self.result <= self.v1,
+            FlagsOp::Add => self.result < self.v1,
+            FlagsOp::Sub if self.carry => self.v1 <= self.v2,
+            FlagsOp::Sub => self.v1 < self.v2,
+            FlagsOp::Logic => false,
+            FlagsOp::Inc | FlagsOp::Dec => self.carry,
+        }
+    }
+
+    pub fn parity(&self) -> bool {
+        (self.result as u8).count_ones().is_multiple_of(2)
+    }
+
+    pub fn aux_carry(&self) -> bool {
+        match self.op {
+            FlagsOp::Logic => false,
+            _ => (self.v1 ^ self.v2 ^ self.result) & 0x10 != 0,
+        }
+    }
+
+    pub fn zero(&self) -> bool {
+        self.result == 0
+    }
+
+    pub fn sign(&self) -> bool {
+        self.result & self.sign_bit() != 0
+    }
+
+    pub fn overflow(&self) -> bool {
+        let (v1, v2, result) = (self.v1, self.v2, self.result);
+        match self.op {
+            FlagsOp::Add | FlagsOp::Inc => (v1 ^ result) & (v2 ^ result) & self.sign_bit() != 0,
+            FlagsOp::Sub | FlagsOp::Dec => (v1 ^ v2) & (v1 ^ result) & self.sign_bit() != 0,
+            FlagsOp::Logic => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pending flags of `op` on `size`-bit operands, truncated as the
+    /// emulator records them. `carry` is the carry-in of ADC/SBB or the CF
+    /// that INC/DEC keep.
+    fn flags(op: FlagsOp, v1: u32, v2: u32, carry: bool, size: u32) -> LazyFlags {
+        let result = match op {
+            FlagsOp::Add => v1.wrapping_add(v2).wrapping_add(carry as u32),
+            FlagsOp::Sub => v1.wrapping_sub(v2).wrapping_sub(carry as u32),
+            FlagsOp::Logic => v1 & v2,
+            FlagsOp::Inc => v1.wrapping_add(1),
+            FlagsOp::Dec => v1.wrapping_sub(1),
+        };
+        let mask = ((1u64 << size) - 1) as u32;
+        LazyFlags {
+            op,
+            v1: v1 & mask,
+            v2: v2 & mask,
+            result: result & mask,
+            size,
+            carry,
+        }
+    }
+
+    #[test]
+    fn adc_carries_out_at_the_top() {
+        let adc = flags(FlagsOp::Add, 0xffff_ffff, 0, true, 32);
+        assert_eq!(adc.result, 0);
+        assert!(adc.carry() && adc.zero() && !adc.overflow());
+        assert!(!flags(FlagsOp::Add, 0xffff_ffff, 0, false, 32).carry());
+        // 0xFFFFFFFF + 0xFFFFFFFF + 1 wraps back to the first operand.
+        let adc = flags(FlagsOp::Add, 0xffff_ffff, 0xffff_ffff, true, 32);
+        assert_eq!(adc.result, 0xffff_ffff);
+        assert!(adc.carry() && !adc.overflow());
+    }
+
+    #[test]
+    fn adc_overflows_on_the_carry_in() {
+        let adc = flags(FlagsOp::Add, 0x7fff_ffff, 0, true, 32);
+        assert!(adc.overflow() && adc.sign() && !adc.carry());
+        assert!(!flags(FlagsOp::Add, 0x7fff_ffff, 0, false, 32).overflow());
+    }
+
+    #[test]
+    fn sbb_borrows_on_the_carry_in() {
+        let sbb = flags(FlagsOp::Sub, 5, 5, true, 32);
+        assert_eq!(sbb.result, 0xffff_ffff);
+        assert!(sbb.carry() && sbb.sign());
+        assert!(!flags(FlagsOp::Sub, 5, 5, false, 32).carry());
+        // 0 - 0xFFFFFFFF - 1 wraps to 0 with a borrow.
+        let sbb = flags(FlagsOp::Sub, 0, 0xffff_ffff, true, 32);
+        assert_eq!(sbb.result, 0);
+        assert!(sbb.carry() && sbb.zero());
+    }
+
+    #[test]
+    fn sbb_overflows_on_the_carry_in() {
+        let sbb = flags(FlagsOp::Sub, 0x8000_0000, 0, true, 32);
+        assert_eq!(sbb.result, 0x7fff_ffff);
+        assert!(sbb.overflow() && !sbb.carry());
+        assert!(!flags(FlagsOp::Sub, 0x8000_0000, 0, false, 32).overflow());
+    }
+
+    #[test]
+    fn inc_and_dec_keep_the_carry() {
+        for carry in [false, true] {
+            let inc = flags(FlagsOp::Inc, 0xff, 1, carry, 8);
+            assert!(inc.zero());
+            assert_eq!(inc.carry(), carry);
+            let dec = flags(FlagsOp::Dec, 0, 1, carry, 8);
+            assert_eq!(dec.result, 0xff);
+            assert_eq!(dec.carry(), carry);
+        }
+        assert!(flags(FlagsOp::Inc, 0x7f, 1, false, 8).overflow());
+        assert!(flags(FlagsOp::Dec, 0x80, 1, false, 8).overflow());
+    }
+
+    #[test]
+    fn aux_carry_is_the_carry_out_of_bit_3() {
+        assert!(flags(FlagsOp::Add, 0x0f, 1, false, 8).aux_carry());
+        assert!(!flags(FlagsOp::Add, 0x0e, 1, false, 8).aux_carry());
+        assert!(flags(FlagsOp::Sub, 0x10, 1, false, 8).aux_carry());
+        assert!(flags(FlagsOp::Inc, 0x0f, 1, false, 8).aux_carry());
+        assert!(flags(FlagsOp::Dec, 0x10, 1, false, 8).aux_carry());
+        assert!(!flags(FlagsOp::Logic, 0x1f, 0x1f, false, 8).aux_carry());
+    }
+
+    #[test]
+    fn parity_counts_the_low_byte_only() {
+        assert!(flags(FlagsOp::Logic, 0x03, 0xff, false, 8).parity());
+        assert!(!flags(FlagsOp::Logic, 0x01, 0xff, false, 8).parity());
+        // Bit 8 is outside the low byte.
+        assert!(flags(FlagsOp::Logic, 0x0103, 0xffff, false, 16).parity());
+        assert!(flags(FlagsOp::Logic, 0, 0, false, 32).parity());
+    }
+
+    #[test]
+    fn widths_set_carry_sign_and_overflow_at_their_top_bit() {
+        for (size, top) in [(8, 0x80), (16, 0x8000), (32, 0x8000_0000)] {
+            let max = ((1u64 << size) - 1) as u32;
+            let add = flags(FlagsOp::Add, max, 1, false, size);
+            assert!(add.carry() && add.zero() && !add.sign() && !add.overflow());
+            let add = flags(FlagsOp::Add, top - 1, 1, false, size);
+            assert!(!add.carry() && add.sign() && add.overflow());
+            let sub = flags(FlagsOp::Sub, 0, 1, false, size);
+            assert_eq!(sub.result, max);
+            assert!(sub.carry() && sub.sign() && !sub.overflow());
+            let sub = flags(FlagsOp::Sub, top, 1, false, size);
+            assert!(!sub.carry() && !sub.sign() && sub.overflow());
+        }
+    }
+}
@@ -0,0 +1,1519 @@
+use std::cmp::Ordering;
+
+/// x87 exception flags, at their positions in the status word and the mask
+/// positions in the control word.
+pub const INVALID: u16 = 1 << 0;
+pub const DENORMAL: u16 = 1 << 1;
+pub const ZERO_DIVIDE: u16 = 1 << 2;
+pub const OVERFLOW: u16 = 1 << 3;
+pub const UNDERFLOW: u16 = 1 << 4;
+pub const PRECISION: u16 = 1 << 5;
+
+const EXPONENT_BIAS: i32 = 16383;
+const EXPONENT_MAX: u16 = 0x7fff;
+const INTEGER_BIT: u64 = 1 << 63;
+const QUIET_BIT: u64 = 1 << 62;
+
+/// Exponent adjustment applied to register results when overflow or
+/// underflow is unmasked.
+const EXPONENT_WRAP: i32 = 24576;
+
+/// Rounding mode selected by the RC field of the control word.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Nearest,
+    Down,
+    Up,
+    Zero,
+}
+
+impl Rounding {
+    pub fn from_control(control: u16) -> Self {
+        match (control >> 10) & 3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::Zero,
+        }
+    }
+}
+
+/// Rounding parameters for one operation and the exceptions it raised.
+pub struct FloatContext {
+    pub rounding: Rounding,
+    /// Significand bits kept by FADD, FSUB, FMUL, FDIV and FSQRT: 24, 53 or 64.
+    pub precision: u32,
+    /// Exception masks from the control word.
+    pub masks: u16,
+    pub flags: u16,
+    /// Whether the last inexact result was rounded away from zero (C1).
+    pub rounded_up: bool,
+}
+
+impl FloatContext {
+    pub fn new(control: u16) -> Self {
+        let precision = match (control >> 8) & 3 {
+            0 => 24,
+            2 => 53,
+            _ => 64,
+        };
+        FloatContext {
+            rounding: Rounding::from_control(control),
+            precision,
+            masks: control & 0x3f,
+            flags: 0,
+            rounded_up: false,
+        }
+    }
+
+    fn raise(&mut self, flags: u16) {
+        self.flags |= flags;
+    }
+
+    fn is_masked(&self, flag: u16) -> bool {
+        self.masks & flag != 0
+    }
+
+    /// Decides whether a discarded fraction rounds the magnitude up.
+    fn rounds_up(&self, sign: bool, is_odd: bool, rest: u128) -> bool {
+        const HALF: u128 = 1 << 127;
+        match self.rounding {
+            Rounding::Nearest => rest > HALF || (rest == HALF && is_odd),
+            Rounding::Down => sign && rest != 0,
+            Rounding::Up => !sign && rest != 0,
+            Rounding::Zero => false,
+        }
+    }
+}
+
+/// Operand classes as reported by FXAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Zero,
+    Denormal,
+    Normal,
+    Infinity,
+    QuietNan,
+    SignalingNan,
+    /// Unnormals, pseudo-NaNs and pseudo-infinities, which the 387 rejects.
+    Unsupported,
+}
+
+/// A binary interchange or extended format that results can be rounded to.
+struct Format {
+    precision: u32,
+    bias: i32,
+    /// The all-ones exponent used for infinities and NaNs.
+    exponent_max: i32,
+}
+
+const SINGLE: Format = Format {
+    precision: 24,
+    bias: 127,
+    exponent_max: 0xff,
+};
+const DOUBLE: Format = Format {
+    precision: 53,
+    bias: 1023,
+    exponent_max: 0x7ff,
+};
+const EXTENDED: Format = Format {
+    precision: 64,
+    bias: EXPONENT_BIAS,
+    exponent_max: EXPONENT_MAX as i32,
+};
+
+/// An 80-bit extended precision value with an explicit integer bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Float80 {
+    pub sign: bool,
+    pub exponent: u16,
+    pub significand: u64,
+}
+
+impl Float80 {
+    pub const ZERO: Float80 = Float80 {
+        sign: false,
+        exponent: 0,
+        significand: 0,
+    };
+    pub const ONE: Float80 = Float80 {
+        sign: false,
+        exponent: EXPONENT_BIAS as u16,
+        significand: INTEGER_BIT,
+    };
+    pub const INFINITY: Float80 = Float80 {
+        sign: false,
+        exponent: EXPONENT_MAX,
+        significand: INTEGER_BIT,
+    };
+    /// The default NaN produced by masked invalid operations.
+    pub const INDEFINITE: Float80 = Float80 {
+        sign: true,
+        exponent: EXPONENT_MAX,
+        significand: INTEGER_BIT | QUIET_BIT,
+    };
+
+    pub fn from_bytes(bytes: &[u8; 10]) -> Self {
+        let mut significand = [0; 8];
+        significand.copy_from_slice(&bytes[..8]);
+        let exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+        Float80 {
+            sign: exponent & 0x8000 != 0,
+            exponent: exponent & EXPONENT_MAX,
+            significand: u64::from_le_bytes(significand),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 10] {
+        let mut bytes = [0; 10];
+        bytes[..8].copy_from_slice(&self.significand.to_le_bytes());
+        let exponent = self.exponent | if self.sign { 0x8000 } else { 0 };
+        bytes[8..].copy_from_slice(&exponent.to_le_bytes());
+        bytes
+    }
+
+    pub fn class(self) -> Class {
+        match self.exponent {
+            0 if self.significand == 0 => Class::Zero,
+            0 => Class::Denormal,
+            EXPONENT_MAX if self.significand & INTEGER_BIT == 0 => Class::Unsupported,
+            EXPONENT_MAX if self.significand << 1 == 0 => Class::Infinity,
+            EXPONENT_MAX if self.significand & QUIET_BIT != 0 => Class::QuietNan,
+            EXPONENT_MAX => Class::SignalingNan,
+            _ if self.significand & INTEGER_BIT == 0 => Class::Unsupported,
+            _ => Class::Normal,
+        }
+    }
+
+    pub fn is_nan(self) -> bool {
+        matches!(self.class(), Class::QuietNan | Class::SignalingNan)
+    }
+
+    fn is_finite(self) -> bool {
+        matches!(self.class(), Class::Zero | Class::Denormal | Class::Normal)
+    }
+
+    fn is_zero(self) -> bool {
+        self.class() == Class::Zero
+    }
+
+    fn is_infinity(self) -> bool {
+        self.class() == Class::Infinity
+    }
+
+    pub fn negate(self) -> Self {
+        Float80 {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        Float80 {
+            sign: false,
+            ..self
+        }
+    }
+
+    fn zero(sign: bool) -> Self {
+        Float80 {
+            sign,
+            ..Float80::ZERO
+        }
+    }
+
+    fn infinity(sign: bool) -> Self {
+        Float80 {
+            sign,
+            ..Float80::INFINITY
+        }
+    }
+
+    fn quiet(self) -> Self {
+        Float80 {
+            significand: self.significand | QUIET_BIT,
+            ..self
+        }
+    }
+
+    fn to_wide(self) -> Wide {
+        let exponent = if self.exponent == 0 {
+            1 - EXPONENT_BIAS
+        } else {
+            self.exponent as i32 - EXPONENT_BIAS
+        };
+        Wide::new(self.sign, exponent, (self.significand as u128) << 64)
+    }
+
+    /// Rounds an internal result to `precision` bits in extended format.
+    fn from_wide(value: Wide, precision: u32, ctx: &mut FloatContext) -> Self {
+        match round(value, precision, &EXTENDED, true, ctx) {
+            Rounded::Zero => Float80::zero(value.sign),
+            Rounded::Infinity => Float80::infinity(value.sign),
+            Rounded::Finite(exponent, significand) => Float80 {
+                sign: value.sign,
+                exponent: exponent as u16,
+                significand: significand << (64 - precision),
+            },
+        }
+    }
+
+    fn from_wide_exact(value: Wide) -> Self {
+        Float80::from_wide(value, 64, &mut FloatContext::new(0x037f))
+    }
+
+    /// Raises #I for unsupported operands and signaling NaNs and picks the
+    /// NaN that propagates, or flags denormal operands and returns `None`.
+    fn check_operands(operands: &[Float80], ctx: &mut FloatContext) -> Option<Float80> {
+        let mut nan: Option<Float80> = None;
+        for &operand in operands {
+            match operand.class() {
+                Class::Unsupported => {
+                    ctx.raise(INVALID);
+                    return Some(Float80::INDEFINITE);
+                }
+                Class::SignalingNan | Class::QuietNan => {
+                    if operand.class() == Class::SignalingNan {
+                        ctx.raise(INVALID);
+                    }
+                    nan = match nan {
+                        Some(other) if other.significand << 1 >= operand.significand << 1 => {
+                            Some(other)
+                        }
+                        _ => Some(operand),
+                    };
+                }
+                Class::Denormal => ctx.raise(DENORMAL),
+                _ => {}
+            }
+        }
+        nan.map(Float80::quiet)
+    }
+
+    fn invalid(ctx: &mut FloatContext) -> Self {
+        ctx.raise(INVALID);
+        Float80::INDEFINITE
+    }
+
+    pub fn from_f32_bits(bits: u32, ctx: &mut FloatContext) -> Self {
+        Float80::from_binary(bits as u64, &SINGLE, 23, ctx)
+    }
+
+    pub fn from_f64_bits(bits: u64, ctx: &mut FloatContext) -> Self {
+        Float80::from_binary(bits, &DOUBLE, 52, ctx)
+    }
+
+    /// Widens a single or double precision value, which is always exact.
+    fn from_binary(bits: u64, format: &Format, fraction_bits: u32, ctx: &mut FloatContext) -> Self {
+        let sign = (bits >> (fraction_bits + exponent_bits(format))) & 1 != 0;
+        let exponent = ((bits >> fraction_bits) as i32) & format.exponent_max;
+        let fraction = bits & ((1 << fraction_bits) - 1);
+        let significand = fraction << (63 - fraction_bits);
+        if exponent == format.exponent_max {
+            let value = Float80 {
+                sign,
+                exponent: EXPONENT_MAX,
+                significand: INTEGER_BIT | significand,
+            };
+            if value.class() == Class::SignalingNan {
+                ctx.raise(INVALID);
+                return value.quiet();
+            }
+            return value;
+        }
+        if exponent == 0 {
+            if fraction == 0 {
+                return Float80::zero(sign);
+            }
+            ctx.raise(DENORMAL);
+            let value = Wide::new(sign, 1 - format.bias, (significand as u128) << 64);
+            return Float80::from_wide_exact(value);
+        }
+        Float80 {
+            sign,
+            exponent: (exponent - format.bias + EXPONENT_BIAS) as u16,
+            significand: INTEGER_BIT | significand,
+        }
+    }
+
+    pub fn to_f32_bits(self, ctx: &mut FloatContext) -> u32 {
+        self.to_binary(&SINGLE, 23, 0xffc0_0000, ctx) as u32
+    }
+
+    pub fn to_f64_bits(self, ctx: &mut FloatContext) -> u64 {
+        self.to_binary(&DOUBLE, 52, 0xfff8_0000_0000_0000, ctx)
+    }
+
+    /// Rounds to a single or double precision encoding.
+    fn to_binary(
+        self,
+        format: &Format,
+        fraction_bits: u32,
+        indefinite: u64,
+        ctx: &mut FloatContext,
+    ) -> u64 {
+        let sign = (self.sign as u64) << (fraction_bits + exponent_bits(format));
+        let exponent_max = format.exponent_max as u64;
+        match self.class() {
+            Class::Unsupported => {
+                ctx.raise(INVALID);
+                indefinite
+            }
+            Class::QuietNan | Class::SignalingNan => {
+                if self.class() == Class::SignalingNan {
+                    ctx.raise(INVALID);
+                }
+                let fraction = (self.quiet().significand << 1) >> (64 - fraction_bits);
+                sign | (exponent_max << fraction_bits) | fraction
+            }
+            Class::Infinity => sign | (exponent_max << fraction_bits),
+            Class::Zero => sign,
+            Class::Denormal | Class::Normal => {
+                let fraction_mask = (1 << fraction_bits) - 1;
+                match round(self.to_wide(), format.precision, format, false, ctx) {
+                    Rounded::Zero => sign,
+                    Rounded::Infinity => sign | (exponent_max << fraction_bits),
+                    Rounded::Finite(exponent, significand) => {
+                        sign | ((exponent as u64) << fraction_bits) | (significand & fraction_mask)
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        Float80::from_wide_exact(Wide::new(value < 0, 127, value.unsigned_abs() as u128))
+    }
+
+    /// Converts to a `bits`-wide signed integer using the current rounding
+    /// mode. Out-of-range values and NaNs give the integer indefinite.
+    pub fn to_integer(self, bits: u32, ctx: &mut FloatContext) -> i64 {
+        let indefinite = i64::MIN >> (64 - bits);
+        match self.class() {
+            Class::Zero => return 0,
+            Class::Denormal | Class::Normal => {}
+            _ => {
+                ctx.raise(INVALID);
+                return indefinite;
+            }
+        }
+        let value = self.to_wide();
+        if value.exponent >= 64 {
+            ctx.raise(INVALID);
+            return indefinite;
+        }
+        let magnitude = round_to_integer(value, ctx);
+        let limit = 1u128 << (bits - 1);
+        if magnitude > limit || (magnitude == limit && !self.sign) {
+            ctx.flags &= !PRECISION;
+            ctx.raise(INVALID);
+            return indefinite;
+        }
+        if self.sign {
+            (magnitude as i64).wrapping_neg()
+        } else {
+            magnitude as i64
+        }
+    }
+
+    /// FRNDINT: rounds to an integral value using the current rounding mode.
+    pub fn round_to_integral(self, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return nan;
+        }
+        if !self.is_finite() || self.is_zero() || self.to_wide().exponent >= 63 {
+            return self;
+        }
+        let magnitude = round_to_integer(self.to_wide(), ctx);
+        if magnitude == 0 {
+            return Float80::zero(self.sign);
+        }
+        Float80::from_wide_exact(Wide::new(self.sign, 127, magnitude))
+    }
+
+    /// Loads an 18-digit packed BCD integer.
+    pub fn from_bcd(bytes: &[u8; 10]) -> Self {
+        let mut value: i64 = 0;
+        for &byte in bytes[..9].iter().rev() {
+            value = value * 100 + ((byte >> 4) as i64) * 10 + (byte & 0x0f) as i64;
+        }
+        let result = Float80::from_i64(value);
+        if bytes[9] & 0x80 != 0 {
+            result.negate()
+        } else {
+            result
+        }
+    }
+
+    /// FBSTP: rounds to an integer and encodes it as packed BCD.
+    pub fn to_bcd(self, ctx: &mut FloatContext) -> [u8; 10] {
+        const INDEFINITE: [u8; 10] = [0, 0, 0, 0, 0, 0, 0, 0xc0, 0xff, 0xff];
+        let value = self.to_integer(64, ctx);
+        if ctx.flags & INVALID != 0 || value.unsigned_abs() >= 1_000_000_000_000_000_000 {
+            ctx.flags &= !PRECISION;
+            ctx.raise(INVALID);
+            return INDEFINITE;
+        }
+        let mut bytes = [0; 10];
+        let mut magnitude = value.unsigned_abs();
+        for byte in bytes[..9].iter_mut() {
+            let low = (magnitude % 10) as u8;
+            magnitude /= 10;
+            let high = (magnitude % 10) as u8;
+            magnitude /= 10;
+            *byte = (high << 4) | low;
+        }
+        if self.sign {
+            bytes[9] = 0x80;
+        }
+        bytes
+    }
+
+    pub fn add(self, other: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, other], ctx) {
+            return nan;
+        }
+        match (self.is_infinity(), other.is_infinity()) {
+            (true, true) if self.sign != other.sign => return Float80::invalid(ctx),
+            (true, _) => return self,
+            (_, true) => return other,
+            _ => {}
+        }
+        let sum = self.to_wide().add(other.to_wide());
+        if sum.is_zero() {
+            // Exact zero sums are negative only when rounding down, unless
+            // both operands were negative.
+            let sign = if self.sign == other.sign {
+                self.sign
+            } else {
+                ctx.rounding == Rounding::Down
+            };
+            return Float80::zero(sign);
+        }
+        Float80::from_wide(sum, ctx.precision, ctx)
+    }
+
+    pub fn sub(self, other: Float80, ctx: &mut FloatContext) -> Self {
+        if other.is_nan() {
+            return self.add(other, ctx);
+        }
+        self.add(other.negate(), ctx)
+    }
+
+    pub fn mul(self, other: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, other], ctx) {
+            return nan;
+        }
+        let sign = self.sign != other.sign;
+        if self.is_infinity() || other.is_infinity() {
+            if self.is_zero() || other.is_zero() {
+                return Float80::invalid(ctx);
+            }
+            return Float80::infinity(sign);
+        }
+        if self.is_zero() || other.is_zero() {
+            return Float80::zero(sign);
+        }
+        Float80::from_wide(self.to_wide().mul(other.to_wide()), ctx.precision, ctx)
+    }
+
+    pub fn div(self, other: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, other], ctx) {
+            return nan;
+        }
+        let sign = self.sign != other.sign;
+        match (self.class(), other.class()) {
+            (Class::Infinity, Class::Infinity) | (Class::Zero, Class::Zero) => {
+                Float80::invalid(ctx)
+            }
+            (Class::Infinity, _) => Float80::infinity(sign),
+            (_, Class::Infinity) | (Class::Zero, _) => Float80::zero(sign),
+            (_, Class::Zero) => {
+                ctx.raise(ZERO_DIVIDE);
+                Float80::infinity(sign)
+            }
+            _ => Float80::from_wide(self.to_wide().div(other.to_wide()), ctx.precision, ctx),
+        }
+    }
+
+    pub fn sqrt(self, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return nan;
+        }
+        if self.is_zero() {
+            return self;
+        }
+        if self.sign {
+            return Float80::invalid(ctx);
+        }
+        if self.is_infinity() {
+            return self;
+        }
+        Float80::from_wide(self.to_wide().sqrt(), ctx.precision, ctx)
+    }
+
+    /// Orders two values for FCOM and FUCOM. `None` means unordered; FCOM
+    /// (`quiet == false`) also raises #I for quiet NaNs.
+    pub fn compare(self, other: Float80, quiet: bool, ctx: &mut FloatContext) -> Option<Ordering> {
+        let mut unordered = false;
+        for operand in [self, other] {
+            match operand.class() {
+                Class::Unsupported | Class::SignalingNan => {
+                    ctx.raise(INVALID);
+                    unordered = true;
+                }
+                Class::QuietNan => {
+                    if !quiet {
+                        ctx.raise(INVALID);
+                    }
+                    unordered = true;
+                }
+                Class::Denormal => ctx.raise(DENORMAL),
+                _ => {}
+            }
+        }
+        if unordered {
+            return None;
+        }
+        if self.is_zero() && other.is_zero() {
+            return Some(Ordering::Equal);
+        }
+        let magnitude = (self.is_infinity(), other.is_infinity());
+        let ordering = match magnitude {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.to_wide().compare_magnitude(&other.to_wide()),
+        };
+        Some(
+            match (self.sign && !self.is_zero(), other.sign && !other.is_zero()) {
+                (false, false) => ordering,
+                (true, true) => ordering.reverse(),
+                (false, true) => Ordering::Greater,
+                (true, false) => Ordering::Less,
+            },
+        )
+    }
+
+    /// FSCALE: multiplies by two raised to the integer part of `scale`.
+    pub fn scale(self, scale: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, scale], ctx) {
+            return nan;
+        }
+        if scale.is_infinity() {
+            return match (scale.sign, self.class()) {
+                (false, Class::Zero) | (true, Class::Infinity) => Float80::invalid(ctx),
+                (false, _) => Float80::infinity(self.sign),
+                (true, _) => Float80::zero(self.sign),
+            };
+        }
+        if !self.is_finite() || self.is_zero() {
+            return self;
+        }
+        let exponent = scale.to_wide().exponent;
+        let shift = if scale.is_zero() || exponent < 0 {
+            0
+        } else if exponent >= 20 {
+            1 << 20
+        } else {
+            (scale.to_wide().significand >> (127 - exponent)) as i32
+        };
+        let mut value = self.to_wide();
+        value.exponent += if scale.sign { -shift } else { shift };
+        Float80::from_wide(value, 64, ctx)
+    }
+
+    /// FXTRACT: splits into the unbiased exponent and a significand in [1, 2).
+    pub fn extract(self, ctx: &mut FloatContext) -> (Float80, Float80) {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return (nan, nan);
+        }
+        match self.class() {
+            Class::Zero => {
+                ctx.raise(ZERO_DIVIDE);
+                (Float80::infinity(true), self)
+            }
+            Class::Infinity => (Float80::INFINITY, self),
+            _ => {
+                let mut value = self.to_wide();
+                let exponent = Float80::from_i64(value.exponent as i64);
+                value.exponent = 0;
+                (exponent, Float80::from_wide_exact(value))
+            }
+        }
+    }
+
+    /// FPREM and FPREM1. Returns the remainder, the low three quotient bits
+    /// and whether the reduction is complete; an exponent difference of 64
+    /// or more is reduced only partially.
+    pub fn remainder(
+        self,
+        divisor: Float80,
+        nearest: bool,
+        ctx: &mut FloatContext,
+    ) -> (Float80, u8, bool) {
+        if let Some(nan) = Float80::check_operands(&[self, divisor], ctx) {
+            return (nan, 0, true);
+        }
+        if self.is_infinity() || divisor.is_zero() {
+            return (Float80::invalid(ctx), 0, true);
+        }
+        if divisor.is_infinity() || self.is_zero() {
+            return (self, 0, true);
+        }
+        let dividend = self.to_wide();
+        let divisor = divisor.to_wide();
+        let a = dividend.significand >> 64;
+        let b = divisor.significand >> 64;
+        let difference = dividend.exponent - divisor.exponent;
+        let mut sign = self.sign;
+        if difference < 0 {
+            // |self| < |divisor|: only FPREM1 may need one subtraction.
+            if nearest && difference == -1 && a > b {
+                let remainder = Wide::new(!sign, dividend.exponent + 64, 2 * b - a);
+                return (Float80::from_wide_exact(remainder), 1, true);
+            }
+            return (self, 0, true);
+        }
+        let complete = difference < 64;
+        let steps = if complete {
+            difference + 1
+        } else {
+            32 + difference % 32
+        };
+        let mut remainder = a;
+        let mut quotient: u64 = 0;
+        for step in 0..steps {
+            quotient <<= 1;
+            if remainder >= b {
+                remainder -= b;
+                quotient |= 1;
+            }
+            if step + 1 < steps {
+                remainder <<= 1;
+            }
+        }
+        if complete && nearest && (2 * remainder > b || (2 * remainder == b && quotient & 1 != 0)) {
+            remainder = b - remainder;
+            quotient = quotient.wrapping_add(1);
+            sign = !sign;
+        }
+        let exponent = dividend.exponent - (steps - 1);
+        if remainder == 0 {
+            return (Float80::zero(self.sign), quotient as u8 & 7, complete);
+        }
+        let remainder = Wide::new(sign, exponent + 64, remainder);
+        (
+            Float80::from_wide(remainder, 64, ctx),
+            quotient as u8 & 7,
+            complete,
+        )
+    }
+
+    /// F2XM1: 2^x - 1, defined for -1 <= x <= 1.
+    pub fn exp2_minus_one(self, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return nan;
+        }
+        match self.class() {
+            Class::Zero => return self,
+            Class::Infinity if self.sign => return Float80::ONE.negate(),
+            Class::Infinity => return self,
+            _ => {}
+        }
+        let value = self.to_wide();
+        if value.compare_magnitude(&Wide::new(false, 0, 1 << 127)) == Ordering::Greater {
+            // Outside -1..+1 the result is undefined; the operand is left as is.
+            ctx.raise(PRECISION);
+            return self;
+        }
+        Float80::from_wide(exp_minus_one(value.mul(LN_2)), 64, ctx)
+    }
+
+    /// FYL2X: y * log2(x), with `self` as y.
+    pub fn mul_log2(self, x: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, x], ctx) {
+            return nan;
+        }
+        if x.sign && !x.is_zero() {
+            return Float80::invalid(ctx);
+        }
+        if x.is_zero() {
+            if self.is_zero() {
+                return Float80::invalid(ctx);
+            }
+            if !self.is_infinity() {
+                ctx.raise(ZERO_DIVIDE);
+            }
+            return Float80::infinity(!self.sign);
+        }
+        let log = if x.is_infinity() {
+            None
+        } else {
+            Some(log2(x.to_wide()))
+        };
+        match log {
+            None if self.is_zero() => Float80::invalid(ctx),
+            None => Float80::infinity(self.sign),
+            Some(log) if self.is_infinity() => {
+                if log.is_zero() {
+                    Float80::invalid(ctx)
+                } else {
+                    Float80::infinity(self.sign != log.sign)
+                }
+            }
+            Some(log) if self.is_zero() || log.is_zero() => Float80::zero(self.sign != log.sign),
+            Some(log) => Float80::from_wide(self.to_wide().mul(log), 64, ctx),
+        }
+    }
+
+    /// FYL2XP1: y * log2(x + 1), with `self` as y.
+    pub fn mul_log2_plus_one(self, x: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, x], ctx) {
+            return nan;
+        }
+        if x.is_infinity() || (self.is_infinity() && x.is_zero()) {
+            return Float80::invalid(ctx);
+        }
+        if x.is_zero() {
+            return Float80::zero(self.sign != x.sign);
+        }
+        let x = x.to_wide();
+        let one = Wide::new(false, 0, 1 << 127);
+        let log = if x.exponent < -1 {
+            // ln(1 + x) = 2 atanh(x / (2 + x)) keeps full precision near zero.
+            let two = Wide::new(false, 1, 1 << 127);
+            atanh(x.div(two.add(x))).mul(LOG2_E).scale(1)
+        } else {
+            let sum = one.add(x);
+            if sum.sign || sum.is_zero() {
+                // Undefined below -1; like F2XM1 the operand is left as is.
+                ctx.raise(PRECISION);
+                return Float80::from_wide(x, 64, ctx);
+            }
+            log2(sum)
+        };
+        if self.is_infinity() {
+            return Float80::infinity(self.sign != log.sign);
+        }
+        if self.is_zero() {
+            return Float80::zero(self.sign != log.sign);
+        }
+        Float80::from_wide(self.to_wide().mul(log), 64, ctx)
+    }
+
+    /// Reduces the argument of FSIN, FCOS, FSINCOS and FPTAN modulo pi/2.
+    /// Returns `None` when |x| >= 2^63, which the FPU reports through C2.
+    fn reduce_trigonometric(self) -> Option<(Wide, u64)> {
+        let value = self.to_wide();
+        if value.exponent >= 63 {
+            return None;
+        }
+        let mut ctx = FloatContext::new(0x037f);
+        let quotient = round_to_integer(value.div(PI_2).abs(), &mut ctx);
+        let multiple = Wide::new(false, 127, quotient).mul(PI_2);
+        let reduced = value.abs().sub(multiple);
+        let (reduced, quadrant) = if value.sign {
+            (reduced.negate(), (4 - (quotient % 4) as u64) % 4)
+        } else {
+            (reduced, (quotient % 4) as u64)
+        };
+        Some((reduced, quadrant))
+    }
+
+    /// Computes sine and cosine together. `None` means the operand is out of
+    /// range and must be left unchanged.
+    pub fn sin_cos(self, ctx: &mut FloatContext) -> Option<(Float80, Float80)> {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return Some((nan, nan));
+        }
+        match self.class() {
+            Class::Infinity => {
+                let nan = Float80::invalid(ctx);
+                return Some((nan, nan));
+            }
+            Class::Zero => return Some((self, Float80::ONE)),
+            _ => {}
+        }
+        let (reduced, quadrant) = self.reduce_trigonometric()?;
+        let (sin, cos) = (sin(reduced), cos(reduced));
+        let (sin, cos) = match quadrant {
+            0 => (sin, cos),
+            1 => (cos, sin.negate()),
+            2 => (sin.negate(), cos.negate()),
+            _ => (cos.negate(), sin),
+        };
+        Some((
+            Float80::from_wide(sin, 64, ctx),
+            Float80::from_wide(cos, 64, ctx),
+        ))
+    }
+
+    pub fn tan(self, ctx: &mut FloatContext) -> Option<Float80> {
+        if let Some(nan) = Float80::check_operands(&[self], ctx) {
+            return Some(nan);
+        }
+        match self.class() {
+            Class::Infinity => return Some(Float80::invalid(ctx)),
+            Class::Zero => return Some(self),
+            _ => {}
+        }
+        let (reduced, quadrant) = self.reduce_trigonometric()?;
+        let (sin, cos) = (sin(reduced), cos(reduced));
+        let tan = if quadrant % 2 == 0 {
+            sin.div(cos)
+        } else {
+            cos.div(sin).negate()
+        };
+        Some(Float80::from_wide(tan, 64, ctx))
+    }
+
+    /// FPATAN: the angle of the point (x, y), with `self` as y.
+    pub fn atan2(self, x: Float80, ctx: &mut FloatContext) -> Self {
+        if let Some(nan) = Float80::check_operands(&[self, x], ctx) {
+            return nan;
+        }
+        let angle = match (self.class(), x.class()) {
+            (Class::Zero, _) if x.sign => PI,
+            (Class::Zero, _) => return self,
+            (Class::Infinity, Class::Infinity) if x.sign => PI.mul(Wide::new(false, -1, 3 << 126)),
+            (Class::Infinity, Class::Infinity) => PI.scale(-2),
+            (Class::Infinity, _) | (_, Class::Zero) => PI_2,
+            (_, Class::Infinity) if x.sign => PI,
+            (_, Class::Infinity) => return Float80::zero(self.sign),
+            _ => {
+                let angle = atan(self.to_wide().abs().div(x.to_wide().abs()));
+                if x.sign {
+                    PI.sub(angle)
+                } else {
+                    angle
+                }
+            }
+        };
+        let angle = if self.sign { angle.negate() } else { angle };
+        Float80::from_wide(angle, 64, ctx)
+    }
+
+    /// The constants loaded by FLDPI, FLDL2T, FLDL2E, FLDLG2 and FLDLN2,
+    /// selected by the low three bits of D9 E8-EE, rounded per RC.
+    pub fn constant(index: u8, rounding: Rounding) -> Self {
+        let value = match index {
+            0 => return Float80::ONE,
+            1 => LOG2_10,
+            2 => LOG2_E,
+            3 => PI,
+            4 => LOG10_2,
+            5 => LN_2,
+            _ => return Float80::ZERO,
+        };
+        let mut ctx = FloatContext::new(0x037f);
+        ctx.rounding = rounding;
+        Float80::from_wide(value, 64, &mut ctx)
+    }
+}
+
+fn exponent_bits(format: &Format) -> u32 {
+    (format.exponent_max as u32).count_ones()
+}
+
+enum Rounded {
+    Zero,
+    Infinity,
+    /// Biased exponent and a `precision`-bit significand whose integer bit
+    /// is clear for denormals (which then use exponent 0).
+    Finite(i32, u64),
+}
+
+/// Rounds `value` to `precision` significand bits in `format`, raising
+/// precision, overflow and underflow. With `wrap`, unmasked overflow and
+/// underflow rebias the exponent as the x87 does for register results.
+fn round(
+    value: Wide,
+    precision: u32,
+    format: &Format,
+    wrap: bool,
+    ctx: &mut FloatContext,
+) -> Rounded {
+    if value.is_zero() {
+        return Rounded::Zero;
+    }
+    let mut exponent = value.exponent + format.bias;
+    let mut significand = value.significand;
+    let tiny = exponent < 1;
+    if tiny {
+        if wrap && !ctx.is_masked(UNDERFLOW) {
+            ctx.raise(UNDERFLOW);
+            exponent += EXPONENT_WRAP;
+        } else {
+            significand = shift_right_sticky(significand, (1 - exponent) as u32);
+            exponent = 1;
+        }
+    }
+    let mut kept = significand >> (128 - precision);
+    let rest = significand << precision;
+    if rest != 0 {
+        ctx.raise(PRECISION);
+        if tiny && ctx.is_masked(UNDERFLOW) {
+            ctx.raise(UNDERFLOW);
+        }
+        ctx.rounded_up = ctx.rounds_up(value.sign, kept & 1 != 0, rest);
+        if ctx.rounded_up {
+            kept += 1;
+            if kept == 1 << precision {
+                kept >>= 1;
+                exponent += 1;
+            }
+        }
+    }
+    if tiny && !ctx.is_masked(UNDERFLOW) {
+        ctx.raise(UNDERFLOW);
+    }
+    if exponent >= format.exponent_max {
+        ctx.raise(OVERFLOW);
+        if wrap && !ctx.is_masked(OVERFLOW) {
+            return Rounded::Finite(exponent - EXPONENT_WRAP, kept as u64);
+        }
+        ctx.raise(PRECISION);
+        let to_infinity = match ctx.rounding {
+            Rounding::Nearest => true,
+            Rounding::Down => value.sign,
+            Rounding::Up => !value.sign,
+            Rounding::Zero => false,
+        };
+        ctx.rounded_up = to_infinity;
+        if to_infinity {
+            return Rounded::Infinity;
+        }
+        return Rounded::Finite(format.exponent_max - 1, u64::MAX >> (64 - precision));
+    }
+    if kept == 0 {
+        return Rounded::Zero;
+    }
+    if kept >> (precision - 1) == 0 {
+        exponent = 0;
+    }
+    Rounded::Finite(exponent, kept as u64)
+}
+
+/// Rounds a finite value with exponent below 64 to an integer magnitude,
+/// raising precision when a fraction is discarded.
+fn round_to_integer(value: Wide, ctx: &mut FloatContext) -> u128 {
+    if value.is_zero() {
+        return 0;
+    }
+    let (integer, rest) = match value.exponent {
+        e if e >= 0 => (
+            value.significand >> (127 - e),
+            value.significand.checked_shl((e + 1) as u32).unwrap_or(0),
+        ),
+        -1 => (0, value.significand),
+        e => (0, shift_right_sticky(value.significand, (-1 - e) as u32)),
+    };
+    if rest == 0 {
+        return integer;
+    }
+    ctx.raise(PRECISION);
+    ctx.rounded_up = ctx.rounds_up(value.sign, integer & 1 != 0, rest);
+    integer + ctx.rounded_up as u128
+}
+
+fn shift_right_sticky(value: u128, shift: u32) -> u128 {
+    match shift {
+        0 => value,
+        1..=127 => (value >> shift) | ((value << (128 - shift) != 0) as u128),
+        _ => (value != 0) as u128,
+    }
+}
+
+/// 128 x 128 -> 256-bit product as (high, low).
+fn mul_u128(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a1, a0) = (a >> 64, a & MASK);
+    let (b1, b0) = (b >> 64, b & MASK);
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+    let middle = (p00 >> 64) + (p01 & MASK) + (p10 & MASK);
+    let low = (p00 & MASK) | (middle << 64);
+    let high = p11 + (p01 >> 64) + (p10 >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// An unrounded intermediate with a 128-bit significand, used so that every
+/// x87 result is rounded exactly once. The value is
+/// `significand * 2^(exponent - 127)`; the significand is normalized (bit 127
+/// set) unless it is zero, and bits shifted out are kept as a sticky bit 0.
+#[derive(Clone, Copy)]
+struct Wide {
+    sign: bool,
+    exponent: i32,
+    significand: u128,
+}
+
+const PI: Wide = Wide {
+    sign: false,
+    exponent: 1,
+    significand: 0xc90f_daa2_2168_c234_c4c6_628b_80dc_1cd1,
+};
+const PI_2: Wide = Wide {
+    sign: false,
+    exponent: 0,
+    significand: 0xc90f_daa2_2168_c234_c4c6_628b_80dc_1cd1,
+};
+const LN_2: Wide = Wide {
+    sign: false,
+    exponent: -1,
+    significand: 0xb172_17f7_d1cf_79ab_c9e3_b398_03f2_f6af,
+};
+const LOG2_E: Wide = Wide {
+    sign: false,
+    exponent: 0,
+    significand: 0xb8aa_3b29_5c17_f0bb_be87_fed0_691d_3e88,
+};
+const LOG2_10: Wide = Wide {
+    sign: false,
+    exponent: 1,
+    significand: 0xd49a_784b_cd1b_8afe_492b_f6ff_4daf_db4c,
+};
+const LOG10_2: Wide = Wide {
+    sign: false,
+    exponent: -2,
+    significand: 0x9a20_9a84_fbcf_f798_8f89_59ac_0b7c_9178,
+};
+const SQRT_2: u128 = 0xb504_f333_f9de_6484_597d_89b3_754a_be9f;
+
+impl Wide {
+    const ZERO: Wide = Wide {
+        sign: false,
+        exponent: 0,
+        significand: 0,
+    };
+
+    fn new(sign: bool, exponent: i32, significand: u128) -> Self {
+        if significand == 0 {
+            return Wide { sign, ..Wide::ZERO };
+        }
+        let shift = significand.leading_zeros();
+        Wide {
+            sign,
+            exponent: exponent - shift as i32,
+            significand: significand << shift,
+        }
+    }
+
+    fn from_integer(value: u128) -> Self {
+        Wide::new(false, 127, value)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.significand == 0
+    }
+
+    fn negate(self) -> Self {
+        Wide {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    fn abs(self) -> Self {
+        Wide {
+            sign: false,
+            ..self
+        }
+    }
+
+    fn scale(self, shift: i32) -> Self {
+        Wide {
+            exponent: self.exponent + shift,
+            ..self
+        }
+    }
+
+    fn compare_magnitude(&self, other: &Wide) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => (self.exponent, self.significand).cmp(&(other.exponent, other.significand)),
+        }
+    }
+
+    fn add(self, other: Wide) -> Wide {
+        if self.is_zero() {
+            return other;
+        }
+        if other.is_zero() {
+            return self;
+        }
+        let (large, small) = if self.compare_magnitude(&other) == Ordering::Less {
+            (other, self)
+        } else {
+            (self, other)
+        };
+        // One spare bit at the top absorbs the carry of an addition.
+        let shift = (large.exponent - small.exponent) as u32;
+        let a = shift_right_sticky(large.significand, 1);
+        let b = shift_right_sticky(small.significand, shift.saturating_add(1));
+        let significand = if large.sign == small.sign {
+            a + b
+        } else {
+            a - b
+        };
+        Wide::new(large.sign, large.exponent + 1, significand)
+    }
+
+    fn sub(self, other: Wide) -> Wide {
+        self.add(other.negate())
+    }
+
+    fn mul(self, other: Wide) -> Wide {
+        let sign = self.sign != other.sign;
+        if self.is_zero() || other.is_zero() {
+            return Wide { sign, ..Wide::ZERO };
+        }
+        let (high, low) = mul_u128(self.significand, other.significand);
+        Wide::new(
+            sign,
+            self.exponent + other.exponent + 1,
+            high | (low != 0) as u128,
+        )
+    }
+
+    fn div(self, other: Wide) -> Wide {
+        let sign = self.sign != other.sign;
+        if self.is_zero() {
+            return Wide { sign, ..Wide::ZERO };
+        }
+        let divisor = other.significand;
+        let mut remainder = self.significand;
+        let mut carry = false;
+        let mut quotient: u128 = 0;
+        for _ in 0..128 {
+            quotient <<= 1;
+            if carry || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient |= 1;
+            }
+            carry = remainder >> 127 != 0;
+            remainder <<= 1;
+        }
+        let sticky = (remainder != 0 || carry) as u128;
+        Wide::new(sign, self.exponent - other.exponent, quotient | sticky)
+    }
+
+    /// Square root of a positive value, computed digit by digit.
+    fn sqrt(self) -> Wide {
+        // Scale the significand so that the remaining power of two is even
+        // and the root has about 123 bits.
+        let exponent = self.exponent - 127;
+        let shift = 116 + (exponent - 116).rem_euclid(2) as u32;
+        let bit = |position: u32| -> u128 {
+            if position >= shift && position - shift < 128 {
+                (self.significand >> (position - shift)) & 1
+            } else {
+                0
+            }
+        };
+        let mut root: u128 = 0;
+        let mut remainder: u128 = 0;
+        for pair in (0..123).rev() {
+            remainder = (remainder << 2) | (bit(2 * pair + 1) << 1) | bit(2 * pair);
+            let trial = (root << 2) | 1;
+            root <<= 1;
+            if remainder >= trial {
+                remainder -= trial;
+                root |= 1;
+            }
+        }
+        let sticky = (remainder != 0) as u128;
+        Wide::new(false, (exponent - shift as i32) / 2 + 127, root | sticky)
+    }
+}
+
+/// Sums a power series until the terms no longer affect the result. `next`
+/// turns term k-1 into term k.
+fn sum_series(first: Wide, mut next: impl FnMut(Wide, u32) -> Wide) -> Wide {
+    let mut sum = first;
+    let mut term = first;
+    for k in 1.. {
+        term = next(term, k);
+        // The last, negligible term still lands in the sticky bit so the
+        // truncated tail is reported as inexact.
+        sum = sum.add(term);
+        if term.is_zero() || term.exponent < sum.exponent - 130 {
+            break;
+        }
+    }
+    sum
+}
+
+/// e^x - 1 for small |x|.
+fn exp_minus_one(x: Wide) -> Wide {
+    sum_series(x, |term, k| {
+        term.mul(x).div(Wide::from_integer(k as u128 + 1))
+    })
+}
+
+/// atanh(x) = x + x^3/3 + x^5/5 + ... for small |x|.
+fn atanh(x: Wide) -> Wide {
+    let square = x.mul(x);
+    let mut power = x;
+    sum_series(x, |_, k| {
+        power = power.mul(square);
+        power.div(Wide::from_integer(2 * k as u128 + 1))
+    })
+}
+
+/// log2 of a positive value.
+fn log2(x: Wide) -> Wide {
+    // x = m * 2^e with m in [sqrt(2)/2, sqrt(2)), ln m = 2 atanh((m-1)/(m+1)).
+    let mut exponent = x.exponent;
+    let mut m = Wide { exponent: 0, ..x };
+    if m.significand > SQRT_2 {
+        m.exponent = -1;
+        exponent += 1;
+    }
+    let one = Wide::new(false, 0, 1 << 127);
+    let ln = atanh(m.sub(one).div(m.add(one))).scale(1);
+    let integer = Wide::new(exponent < 0, 127, exponent.unsigned_abs() as u128);
+    integer.add(ln.mul(LOG2_E))
+}
+
+fn sin(x: Wide) -> Wide {
+    let square = x.mul(x);
+    sum_series(x, |term, k| {
+        let divisor = (2 * k as u128) * (2 * k as u128 + 1);
+        term.mul(square).div(Wide::from_integer(divisor)).negate()
+    })
+}
+
+fn cos(x: Wide) -> Wide {
+    let square = x.mul(x);
+    let one = Wide::new(false, 0, 1 << 127);
+    sum_series(one, |term, k| {
+        let divisor = (2 * k as u128 - 1) * (2 * k as u128);
+        term.mul(square).div(Wide::from_integer(divisor)).negate()
+    })
+}
+
+/// atan of a non-negative value.
+fn atan(x: Wide) -> Wide {
+    let one = Wide::new(false, 0, 1 << 127);
+    if x.compare_magnitude(&one) == Ordering::Greater {
+        return PI_2.sub(atan(one.div(x)));
+    }
+    // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))); three halvings bring x
+    // below tan(pi/32) so the series converges quickly.
+    let mut x = x;
+    for _ in 0..3 {
+        if x.is_zero() {
+            break;
+        }
+        x = x.div(one.add(one.add(x.mul(x)).sqrt()));
+    }
+    let square = x.mul(x);
+    let mut power = x;
+    let series = sum_series(x, |_, k| {
+        power = power.mul(square).negate();
+        power.div(Wide::from_integer(2 * k as u128 + 1))
+    });
+    series.scale(3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Control words with every exception masked: 64-bit precision with
+    /// each rounding mode, and 24-bit precision rounding to nearest.
+    const NEAREST: u16 = 0x037f;
+    const DOWN: u16 = 0x077f;
+    const UP: u16 = 0x0b7f;
+    const ZERO: u16 = 0x0f7f;
+    const SINGLE_NEAREST: u16 = 0x007f;
+
+    fn value(x: f64) -> Float80 {
+        Float80::from_f64_bits(x.to_bits(), &mut FloatContext::new(NEAREST))
+    }
+
+    fn to_f64(x: Float80) -> f64 {
+        f64::from_bits(x.to_f64_bits(&mut FloatContext::new(NEAREST)))
+    }
+
+    /// 1 + 2^-64 lies halfway between 1 and the next value up.
+    fn one_plus_half_ulp() -> Float80 {
+        Float80 {
+            exponent: EXPONENT_BIAS as u16 - 64,
+            ..Float80::ONE
+        }
+    }
+
+    #[test]
+    fn add_exact() {
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(to_f64(value(1.0).add(value(2.0), &mut ctx)), 3.0);
+        assert_eq!(to_f64(value(1.5).sub(value(4.0), &mut ctx)), -2.5);
+        assert_eq!(ctx.flags, 0);
+    }
+
+    #[test]
+    fn add_rounds_ties_to_even() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let sum = Float80::ONE.add(one_plus_half_ulp(), &mut ctx);
+        assert_eq!(sum, Float80::ONE);
+        assert_eq!(ctx.flags, PRECISION);
+        assert!(!ctx.rounded_up);
+    }
+
+    #[test]
+    fn add_rounding_modes() {
+        for (control, significand) in [
+            (DOWN, INTEGER_BIT),
+            (ZERO, INTEGER_BIT),
+            (UP, INTEGER_BIT | 1),
+        ] {
+            let mut ctx = FloatContext::new(control);
+            let sum = Float80::ONE.add(one_plus_half_ulp(), &mut ctx);
+            assert_eq!(sum.significand, significand);
+            assert_eq!(ctx.flags, PRECISION);
+            assert_eq!(ctx.rounded_up, control == UP);
+        }
+    }
+
+    #[test]
+    fn exact_zero_sum_sign() {
+        let mut ctx = FloatContext::new(NEAREST);
+        assert!(!value(1.0).add(value(-1.0), &mut ctx).sign);
+        let mut ctx = FloatContext::new(DOWN);
+        assert!(value(1.0).add(value(-1.0), &mut ctx).sign);
+        assert!(value(-0.0).add(value(-0.0), &mut ctx).sign);
+    }
+
+    #[test]
+    fn add_opposite_infinities_is_invalid() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let sum = Float80::INFINITY.add(Float80::INFINITY.negate(), &mut ctx);
+        assert_eq!(sum, Float80::INDEFINITE);
+        assert_eq!(ctx.flags, INVALID);
+    }
+
+    #[test]
+    fn signaling_nan_is_quieted() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let snan = Float80 {
+            significand: INTEGER_BIT | 1,
+            ..Float80::INFINITY
+        };
+        let sum = snan.add(Float80::ONE, &mut ctx);
+        assert_eq!(sum.class(), Class::QuietNan);
+        assert_eq!(sum.significand, INTEGER_BIT | QUIET_BIT | 1);
+        assert_eq!(ctx.flags, INVALID);
+    }
+
+    #[test]
+    fn mul() {
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(to_f64(value(3.0).mul(value(-0.5), &mut ctx)), -1.5);
+        assert_eq!(ctx.flags, 0);
+        let product = Float80::ZERO.mul(Float80::INFINITY, &mut ctx);
+        assert_eq!(product, Float80::INDEFINITE);
+        assert_eq!(ctx.flags, INVALID);
+    }
+
+    #[test]
+    fn mul_overflow() {
+        let largest = Float80 {
+            sign: false,
+            exponent: EXPONENT_MAX - 1,
+            significand: u64::MAX,
+        };
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(largest.mul(value(2.0), &mut ctx), Float80::INFINITY);
+        assert_eq!(ctx.flags, OVERFLOW | PRECISION);
+        let mut ctx = FloatContext::new(ZERO);
+        assert_eq!(largest.mul(value(2.0), &mut ctx), largest);
+        assert_eq!(ctx.flags, OVERFLOW | PRECISION);
+    }
+
+    #[test]
+    fn mul_underflow_and_denormal() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let tiny = Float80 {
+            sign: false,
+            exponent: 1,
+            significand: INTEGER_BIT | 1,
+        };
+        let product = tiny.mul(value(0.5), &mut ctx);
+        assert_eq!(product.class(), Class::Denormal);
+        assert_eq!(ctx.flags, UNDERFLOW | PRECISION);
+        let mut ctx = FloatContext::new(NEAREST);
+        product.mul(Float80::ONE, &mut ctx);
+        assert_eq!(ctx.flags, DENORMAL);
+    }
+
+    #[test]
+    fn div() {
+        let mut ctx = FloatContext::new(SINGLE_NEAREST);
+        let third = Float80::ONE.div(value(3.0), &mut ctx);
+        assert_eq!(third.to_f32_bits(&mut ctx), 0x3eaa_aaab);
+        assert_eq!(third.significand, 0xaaaa_ab00_0000_0000);
+        assert_eq!(ctx.flags, PRECISION);
+        assert!(ctx.rounded_up);
+    }
+
+    #[test]
+    fn div_by_zero() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let quotient = value(-1.0).div(Float80::ZERO, &mut ctx);
+        assert_eq!(quotient, Float80::INFINITY.negate());
+        assert_eq!(ctx.flags, ZERO_DIVIDE);
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(
+            Float80::ZERO.div(Float80::ZERO, &mut ctx),
+            Float80::INDEFINITE
+        );
+        assert_eq!(ctx.flags, INVALID);
+    }
+
+    #[test]
+    fn sqrt() {
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(to_f64(value(4.0).sqrt(&mut ctx)), 2.0);
+        assert_eq!(ctx.flags, 0);
+        let root = value(2.0).sqrt(&mut ctx);
+        assert_eq!(root.significand, 0xb504_f333_f9de_6484);
+        assert_eq!(ctx.flags, PRECISION);
+        let mut ctx = FloatContext::new(UP);
+        assert_eq!(value(2.0).sqrt(&mut ctx).significand, 0xb504_f333_f9de_6485);
+        let mut ctx = FloatContext::new(NEAREST);
+        assert_eq!(value(-1.0).sqrt(&mut ctx), Float80::INDEFINITE);
+        assert_eq!(ctx.flags, INVALID);
+        assert!(value(-0.0).sqrt(&mut ctx).sign);
+    }
+
+    #[test]
+    fn remainder() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let (remainder, quotient, complete) = value(7.0).remainder(value(3.0), false, &mut ctx);
+        assert_eq!((to_f64(remainder), quotient, complete), (1.0, 2, true));
+        // FPREM1 rounds the quotient to nearest: 8 = 3 * 3 - 1.
+        let (remainder, quotient, complete) = value(8.0).remainder(value(3.0), true, &mut ctx);
+        assert_eq!((to_f64(remainder), quotient, complete), (-1.0, 3, true));
+        let (remainder, quotient, _) = value(8.0).remainder(value(3.0), false, &mut ctx);
+        assert_eq!((to_f64(remainder), quotient), (2.0, 2));
+        assert_eq!(ctx.flags, 0);
+    }
+
+    #[test]
+    fn remainder_partial_and_invalid() {
+        let mut ctx = FloatContext::new(NEAREST);
+        let (_, _, complete) = value(1e30).remainder(value(3.0), false, &mut ctx);
+        assert!(!complete);
+        let (remainder, _, _) = value(5.0).remainder(Float80::ZERO, false, &mut ctx);
+        assert_eq!(remainder, Float80::INDEFINITE);
+        assert_eq!(ctx.flags, INVALID);
+    }
+}
@@ -0,0 +1,842 @@
+use crate::float80::{
+    Class, Float80, FloatContext, Rounding, DENORMAL, INVALID, OVERFLOW, PRECISION, UNDERFLOW,
+    ZERO_DIVIDE,
+};
+use std::cmp::Ordering;
+
+const STACK_FAULT: u16 = 1 << 6;
+const ERROR_SUMMARY: u16 = 1 << 7;
+const C0: u16 = 1 << 8;
+const C1: u16 = 1 << 9;
+const C2: u16 = 1 << 10;
+const TOP_SHIFT: u16 = 11;
+const TOP_MASK: u16 = 7 << TOP_SHIFT;
+const C3: u16 = 1 << 14;
+const BUSY: u16 = 1 << 15;
+const EXCEPTION_FLAGS: u16 = 0x3f;
+
+/// Control word after FNINIT: all exceptions masked, 64-bit precision,
+/// round to nearest.
+const CONTROL_DEFAULT: u16 = 0x037f;
+
+const TAG_VALID: u16 = 0;
+const TAG_ZERO: u16 = 1;
+const TAG_SPECIAL: u16 = 2;
+const TAG_EMPTY: u16 = 3;
+
+/// Memory operand formats of x87 loads and stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Single,
+    Double,
+    Extended,
+    Int16,
+    Int32,
+    Int64,
+    Bcd,
+}
+
+impl Format {
+    pub fn size(self) -> usize {
+        match self {
+            Format::Int16 => 2,
+            Format::Single | Format::Int32 => 4,
+            Format::Double | Format::Int64 => 8,
+            Format::Extended | Format::Bcd => 10,
+        }
+    }
+}
+
+/// The second operand of an arithmetic or compare instruction.
+#[derive(Clone, Copy)]
+pub enum Operand {
+    Register(usize),
+    Memory(Format, [u8; 10]),
+}
+
+/// Operations selected by the reg field of D8, DA, DC and DE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Mul,
+    Com,
+    Comp,
+    Sub,
+    Subr,
+    Div,
+    Divr,
+}
+
+impl ArithOp {
+    pub fn from_u8(value: u8) -> Self {
+        match value & 7 {
+            0 => ArithOp::Add,
+            1 => ArithOp::Mul,
+            2 => ArithOp::Com,
+            3 => ArithOp::Comp,
+            4 => ArithOp::Sub,
+            5 => ArithOp::Subr,
+            6 => ArithOp::Div,
+            _ => ArithOp::Divr,
+        }
+    }
+
+    /// The same operation with its operands swapped (FSUB <-> FSUBR).
+    pub fn reversed(self) -> Self {
+        match self {
+            ArithOp::Sub => ArithOp::Subr,
+            ArithOp::Subr => ArithOp::Sub,
+            ArithOp::Div => ArithOp::Divr,
+            ArithOp::Divr => ArithOp::Div,
+            op => op,
+        }
+    }
+}
+
+/// The x87 register stack, control, status and tag words, and the pointers
+/// to the last non-control instruction and its memory operand.
+pub struct Fpu {
+    registers: [Float80; 8],
+    control: u16,
+    /// Status word with the TOP field kept separately in `top`.
+    status: u16,
+    top: usize,
+    tags: u16,
+    instruction_pointer: u32,
+    instruction_selector: u16,
+    opcode: u16,
+    operand_pointer: u32,
+    operand_selector: u16,
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        let mut fpu = Fpu {
+            registers: [Float80::ZERO; 8],
+            control: 0,
+            status: 0,
+            top: 0,
+            tags: 0,
+            instruction_pointer: 0,
+            instruction_selector: 0,
+            opcode: 0,
+            operand_pointer: 0,
+            operand_selector: 0,
+        };
+        fpu.init();
+        fpu
+    }
+}
+
+impl Fpu {
+    /// FNINIT. Register contents are kept but all tags become empty.
+    pub fn init(&mut self) {
+        self.control = CONTROL_DEFAULT;
+        self.status = 0;
+        self.top = 0;
+        self.tags = 0xffff;
+        self.instruction_pointer = 0;
+        self.instruction_selector = 0;
+        self.opcode = 0;
+        self.operand_pointer = 0;
+        self.operand_selector = 0;
+    }
+
+    /// FNCLEX.
+    pub fn clear_exceptions(&mut self) {
+        self.status &= !(EXCEPTION_FLAGS | STACK_FAULT | ERROR_SUMMARY | BUSY);
+    }
+
+    pub fn control_word(&self) -> u16 {
+        self.control
+    }
+
+    /// FLDCW. Unmasking an exception that is already flagged makes it
+    /// pending.
+    pub fn set_control_word(&mut self, value: u16) {
+        self.control = value | 0x0040;
+        self.update_error_summary();
+    }
+
+    pub fn status_word(&self) -> u16 {
+        (self.status & !TOP_MASK) | ((self.top as u16) << TOP_SHIFT)
+    }
+
+    fn set_status_word(&mut self, value: u16) {
+        self.status = value & !TOP_MASK;
+        self.top = ((value & TOP_MASK) >> TOP_SHIFT) as usize;
+    }
+
+    /// Whether an unmasked exception is waiting to be reported as #MF by the
+    /// next waiting FPU instruction.
+    pub fn has_pending_exception(&self) -> bool {
+        self.status & ERROR_SUMMARY != 0
+    }
+
+    fn update_error_summary(&mut self) {
+        if self.status & !self.control & EXCEPTION_FLAGS != 0 {
+            self.status |= ERROR_SUMMARY | BUSY;
+        } else {
+            self.status &= !(ERROR_SUMMARY | BUSY);
+        }
+    }
+
+    /// Records the last non-control instruction for FSTENV and FSAVE.
+    /// `opcode` holds the low three bits of the escape byte and the ModRM
+    /// byte.
+    pub fn record_instruction(&mut self, pointer: u32, selector: u16, opcode: u16) {
+        self.instruction_pointer = pointer;
+        self.instruction_selector = selector;
+        self.opcode = opcode & 0x07ff;
+    }
+
+    pub fn record_operand(&mut self, pointer: u32, selector: u16) {
+        self.operand_pointer = pointer;
+        self.operand_selector = selector;
+    }
+
+    fn physical(&self, index: usize) -> usize {
+        (self.top + index) & 7
+    }
+
+    fn tag(&self, physical: usize) -> u16 {
+        (self.tags >> (physical * 2)) & 3
+    }
+
+    fn set_tag(&mut self, physical: usize, tag: u16) {
+        self.tags = (self.tags & !(3 << (physical * 2))) | (tag << (physical * 2));
+    }
+
+    fn is_empty(&self, index: usize) -> bool {
+        self.tag(self.physical(index)) == TAG_EMPTY
+    }
+
+    fn set_st(&mut self, index: usize, value: Float80) {
+        let physical = self.physical(index);
+        let tag = match value.class() {
+            Class::Normal => TAG_VALID,
+            Class::Zero => TAG_ZERO,
+            _ => TAG_SPECIAL,
+        };
+        self.registers[physical] = value;
+        self.set_tag(physical, tag);
+    }
+
+    fn push(&mut self, value: Float80) {
+        self.top = (self.top + 7) & 7;
+        self.set_st(0, value);
+    }
+
+    fn pop(&mut self) {
+        self.set_tag(self.physical(0), TAG_EMPTY);
+        self.top = (self.top + 1) & 7;
+    }
+
+    fn set_condition(&mut self, c3: bool, c2: bool, c1: bool, c0: bool) {
+        self.status &= !(C0 | C1 | C2 | C3);
+        for (flag, is_set) in [(C0, c0), (C1, c1), (C2, c2), (C3, c3)] {
+            if is_set {
+                self.status |= flag;
+            }
+        }
+    }
+
+    fn context(&self) -> FloatContext {
+        FloatContext::new(self.control)
+    }
+
+    /// Folds the exceptions of one operation into the status word. Returns
+    /// false when an unmasked exception suppresses the result.
+    /// An invalid operation takes precedence over a denormal operand.
+    fn commit(&mut self, ctx: &FloatContext, suppressing: u16) -> bool {
+        let flags = if ctx.flags & INVALID != 0 {
+            ctx.flags & !DENORMAL
+        } else {
+            ctx.flags
+        };
+        self.status &= !C1;
+        if flags & PRECISION != 0 && ctx.rounded_up {
+            self.status |= C1;
+        }
+        self.status |= flags;
+        self.update_error_summary();
+        flags & !ctx.masks & suppressing == 0
+    }
+
+    /// Stack underflow: reading an empty register. Returns the masked
+    /// response, or `None` if #IS is unmasked.
+    fn stack_underflow(&mut self) -> Option<Float80> {
+        self.status = (self.status & !C1) | INVALID | STACK_FAULT;
+        self.update_error_summary();
+        if self.control & INVALID != 0 {
+            Some(Float80::INDEFINITE)
+        } else {
+            None
+        }
+    }
+
+    /// Stack overflow: pushing onto a full stack. Pushes the indefinite if
+    /// #IS is masked.
+    fn stack_overflow(&mut self) {
+        self.status |= C1 | INVALID | STACK_FAULT;
+        self.update_error_summary();
+        if self.control & INVALID != 0 {
+            self.push(Float80::INDEFINITE);
+        }
+    }
+
+    fn read(&mut self, index: usize) -> Option<Float80> {
+        if self.is_empty(index) {
+            return self.stack_underflow();
+        }
+        Some(self.registers[self.physical(index)])
+    }
+
+    fn can_push(&mut self) -> bool {
+        if self.is_empty(7) {
+            true
+        } else {
+            self.stack_overflow();
+            false
+        }
+    }
+
+    fn decode(format: Format, bytes: &[u8; 10], ctx: &mut FloatContext) -> Float80 {
+        let mut low = [0; 8];
+        low.copy_from_slice(&bytes[..8]);
+        let low = u64::from_le_bytes(low);
+        match format {
+            Format::Single => Float80::from_f32_bits(low as u32, ctx),
+            Format::Double => Float80::from_f64_bits(low, ctx),
+            Format::Extended => Float80::from_bytes(bytes),
+            Format::Int16 => Float80::from_i64(low as i16 as i64),
+            Format::Int32 => Float80::from_i64(low as i32 as i64),
+            Format::Int64 => Float80::from_i64(low as i64),
+            Format::Bcd => Float80::from_bcd(bytes),
+        }
+    }
+
+    fn encode(value: Float80, format: Format, ctx: &mut FloatContext) -> [u8; 10] {
+        let low = match format {
+            Format::Extended => return value.to_bytes(),
+            Format::Bcd => return value.to_bcd(ctx),
+            Format::Single => value.to_f32_bits(ctx) as u64,
+            Format::Double => value.to_f64_bits(ctx),
+            Format::Int16 => value.to_integer(16, ctx) as u64,
+            Format::Int32 => value.to_integer(32, ctx) as u64,
+            Format::Int64 => value.to_integer(64, ctx) as u64,
+        };
+        let mut bytes = [0; 10];
+        bytes[..8].copy_from_slice(&low.to_le_bytes());
+        bytes
+    }
+
+    fn fetch(&mut self, operand: Operand, ctx: &mut FloatContext) -> Option<Float80> {
+        match operand {
+            Operand::Register(index) => self.read(index),
+            Operand::Memory(format, bytes) => Some(Fpu::decode(format, &bytes, ctx)),
+        }
+    }
+
+    fn load(&mut self, value: Float80) {
+        if self.can_push() {
+            self.push(value);
+        }
+    }
+
+    /// FLD, FILD and FBLD.
+    pub fn load_operand(&mut self, operand: Operand) {
+        let mut ctx = self.context();
+        let Some(value) = self.fetch(operand, &mut ctx) else {
+            return;
+        };
+        if self.commit(&ctx, INVALID | DENORMAL) {
+            self.load(value);
+        }
+    }
+
+    /// FLD1, FLDL2T, FLDL2E, FLDPI, FLDLG2, FLDLN2 and FLDZ.
+    pub fn load_constant(&mut self, index: u8) {
+        self.status &= !C1;
+        self.load(Float80::constant(
+            index,
+            Rounding::from_control(self.control),
+        ));
+    }
+
+    /// FST, FIST and FBSTP to memory. Returns the bytes to write, or `None`
+    /// when an unmasked exception suppresses the store.
+    pub fn store_memory(&mut self, format: Format, pop: bool) -> Option<[u8; 10]> {
+        let value = self.read(0)?;
+        let mut ctx = self.context();
+        let bytes = Fpu::encode(value, format, &mut ctx);
+        if !self.commit(&ctx, INVALID | DENORMAL | OVERFLOW | UNDERFLOW) {
+            return None;
+        }
+        if pop {
+            self.pop();
+        }
+        Some(bytes)
+    }
+
+    /// FST and FSTP to a register.
+    pub fn store_register(&mut self, index: usize, pop: bool) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        self.status &= !C1;
+        self.set_st(index, value);
+        if pop {
+            self.pop();
+        }
+    }
+
+    /// FXCH.
+    pub fn exchange(&mut self, index: usize) {
+        let (Some(a), Some(b)) = (self.read(0), self.read(index)) else {
+            return;
+        };
+        self.status &= !C1;
+        self.set_st(0, b);
+        self.set_st(index, a);
+    }
+
+    /// FFREE.
+    pub fn free(&mut self, index: usize) {
+        let physical = self.physical(index);
+        self.set_tag(physical, TAG_EMPTY);
+    }
+
+    /// FINCSTP and FDECSTP rotate the stack without changing tags.
+    pub fn rotate(&mut self, increment: bool) {
+        self.status &= !C1;
+        self.top = if increment {
+            (self.top + 1) & 7
+        } else {
+            (self.top + 7) & 7
+        };
+    }
+
+    /// FADD, FMUL, FSUB(R) and FDIV(R): `ST(dest) = ST(dest) op source`.
+    pub fn arithmetic(&mut self, op: ArithOp, dest: usize, source: Operand, pop: bool) {
+        if matches!(op, ArithOp::Com | ArithOp::Comp) {
+            let pops = if op == ArithOp::Comp { 1 } else { 0 };
+            self.compare(source, false, pops);
+            return;
+        }
+        let mut ctx = self.context();
+        let Some(value) = self.read(dest) else {
+            return;
+        };
+        let Some(operand) = self.fetch(source, &mut ctx) else {
+            return;
+        };
+        let result = match op {
+            ArithOp::Add => value.add(operand, &mut ctx),
+            ArithOp::Mul => value.mul(operand, &mut ctx),
+            ArithOp::Sub => value.sub(operand, &mut ctx),
+            ArithOp::Subr => operand.sub(value, &mut ctx),
+            ArithOp::Div => value.div(operand, &mut ctx),
+            _ => operand.div(value, &mut ctx),
+        };
+        if self.commit(&ctx, INVALID | DENORMAL | ZERO_DIVIDE) {
+            self.set_st(dest, result);
+            if pop {
+                self.pop();
+            }
+        }
+    }
+
+    /// FCOM, FUCOM and their popping forms: compares ST(0) with `source`
+    /// and sets C3, C2 and C0.
+    pub fn compare(&mut self, source: Operand, quiet: bool, pops: u32) {
+        let mut ctx = self.context();
+        let ordering = match (self.read(0), self.fetch(source, &mut ctx)) {
+            (Some(value), Some(operand)) => value.compare(operand, quiet, &mut ctx),
+            _ if self.control & INVALID != 0 => None,
+            _ => return,
+        };
+        if !self.commit(&ctx, INVALID | DENORMAL) {
+            return;
+        }
+        match ordering {
+            Some(Ordering::Greater) => self.set_condition(false, false, false, false),
+            Some(Ordering::Less) => self.set_condition(false, false, false, true),
+            Some(Ordering::Equal) => self.set_condition(true, false, false, false),
+            None => self.set_condition(true, true, false, true),
+        }
+        for _ in 0..pops {
+            self.pop();
+        }
+    }
+
+    /// FTST: compares ST(0) with +0.0.
+    pub fn test(&mut self) {
+        let zero = Operand::Memory(Format::Extended, Float80::ZERO.to_bytes());
+        self.compare(zero, false, 0);
+    }
+
+    /// FXAM: classifies ST(0) into C3, C2 and C0, with its sign in C1.
+    pub fn examine(&mut self) {
+        let value = self.registers[self.physical(0)];
+        let (c3, c2, c0) = if self.is_empty(0) {
+            (true, false, true)
+        } else {
+            match value.class() {
+                Class::Unsupported => (false, false, false),
+                Class::QuietNan | Class::SignalingNan => (false, false, true),
+                Class::Normal => (false, true, false),
+                Class::Infinity => (false, true, true),
+                Class::Zero => (true, false, false),
+                Class::Denormal => (true, true, false),
+            }
+        };
+        self.set_condition(c3, c2, value.sign, c0);
+    }
+
+    /// Replaces ST(0) with `f(ST(0))`.
+    fn unary(&mut self, f: impl FnOnce(Float80, &mut FloatContext) -> Float80) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        let mut ctx = self.context();
+        let result = f(value, &mut ctx);
+        if self.commit(&ctx, INVALID | DENORMAL | ZERO_DIVIDE) {
+            self.set_st(0, result);
+        }
+    }
+
+    /// Stores `f(ST(0), ST(1))` into ST(1) and pops, as FYL2X, FYL2XP1 and
+    /// FPATAN do.
+    fn binary_pop(&mut self, f: impl FnOnce(Float80, Float80, &mut FloatContext) -> Float80) {
+        let (Some(st0), Some(st1)) = (self.read(0), self.read(1)) else {
+            return;
+        };
+        let mut ctx = self.context();
+        let result = f(st0, st1, &mut ctx);
+        if self.commit(&ctx, INVALID | DENORMAL | ZERO_DIVIDE) {
+            self.set_st(1, result);
+            self.pop();
+        }
+    }
+
+    pub fn change_sign(&mut self) {
+        self.unary(|value, _| value.negate());
+    }
+
+    pub fn absolute(&mut self) {
+        self.unary(|value, _| value.abs());
+    }
+
+    pub fn square_root(&mut self) {
+        self.unary(|value, ctx| value.sqrt(ctx));
+    }
+
+    pub fn round_to_integer(&mut self) {
+        self.unary(|value, ctx| value.round_to_integral(ctx));
+    }
+
+    pub fn exp2_minus_one(&mut self) {
+        self.unary(|value, ctx| value.exp2_minus_one(ctx));
+    }
+
+    pub fn scale(&mut self) {
+        let Some(scale) = self.read(1) else {
+            return;
+        };
+        self.unary(|value, ctx| value.scale(scale, ctx));
+    }
+
+    pub fn y_log2_x(&mut self) {
+        self.binary_pop(|x, y, ctx| y.mul_log2(x, ctx));
+    }
+
+    pub fn y_log2_x_plus_one(&mut self) {
+        self.binary_pop(|x, y, ctx| y.mul_log2_plus_one(x, ctx));
+    }
+
+    pub fn partial_arctangent(&mut self) {
+        self.binary_pop(|x, y, ctx| y.atan2(x, ctx));
+    }
+
+    /// FPREM and FPREM1. C2 is set while the reduction is incomplete;
+    /// otherwise C0, C3 and C1 receive the low quotient bits.
+    pub fn partial_remainder(&mut self, nearest: bool) {
+        let (Some(value), Some(divisor)) = (self.read(0), self.read(1)) else {
+            return;
+        };
+        let mut ctx = self.context();
+        let (result, quotient, complete) = value.remainder(divisor, nearest, &mut ctx);
+        if self.commit(&ctx, INVALID | DENORMAL) {
+            self.set_st(0, result);
+            if complete {
+                let bit = |n: u8| quotient & (1 << n) != 0;
+                self.set_condition(bit(1), false, bit(0), bit(2));
+            } else {
+                self.set_condition(false, true, false, false);
+            }
+        }
+    }
+
+    /// FXTRACT: replaces ST(0) with its exponent and pushes its significand.
+    pub fn extract(&mut self) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        if !self.can_push() {
+            return;
+        }
+        let mut ctx = self.context();
+        let (exponent, significand) = value.extract(&mut ctx);
+        if self.commit(&ctx, INVALID | DENORMAL | ZERO_DIVIDE) {
+            self.set_st(0, exponent);
+            self.push(significand);
+        }
+    }
+
+    /// FSIN (`cosine == false`) and FCOS. An operand of 2^63 or more is left
+    /// unchanged with C2 set.
+    pub fn sine_or_cosine(&mut self, cosine: bool) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        let mut ctx = self.context();
+        let Some((sin, cos)) = value.sin_cos(&mut ctx) else {
+            self.set_condition(false, true, false, false);
+            return;
+        };
+        if self.commit(&ctx, INVALID | DENORMAL) {
+            self.status &= !C2;
+            self.set_st(0, if cosine { cos } else { sin });
+        }
+    }
+
+    /// FSINCOS: replaces ST(0) with its sine and pushes its cosine.
+    pub fn sine_cosine(&mut self) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        if !self.can_push() {
+            return;
+        }
+        let mut ctx = self.context();
+        let Some((sin, cos)) = value.sin_cos(&mut ctx) else {
+            self.set_condition(false, true, false, false);
+            return;
+        };
+        if self.commit(&ctx, INVALID | DENORMAL) {
+            self.status &= !C2;
+            self.set_st(0, sin);
+            self.push(cos);
+        }
+    }
+
+    /// FPTAN: replaces ST(0) with its tangent and pushes 1.0.
+    pub fn partial_tangent(&mut self) {
+        let Some(value) = self.read(0) else {
+            return;
+        };
+        if !self.can_push() {
+            return;
+        }
+        let mut ctx = self.context();
+        let Some(tan) = value.tan(&mut ctx) else {
+            self.set_condition(false, true, false, false);
+            return;
+        };
+        if self.commit(&ctx, INVALID | DENORMAL) {
+            self.status &= !C2;
+            self.set_st(0, tan);
+            self.push(Float80::ONE);
+        }
+    }
+
+    /// The environment image stored by FNSTENV: 14 bytes with a 16-bit
+    /// operand size and 28 bytes with a 32-bit one. Real mode stores the
+    /// instruction and operand pointers as linear addresses together with
+    /// the opcode instead of as selector:offset pairs.
+    pub fn save_environment(&self, size: u32, real_mode: bool) -> Vec<u8> {
+        let words: Vec<u32> = if real_mode {
+            let linear =
+                |selector: u16, pointer: u32| ((selector as u32) << 4).wrapping_add(pointer);
+            let ip = linear(self.instruction_selector, self.instruction_pointer);
+            let op = linear(self.operand_selector, self.operand_pointer);
+            let opcode = self.opcode as u32;
+            if size == 16 {
+                vec![
+                    ip & 0xffff,
+                    ((ip >> 4) & 0xf000) | opcode,
+                    op & 0xffff,
+                    (op >> 4) & 0xf000,
+                ]
+            } else {
+                vec![
+                    ip & 0xffff,
+                    ((ip >> 4) & 0x0fff_f000) | opcode,
+                    op & 0xffff,
+                    (op >> 4) & 0x0fff_f000,
+                ]
+            }
+        } else {
+            vec![
+                self.instruction_pointer,
+                self.instruction_selector as u32 | ((self.opcode as u32) << 16),
+                self.operand_pointer,
+                self.operand_selector as u32,
+            ]
+        };
+        let header = [
+            self.control as u32 | 0xffff_0000,
+            self.status_word() as u32 | 0xffff_0000,
+            self.tags as u32 | 0xffff_0000,
+        ];
+        let mut bytes = Vec::new();
+        for word in header.into_iter().chain(words) {
+            if size == 16 {
+                bytes.extend_from_slice(&(word as u16).to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// FNSTENV also masks all exceptions after storing the image.
+    pub fn store_environment(&mut self, size: u32, real_mode: bool) -> Vec<u8> {
+        let bytes = self.save_environment(size, real_mode);
+        self.control |= EXCEPTION_FLAGS;
+        self.update_error_summary();
+        bytes
+    }
+
+    /// FLDENV.
+    pub fn load_environment(&mut self, bytes: &[u8], size: u32, real_mode: bool) {
+        let unit = (size / 8) as usize;
+        let word = |index: usize| -> u32 {
+            let start = index * unit;
+            let mut value = [0; 4];
+            value[..unit].copy_from_slice(&bytes[start..start + unit]);
+            u32::from_le_bytes(value)
+        };
+        self.control = word(0) as u16 | 0x0040;
+        self.set_status_word(word(1) as u16);
+        self.tags = word(2) as u16;
+        if real_mode {
+            let high = if size == 16 { 0xf000 } else { 0x0fff_f000 };
+            self.instruction_pointer = (word(3) & 0xffff) | ((word(4) & high) << 4);
+            self.opcode = (word(4) & 0x07ff) as u16;
+            self.operand_pointer = (word(5) & 0xffff) | ((word(6) & high) << 4);
+            self.instruction_selector = 0;
+            self.operand_selector = 0;
+        } else if size == 16 {
+            self.instruction_pointer = word(3);
+            self.instruction_selector = word(4) as u16;
+            self.operand_pointer = word(5);
+            self.operand_selector = word(6) as u16;
+        } else {
+            self.instruction_pointer = word(3);
+            self.instruction_selector = word(4) as u16;
+            self.opcode = ((word(4) >> 16) & 0x07ff) as u16;
+            self.operand_pointer = word(5);
+            self.operand_selector = word(6) as u16;
+        }
+        self.update_error_summary();
+    }
+
+    /// FNSAVE: the environment followed by ST(0)-ST(7), then FNINIT.
+    pub fn save(&mut self, size: u32, real_mode: bool) -> Vec<u8> {
+        let mut bytes = self.save_environment(size, real_mode);
+        for index in 0..8 {
+            let value = self.registers[self.physical(index)];
+            bytes.extend_from_slice(&value.to_bytes());
+        }
+        self.init();
+        bytes
+    }
+
+    /// FRSTOR.
+    pub fn restore(&mut self, bytes: &[u8], size: u32, real_mode: bool) {
+        self.load_environment(bytes, size, real_mode);
+        let start = if size == 16 { 14 } else { 28 };
+        for index in 0..8 {
+            let mut value = [0; 10];
+            value.copy_from_slice(&bytes[start + index * 10..start + index * 10 + 10]);
+            let physical = self.physical(index);
+            self.registers[physical] = Float80::from_bytes(&value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLD1: u8 = 0;
+    const FLDPI: u8 = 3;
+
+    #[test]
+    fn stack_overflow_pushes_the_indefinite() {
+        let mut fpu = Fpu::default();
+        for _ in 0..8 {
+            fpu.load_constant(FLD1);
+        }
+        assert_eq!(fpu.status_word() & (INVALID | STACK_FAULT), 0);
+        fpu.load_constant(FLD1);
+        assert_eq!(
+            fpu.status_word() & (INVALID | STACK_FAULT | C1),
+            INVALID | STACK_FAULT | C1
+        );
+        assert_eq!(fpu.registers[fpu.physical(0)], Float80::INDEFINITE);
+        assert!(!fpu.has_pending_exception());
+    }
+
+    #[test]
+    fn stack_underflow_stores_the_indefinite() {
+        let mut fpu = Fpu::default();
+        fpu.square_root();
+        assert_eq!(
+            fpu.status_word() & (INVALID | STACK_FAULT | C1),
+            INVALID | STACK_FAULT
+        );
+        assert_eq!(fpu.registers[fpu.physical(0)], Float80::INDEFINITE);
+    }
+
+    #[test]
+    fn unmasked_stack_fault_is_pending() {
+        let mut fpu = Fpu::default();
+        fpu.set_control_word(CONTROL_DEFAULT & !INVALID);
+        fpu.load_constant(FLD1);
+        fpu.exchange(1);
+        assert!(fpu.has_pending_exception());
+        assert_eq!(fpu.registers[fpu.physical(0)], Float80::ONE);
+        assert!(fpu.is_empty(1));
+    }
+
+    #[test]
+    fn save_and_restore_round_trip() {
+        for (size, real_mode, length) in [(16, true, 94), (32, false, 108)] {
+            let mut fpu = Fpu::default();
+            fpu.set_control_word(0x0c7f);
+            fpu.load_constant(FLD1);
+            fpu.load_constant(FLDPI);
+            fpu.record_instruction(0x1234, 0x0008, 0x01e8);
+            fpu.record_operand(0x5678, 0x0010);
+            let (control, status, tags) = (fpu.control, fpu.status_word(), fpu.tags);
+            let registers = fpu.registers;
+
+            let bytes = fpu.save(size, real_mode);
+            assert_eq!(bytes.len(), length);
+            // FNSAVE ends with FNINIT.
+            assert_eq!(fpu.control, CONTROL_DEFAULT);
+            assert_eq!(fpu.tags, 0xffff);
+
+            fpu.restore(&bytes, size, real_mode);
+            assert_eq!(fpu.control, control);
+            assert_eq!(fpu.status_word(), status);
+            assert_eq!(fpu.tags, tags);
+            assert_eq!(fpu.registers, registers);
+            assert_eq!(fpu.opcode, 0x01e8);
+            assert_eq!(fpu.save_environment(size, real_mode), bytes[..length - 80]);
+        }
+    }
+}
@@ -1,16 +1,26 @@
+use crate::cpu::CpuModel;
 use crate::emulator::Emulator;
 use std::env;
 use std::process;
 mod bios;
+mod cpu;
 mod emulator;
+mod exception;
+mod flags;
+mod float80;
+mod fpu;
 mod io;
 mod modrm;
+mod paging;
+mod segment;
+mod timing;
 
 const MEMORY_SIZE: usize = 1024 * 1024;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut quiet = false;
+    let mut model = CpuModel::I386;
 
     let args: Vec<String> = args
         .into_iter()
@@ -18,29 +28,61 @@ fn main() {
             if arg == "-q" {
                 quiet = true;
                 false
+            } else if let Some(name) = arg.strip_prefix("--cpu=") {
+                model = CpuModel::from_name(name).unwrap_or_else(|| {
+                    eprintln!("Unknown CPU model: {} (386, 486 or pentium)", name);
+                    process::exit(1);
+                });
+                false
             } else {
                 true
             }
         })
         .collect();
     if args.len() != 2 {
-        eprintln!("Usage: {} <binary_file>", args[0]);
+        eprintln!(
+            "Usage: {} [-q] [--cpu=386|486|pentium] <binary_file>",
+            args[0]
+        );
         process::exit(1);
     }
     let filename = &args[1];
-    let mut emu = Emulator::new(MEMORY_SIZE, 0x7c00, 0x7c00);
+    let mut emu = Emulator::new(model, MEMORY_SIZE, 0x7c00, 0x7c00);
     emu.init_instructions();
-    emu.read_binary(filename);
-    while (emu.eip as usize) < MEMORY_SIZE {
+    if let Err(message) = emu.read_binary(filename) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+    loop {
         let code: u8 = emu.get_code8(0);
         if !quiet {
             println!("EIP = {}, Code = 0x{:02X}\n", emu.eip, code);
         }
 
-        if let Some(instruction) = emu.instructions.get(&code).cloned() {
-            emu.execute_instruction(&instruction);
-        } else {
-            println!("Not Implemented: 0x{:02X}", code);
+        let result = match emu.fetch_instruction() {
+            Ok(Some(instruction)) => emu.execute_instruction(&instruction),
+            Ok(None) if emu.get_code8(0) == 0x0F => {
+                println!("Not Implemented: 0x0F 0x{:02X}", emu.get_code8(1));
+                break;
+            }
+            Ok(None) => {
+                println!("Not Implemented: 0x{:02X}", emu.get_code8(0));
+                break;
+            }
+            Err(exception) => Err(exception),
+        };
+        if let Err(exception) = result {
+            if !quiet {
+                println!("Exception: {} at EIP = {:08X}\n", exception, emu.eip);
+            }
+            if !emu.raise_exception(exception) {
+                println!("triple fault: reset.\n");
+                break;
+            }
+        }
+
+        if emu.is_halted() {
+            println!("halted.\n");
             break;
         }
 
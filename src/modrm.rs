@@ -1,5 +1,7 @@
 use crate::emulator::Emulator;
 use crate::emulator::Register8;
+use crate::exception::Exception;
+use crate::segment::SegmentRegister;
 
 pub struct ModRM {
     pub mod_val: u8,
@@ -7,79 +9,167 @@ pub struct ModRM {
     pub rm: u8,
     pub sib: Option<u8>,
     pub disp8: Option<i8>,
+    pub disp16: Option<i16>,
     pub disp32: Option<i32>,
+    pub address_size: u32,
 }
 
 impl ModRM {
-    fn calc_memory_address(&self, emu: &Emulator) -> u32 {
-        match self.mod_val {
-            0 => match self.rm {
-                4 => panic!("not implemented ModRM mod = 0, rm = 4"),
-                5 => self.disp32.expect("disp32 is None") as u32,
-                _ => emu.get_register32(self.rm as usize),
-            },
-            1 => {
-                if self.rm == 4 {
-                    panic!("not implemented ModRM mod = 1, rm = 4");
-                }
-                let disp8_to_u32 = self.disp8.expect("disp8 is None") as i32 as u32;
-                emu.get_register32(self.rm as usize)
-                    .wrapping_add(disp8_to_u32)
-            }
-            2 => {
-                if self.rm == 4 {
-                    panic!("not implemented ModRM mod = 2, rm = 4");
-                }
-                let disp32_to_u32 = self.disp32.expect("disp32 is None") as u32;
-                emu.get_register32(self.rm as usize)
-                    .wrapping_add(disp32_to_u32)
-            }
+    /// The segment of the memory operand: SS when the address is based on
+    /// EBP or ESP (BP for 16-bit addressing), DS otherwise, unless a segment
+    /// override prefix is present.
+    pub fn segment(&self, emu: &Emulator) -> SegmentRegister {
+        let is_stack = if self.address_size == 16 {
+            matches!(self.rm, 2 | 3) || (self.rm == 6 && self.mod_val != 0)
+        } else if self.rm == 4 {
+            let base = self.sib.expect("sib is None") & 0x07;
+            base == 4 || (base == 5 && self.mod_val != 0)
+        } else {
+            self.rm == 5 && self.mod_val != 0
+        };
+        emu.segment_override(if is_stack {
+            SegmentRegister::Ss
+        } else {
+            SegmentRegister::Ds
+        })
+    }
+
+    /// The offset of the memory operand within its segment.
+    pub fn calc_effective_address(&self, emu: &Emulator) -> u32 {
+        if self.address_size == 16 {
+            return self.calc_memory_address16(emu);
+        }
+        let disp = match self.mod_val {
+            0 => 0,
+            1 => self.disp8.expect("disp8 is None") as i32 as u32,
+            2 => self.disp32.expect("disp32 is None") as u32,
             3 => panic!("not implemented ModRM mod = 3"),
             _ => unreachable!(),
+        };
+        match self.rm {
+            4 => self.calc_sib_address(emu).wrapping_add(disp),
+            5 if self.mod_val == 0 => self.disp32.expect("disp32 is None") as u32,
+            _ => emu.get_register32(self.rm as usize).wrapping_add(disp),
         }
     }
 
-    pub fn set_rm8(&self, emu: &mut Emulator, value: u8) {
+    /// Computes base + index * scale from the SIB byte. An index of 4 (ESP)
+    /// means no index, and a base of 5 (EBP) with mod = 0 means no base but a
+    /// disp32 instead.
+    fn calc_sib_address(&self, emu: &Emulator) -> u32 {
+        let sib = self.sib.expect("sib is None");
+        let scale = sib >> 6;
+        let index = (sib >> 3) & 0x07;
+        let base = sib & 0x07;
+
+        let base_value = if base == 5 && self.mod_val == 0 {
+            self.disp32.expect("disp32 is None") as u32
+        } else {
+            emu.get_register32(base as usize)
+        };
+        let index_value = if index == 4 {
+            0
+        } else {
+            emu.get_register32(index as usize) << scale
+        };
+        base_value.wrapping_add(index_value)
+    }
+
+    /// Computes a 16-bit effective address from the fixed base/index
+    /// combinations of 16-bit addressing. The result wraps within 64 KiB.
+    fn calc_memory_address16(&self, emu: &Emulator) -> u32 {
+        let bx = emu.get_register16(3);
+        let bp = emu.get_register16(5);
+        let si = emu.get_register16(6);
+        let di = emu.get_register16(7);
+
+        let disp = match self.mod_val {
+            0 => 0,
+            1 => self.disp8.expect("disp8 is None") as i16 as u16,
+            2 => self.disp16.expect("disp16 is None") as u16,
+            3 => panic!("not implemented ModRM mod = 3"),
+            _ => unreachable!(),
+        };
+        let base = match self.rm {
+            0 => bx.wrapping_add(si),
+            1 => bx.wrapping_add(di),
+            2 => bp.wrapping_add(si),
+            3 => bp.wrapping_add(di),
+            4 => si,
+            5 => di,
+            6 if self.mod_val == 0 => self.disp16.expect("disp16 is None") as u16,
+            6 => bp,
+            _ => bx,
+        };
+        base.wrapping_add(disp) as u32
+    }
+
+    fn read_memory(&self, emu: &mut Emulator, size: u32) -> Result<u32, Exception> {
+        emu.read_memory(self.segment(emu), self.calc_effective_address(emu), size)
+    }
+
+    fn write_memory(&self, emu: &mut Emulator, value: u32, size: u32) -> Result<(), Exception> {
+        let segment = self.segment(emu);
+        let offset = self.calc_effective_address(emu);
+        emu.write_memory(segment, offset, value, size)
+    }
+
+    pub fn set_rm8(&self, emu: &mut Emulator, value: u8) -> Result<(), Exception> {
         if self.mod_val == 3 {
             if let Some(reg) = Register8::from_usize(self.rm as usize) {
                 emu.set_register8(reg, value);
             } else {
                 panic!("Invalid register index: {}", self.rm);
             }
+            Ok(())
         } else {
-            let address = self.calc_memory_address(emu);
-            emu.set_memory8(address, value);
+            self.write_memory(emu, value as u32, 8)
         }
     }
 
-    pub fn set_rm32(&self, emu: &mut Emulator, value: u32) {
+    pub fn set_rm32(&self, emu: &mut Emulator, value: u32) -> Result<(), Exception> {
         if self.mod_val == 3 {
             emu.set_register32(self.rm as usize, value);
+            Ok(())
         } else {
-            let address = self.calc_memory_address(emu);
-            emu.set_memory32(address, value);
+            self.write_memory(emu, value, 32)
         }
     }
 
-    pub fn get_rm8(&self, emu: &Emulator) -> u8 {
+    pub fn get_rm8(&self, emu: &mut Emulator) -> Result<u8, Exception> {
         if self.mod_val == 3 {
             if let Some(reg) = Register8::from_usize(self.rm as usize) {
-                emu.get_register8(reg)
+                Ok(emu.get_register8(reg))
             } else {
                 panic!("Invalid register index: {}", self.rm);
             }
         } else {
-            let address = self.calc_memory_address(emu);
-            emu.get_memory8(address)
+            Ok(self.read_memory(emu, 8)? as u8)
         }
     }
 
-    pub fn get_rm32(&self, emu: &Emulator) -> u32 {
+    pub fn set_rm16(&self, emu: &mut Emulator, value: u16) -> Result<(), Exception> {
         if self.mod_val == 3 {
-            emu.get_register32(self.rm as usize)
+            emu.set_register16(self.rm as usize, value);
+            Ok(())
         } else {
-            let address = self.calc_memory_address(emu);
-            emu.get_memory32(address)
+            self.write_memory(emu, value as u32, 16)
+        }
+    }
+
+    pub fn get_rm16(&self, emu: &mut Emulator) -> Result<u16, Exception> {
+        if self.mod_val == 3 {
+            Ok(emu.get_register16(self.rm as usize))
+        } else {
+            Ok(self.read_memory(emu, 16)? as u16)
+        }
+    }
+
+    pub fn get_rm32(&self, emu: &mut Emulator) -> Result<u32, Exception> {
+        if self.mod_val == 3 {
+            Ok(emu.get_register32(self.rm as usize))
+        } else {
+            self.read_memory(emu, 32)
         }
     }
 
@@ -91,6 +181,10 @@ impl ModRM {
         }
     }
 
+    pub fn set_r16(&self, emu: &mut Emulator, value: u16) {
+        emu.set_register16(self.opecode as usize, value);
+    }
+
     pub fn set_r32(&self, emu: &mut Emulator, value: u32) {
         emu.set_register32(self.opecode as usize, value);
     }
@@ -103,7 +197,45 @@ impl ModRM {
         }
     }
 
+    pub fn get_r16(&self, emu: &Emulator) -> u16 {
+        emu.get_register16(self.opecode as usize)
+    }
+
     pub fn get_r32(&self, emu: &Emulator) -> u32 {
         emu.get_register32(self.opecode as usize)
     }
+
+    /// Reads the r/m operand as a byte, word or dword depending on `size`.
+    pub fn get_rm_sized(&self, emu: &mut Emulator, size: u32) -> Result<u32, Exception> {
+        match size {
+            8 => Ok(self.get_rm8(emu)? as u32),
+            16 => Ok(self.get_rm16(emu)? as u32),
+            _ => self.get_rm32(emu),
+        }
+    }
+
+    pub fn set_rm_sized(&self, emu: &mut Emulator, value: u32, size: u32) -> Result<(), Exception> {
+        match size {
+            8 => self.set_rm8(emu, value as u8),
+            16 => self.set_rm16(emu, value as u16),
+            _ => self.set_rm32(emu, value),
+        }
+    }
+
+    /// Reads the register operand as a byte, word or dword depending on `size`.
+    pub fn get_r_sized(&self, emu: &Emulator, size: u32) -> u32 {
+        match size {
+            8 => self.get_r8(emu) as u32,
+            16 => self.get_r16(emu) as u32,
+            _ => self.get_r32(emu),
+        }
+    }
+
+    pub fn set_r_sized(&self, emu: &mut Emulator, value: u32, size: u32) {
+        match size {
+            8 => self.set_r8(emu, value as u8),
+            16 => self.set_r16(emu, value as u16),
+            _ => self.set_r32(emu, value),
+        }
+    }
 }
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+pub const PAGE_SIZE: u32 = 0x1000;
+/// The physical address bits of CR3 and of directory and table entries.
+pub const PAGE_FRAME: u32 = 0xffff_f000;
+
+/// Bits of page directory and page table entries.
+pub const PTE_PRESENT: u32 = 1 << 0;
+pub const PTE_WRITABLE: u32 = 1 << 1;
+pub const PTE_USER: u32 = 1 << 2;
+pub const PTE_ACCESSED: u32 = 1 << 5;
+/// Only used in page table entries.
+pub const PTE_DIRTY: u32 = 1 << 6;
+
+/// Bits of the #PF error code.
+/// Set for a protection violation, clear for a not-present page.
+pub const PF_PROTECTION: u16 = 1 << 0;
+pub const PF_WRITE: u16 = 1 << 1;
+/// Set when the access was made at CPL 3.
+pub const PF_USER: u16 = 1 << 2;
+
+/// A cached translation of one linear page.
+#[derive(Clone, Copy)]
+pub struct TlbEntry {
+    pub frame: u32,
+    /// R/W of the directory and table entries combined.
+    pub writable: bool,
+    /// U/S of the directory and table entries combined.
+    pub user: bool,
+    pub dirty: bool,
+}
+
+impl TlbEntry {
+    /// Whether an access can use this entry without walking the page
+    /// tables. A write to a page that is not yet dirty walks them again so
+    /// that the D bit gets set. `write_protect` is CR0.WP, which makes
+    /// supervisor writes honor R/W as well.
+    pub fn allows(&self, is_write: bool, is_user: bool, write_protect: bool) -> bool {
+        if is_user && !self.user {
+            return false;
+        }
+        !is_write || (self.dirty && (!(is_user || write_protect) || self.writable))
+    }
+}
+
+/// The translation lookaside buffer, indexed by linear page number. It is
+/// flushed as a whole on writes to CR3 and when paging is switched on or
+/// off, and one page at a time by INVLPG, so stale entries survive page
+/// table edits just as on the real processor.
+#[derive(Default)]
+pub struct Tlb {
+    entries: HashMap<u32, TlbEntry>,
+}
+
+impl Tlb {
+    pub fn lookup(&self, linear: u32) -> Option<TlbEntry> {
+        self.entries.get(&(linear >> 12)).copied()
+    }
+
+    pub fn insert(&mut self, linear: u32, entry: TlbEntry) {
+        self.entries.insert(linear >> 12, entry);
+    }
+
+    pub fn invalidate(&mut self, linear: u32) {
+        self.entries.remove(&(linear >> 12));
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(writable: bool, user: bool, dirty: bool) -> TlbEntry {
+        TlbEntry {
+            frame: 0x1000,
+            writable,
+            user,
+            dirty,
+        }
+    }
+
+    #[test]
+    fn supervisor_page_rejects_user_accesses() {
+        let page = entry(true, false, true);
+        assert!(page.allows(false, false, false));
+        assert!(page.allows(true, false, false));
+        assert!(!page.allows(false, true, false));
+        assert!(!page.allows(true, true, false));
+    }
+
+    #[test]
+    fn read_only_page() {
+        let page = entry(false, true, true);
+        assert!(page.allows(false, true, false));
+        assert!(!page.allows(true, true, false));
+        // Supervisor writes ignore R/W unless CR0.WP is set.
+        assert!(page.allows(true, false, false));
+        assert!(!page.allows(true, false, true));
+    }
+
+    #[test]
+    fn clean_page_walks_again_on_write() {
+        let page = entry(true, true, false);
+        assert!(page.allows(false, true, false));
+        assert!(!page.allows(true, true, false));
+        assert!(!page.allows(true, false, false));
+    }
+
+    #[test]
+    fn flush_removes_every_page() {
+        let mut tlb = Tlb::default();
+        tlb.insert(0x1234_5678, entry(true, true, true));
+        tlb.insert(0x1234_6000, entry(true, true, true));
+        assert!(tlb.lookup(0x1234_5000).is_some());
+        tlb.flush();
+        assert!(tlb.lookup(0x1234_5678).is_none());
+        assert!(tlb.lookup(0x1234_6000).is_none());
+    }
+
+    #[test]
+    fn invalidate_removes_one_page() {
+        let mut tlb = Tlb::default();
+        tlb.insert(0x1234_5678, entry(true, true, true));
+        tlb.insert(0x1234_6000, entry(true, true, true));
+        tlb.invalidate(0x1234_5fff);
+        assert!(tlb.lookup(0x1234_5678).is_none());
+        assert!(tlb.lookup(0x1234_6000).is_some());
+    }
+}
@@ -0,0 +1,381 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SegmentRegister {
+    Es,
+    Cs,
+    Ss,
+    Ds,
+    Fs,
+    Gs,
+}
+
+impl SegmentRegister {
+    pub fn from_usize(value: usize) -> Option<Self> {
+        match value {
+            0 => Some(SegmentRegister::Es),
+            1 => Some(SegmentRegister::Cs),
+            2 => Some(SegmentRegister::Ss),
+            3 => Some(SegmentRegister::Ds),
+            4 => Some(SegmentRegister::Fs),
+            5 => Some(SegmentRegister::Gs),
+            _ => None,
+        }
+    }
+}
+
+/// Bits of the access rights byte of a descriptor.
+pub const ACCESS_ACCESSED: u8 = 1 << 0;
+/// Readable for code segments, writable for data segments.
+pub const ACCESS_READ_WRITE: u8 = 1 << 1;
+/// Conforming for code segments, expand-down for data segments.
+pub const ACCESS_CONFORMING: u8 = 1 << 2;
+pub const ACCESS_CODE_SEGMENT: u8 = 1 << 3;
+/// Set for code and data segments, clear for system descriptors.
+pub const ACCESS_SEGMENT: u8 = 1 << 4;
+pub const ACCESS_PRESENT: u8 = 1 << 7;
+
+/// Access rights of a present, accessed, read/write data segment.
+pub const ACCESS_DATA: u8 = ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_READ_WRITE | ACCESS_ACCESSED;
+/// Access rights of a present, accessed, readable code segment.
+pub const ACCESS_CODE: u8 = ACCESS_DATA | ACCESS_CODE_SEGMENT;
+
+/// System descriptor types. Types 1 to 7 come from the 286; the 386
+/// versions of the TSS and the gates have bit 3 set.
+pub const SYSTEM_TSS_16: u8 = 0x1;
+pub const SYSTEM_LDT: u8 = 0x2;
+pub const SYSTEM_TSS_16_BUSY: u8 = 0x3;
+pub const SYSTEM_CALL_GATE_16: u8 = 0x4;
+pub const SYSTEM_TASK_GATE: u8 = 0x5;
+pub const SYSTEM_INTERRUPT_GATE_16: u8 = 0x6;
+pub const SYSTEM_TRAP_GATE_16: u8 = 0x7;
+pub const SYSTEM_TSS: u8 = 0x9;
+pub const SYSTEM_TSS_BUSY: u8 = 0xb;
+pub const SYSTEM_CALL_GATE: u8 = 0xc;
+pub const SYSTEM_INTERRUPT_GATE: u8 = 0xe;
+pub const SYSTEM_TRAP_GATE: u8 = 0xf;
+/// The bit that distinguishes a busy TSS from an available one.
+pub const TSS_BUSY: u8 = 0x2;
+
+/// Granularity flag: the limit is in 4 KiB units.
+const FLAG_GRANULARITY: u8 = 1 << 3;
+/// The D/B flag.
+const FLAG_BIG: u8 = 1 << 2;
+
+/// A segment register: the visible selector and the descriptor cache that
+/// is loaded along with it. Memory accesses only ever use the cache.
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub selector: u16,
+    pub base: u32,
+    pub limit: u32,
+    /// The access rights byte of the descriptor (type, S, DPL and P).
+    pub access: u8,
+    /// The D/B bit: 32-bit default operands and addresses for a code
+    /// segment, ESP instead of SP for a stack segment.
+    pub big: bool,
+}
+
+impl Segment {
+    /// The state after reset: base 0, a 64 KiB limit and 16-bit defaults.
+    pub fn reset(access: u8) -> Self {
+        Segment {
+            selector: 0,
+            base: 0,
+            limit: 0xffff,
+            access,
+            big: false,
+        }
+    }
+
+    /// A null selector loaded into a data segment register in protected
+    /// mode. The register can be loaded but not used for memory accesses.
+    pub fn null(selector: u16) -> Self {
+        Segment {
+            selector,
+            access: 0,
+            ..Segment::reset(0)
+        }
+    }
+
+    /// A segment register in virtual-8086 mode: the base is selector * 16
+    /// as in real mode, with a 64 KiB limit and the access rights of a
+    /// present, writable data segment at DPL 3.
+    pub fn virtual_8086(selector: u16) -> Self {
+        Segment {
+            selector,
+            base: u32::from(selector) << 4,
+            limit: 0xffff,
+            access: ACCESS_DATA | 3 << 5,
+            big: false,
+        }
+    }
+
+    /// Loads a selector in real-address mode, where the base is simply
+    /// selector * 16 and the rest of the cache is left unchanged.
+    pub fn load_real_mode(&mut self, selector: u16) {
+        self.selector = selector;
+        self.base = u32::from(selector) << 4;
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.access & ACCESS_CODE_SEGMENT != 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        !self.is_code() || self.access & ACCESS_READ_WRITE != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        !self.is_code() && self.access & ACCESS_READ_WRITE != 0
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & ACCESS_CONFORMING != 0
+    }
+
+    pub fn dpl(&self) -> u16 {
+        (self.access >> 5 & 3) as u16
+    }
+
+    fn is_expand_down(&self) -> bool {
+        !self.is_code() && self.access & ACCESS_CONFORMING != 0
+    }
+
+    /// Whether `length` bytes at `offset` lie within the limit. Offsets of
+    /// an expand-down segment run from limit + 1 up to 64 KiB or 4 GiB.
+    pub fn contains(&self, offset: u32, length: u32) -> bool {
+        let last = u64::from(offset) + u64::from(length) - 1;
+        if self.is_expand_down() {
+            let upper = if self.big { 0xffff_ffff } else { 0xffff };
+            offset > self.limit && last <= upper
+        } else {
+            last <= u64::from(self.limit)
+        }
+    }
+}
+
+/// The base and limit held in GDTR or IDTR.
+#[derive(Clone, Copy, Default)]
+pub struct DescriptorTable {
+    pub base: u32,
+    pub limit: u16,
+}
+
+/// An 8-byte segment or system descriptor from the GDT, LDT or IDT.
+#[derive(Clone, Copy)]
+pub struct Descriptor {
+    pub base: u32,
+    /// The limit in bytes, with the granularity already applied.
+    pub limit: u32,
+    pub access: u8,
+    /// G, D/B and AVL in the high nibble of the sixth byte.
+    flags: u8,
+}
+
+impl Descriptor {
+    pub fn from_u64(raw: u64) -> Self {
+        let limit = (raw & 0xffff) as u32 | ((raw >> 32) & 0xf_0000) as u32;
+        let base = ((raw >> 16) & 0xff_ffff) as u32 | ((raw >> 32) & 0xff00_0000) as u32;
+        let flags = ((raw >> 52) & 0xf) as u8;
+        let limit = if flags & FLAG_GRANULARITY != 0 {
+            (limit << 12) | 0xfff
+        } else {
+            limit
+        };
+        Descriptor {
+            base,
+            limit,
+            access: (raw >> 40) as u8,
+            flags,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    /// Whether this describes a code or data segment rather than a system
+    /// object (LDT, TSS or gate).
+    pub fn is_segment(&self) -> bool {
+        self.access & ACCESS_SEGMENT != 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_CODE_SEGMENT != 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_CODE_SEGMENT == 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.is_data() || (self.is_code() && self.access & ACCESS_READ_WRITE != 0)
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & ACCESS_CONFORMING != 0
+    }
+
+    pub fn dpl(&self) -> u16 {
+        (self.access >> 5 & 3) as u16
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.is_data() && self.access & ACCESS_READ_WRITE != 0
+    }
+
+    /// The type field of a system descriptor.
+    pub fn system_type(&self) -> u8 {
+        self.access & 0x0f
+    }
+
+    /// The access rights dword loaded by LAR: the access byte and the G,
+    /// D/B and AVL flags in their places in the high half of the
+    /// descriptor.
+    pub fn access_rights(&self) -> u32 {
+        (self.flags as u32) << 20 | (self.access as u32) << 8
+    }
+
+    /// Builds the descriptor cache loaded into a segment register.
+    pub fn to_segment(self, selector: u16) -> Segment {
+        Segment {
+            selector,
+            base: self.base,
+            limit: self.limit,
+            access: self.access,
+            big: self.flags & FLAG_BIG != 0,
+        }
+    }
+}
+
+/// A gate descriptor from the IDT, GDT or LDT: the selector and offset of
+/// the target along with the gate's own type, DPL and present bit.
+#[derive(Clone, Copy)]
+pub struct Gate {
+    pub selector: u16,
+    pub offset: u32,
+    pub access: u8,
+    /// The number of words or dwords a call gate copies to the new stack.
+    pub parameter_count: u8,
+}
+
+impl Gate {
+    pub fn from_u64(raw: u64) -> Self {
+        Gate {
+            selector: (raw >> 16) as u16,
+            offset: (raw & 0xffff) as u32 | ((raw >> 32) & 0xffff_0000) as u32,
+            access: (raw >> 40) as u8,
+            parameter_count: (raw >> 32) as u8 & 0x1f,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u16 {
+        (self.access >> 5 & 3) as u16
+    }
+
+    /// The type field, or an invalid type for a code or data descriptor.
+    pub fn system_type(&self) -> u8 {
+        self.access & (ACCESS_SEGMENT | 0x0f)
+    }
+
+    /// Call, interrupt and trap gates of the 286 types transfer to a 16-bit
+    /// offset, pushing words.
+    pub fn is_32bit(&self) -> bool {
+        self.access & 0x08 != 0
+    }
+
+    pub fn is_interrupt_gate(&self) -> bool {
+        matches!(
+            self.system_type(),
+            SYSTEM_INTERRUPT_GATE_16 | SYSTEM_INTERRUPT_GATE
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_fields() {
+        // Base 0x12345678, limit 0xABCDE in bytes, a present DPL 3 code
+        // segment with D set.
+        let descriptor = Descriptor::from_u64(0x124a_fa34_5678_bcde);
+        assert_eq!(descriptor.base, 0x1234_5678);
+        assert_eq!(descriptor.limit, 0xa_bcde);
+        assert_eq!(descriptor.access, 0xfa);
+        assert!(descriptor.is_present());
+        assert!(descriptor.is_code());
+        assert!(descriptor.is_readable());
+        assert!(!descriptor.is_writable());
+        assert_eq!(descriptor.dpl(), 3);
+        assert_eq!(descriptor.access_rights(), 0x0040_fa00);
+        assert!(descriptor.to_segment(0x1b).big);
+    }
+
+    #[test]
+    fn descriptor_granularity() {
+        // A flat 4 GiB data segment with G and B set.
+        let descriptor = Descriptor::from_u64(0x00cf_9300_0000_ffff);
+        assert_eq!(descriptor.base, 0);
+        assert_eq!(descriptor.limit, 0xffff_ffff);
+        assert!(descriptor.is_data());
+        assert!(descriptor.is_writable());
+    }
+
+    #[test]
+    fn system_descriptor() {
+        let descriptor = Descriptor::from_u64(0x0000_8200_1000_0067);
+        assert!(!descriptor.is_segment());
+        assert_eq!(descriptor.system_type(), SYSTEM_LDT);
+        assert_eq!(descriptor.limit, 0x67);
+    }
+
+    #[test]
+    fn contains_expand_up() {
+        let segment = Segment {
+            limit: 0x0fff,
+            ..Segment::reset(ACCESS_DATA)
+        };
+        assert!(segment.contains(0, 1));
+        assert!(segment.contains(0x0ffc, 4));
+        assert!(!segment.contains(0x0ffd, 4));
+        assert!(!segment.contains(0x1000, 1));
+        assert!(!segment.contains(0xffff_ffff, 2));
+    }
+
+    #[test]
+    fn contains_expand_down() {
+        let mut segment = Segment {
+            limit: 0x0fff,
+            ..Segment::reset(ACCESS_DATA | ACCESS_CONFORMING)
+        };
+        assert!(!segment.contains(0x0fff, 1));
+        assert!(!segment.contains(0x0ffe, 4));
+        assert!(segment.contains(0x1000, 1));
+        assert!(segment.contains(0xfffe, 2));
+        assert!(!segment.contains(0xffff, 2));
+        assert!(!segment.contains(0x1_0000, 1));
+        segment.big = true;
+        assert!(segment.contains(0x1_0000, 4));
+        assert!(segment.contains(0xffff_fffc, 4));
+        assert!(!segment.contains(0xffff_fffd, 4));
+    }
+
+    #[test]
+    fn code_segment_is_not_expand_down() {
+        let segment = Segment {
+            limit: 0x0fff,
+            ..Segment::reset(ACCESS_CODE | ACCESS_CONFORMING)
+        };
+        assert!(segment.contains(0x0fff, 1));
+        assert!(!segment.contains(0x1000, 1));
+    }
+}
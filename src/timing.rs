@@ -0,0 +1,332 @@
+use crate::emulator::Instruction;
+
+/// The rate the cycle counter runs at: a 33 MHz 386DX.
+pub const CLOCK_RATE: u64 = 33_000_000;
+/// The input clock of the 8254 programmable interval timer.
+pub const PIT_RATE: u64 = 1_193_182;
+/// The PIT ticks between two interrupts of the BIOS system timer, about
+/// 18.2 per second.
+pub const PIT_TICKS_PER_TIMER_TICK: u64 = 65536;
+/// The crystal of the real-time clock, divided down to count seconds.
+pub const RTC_RATE: u64 = 32_768;
+
+/// A switch to another task through a 32-bit TSS, which the manual puts at
+/// around 300 clocks whatever started it.
+const TASK_SWITCH_CLOCKS: u32 = 300;
+
+/// What an instruction did as it executed, as far as its clock count is
+/// concerned. It is started afresh for each instruction.
+#[derive(Clone, Copy, Default)]
+pub struct Execution {
+    /// The opcode byte, or the second byte of a two-byte opcode.
+    pub opcode: u8,
+    /// The reg field of the ModR/M byte, which selects the operation of
+    /// group opcodes.
+    pub group: u8,
+    pub memory_operand: bool,
+    pub branch_taken: bool,
+    pub protected_mode: bool,
+    pub operand_size: u32,
+    /// For a REP-prefixed string instruction, whether this was the last
+    /// iteration.
+    pub last_iteration: Option<bool>,
+    /// Whether CPL changed, as for a call through a gate to an inner level
+    /// or a return to an outer one.
+    pub level_change: bool,
+    pub task_switched: bool,
+}
+
+impl Instruction {
+    /// The number of clocks the instruction takes on the 386, from the
+    /// tables of the Intel 80386 Programmer's Reference Manual. Variable
+    /// terms are left out: the `m` of control transfers (the length of the
+    /// next instruction) and the `3n` of BSF and BSR. Where the manual
+    /// gives a range, as for MUL, the upper end is used. A REP-prefixed
+    /// string instruction is charged the per-iteration count each time
+    /// round, and the base count once it has finished.
+    pub fn clocks(&self, e: &Execution) -> u32 {
+        let rm = |register: u32, memory: u32| {
+            if e.memory_operand {
+                memory
+            } else {
+                register
+            }
+        };
+        let branch = |taken: u32, not_taken: u32| {
+            if e.branch_taken {
+                taken
+            } else {
+                not_taken
+            }
+        };
+        let mode = |real: u32, protected: u32| {
+            if e.protected_mode {
+                protected
+            } else {
+                real
+            }
+        };
+        // Far transfers that switch tasks or change privilege level.
+        let far = |same_level: u32, level_change: u32| {
+            if e.task_switched {
+                TASK_SWITCH_CLOCKS
+            } else if e.level_change {
+                level_change
+            } else {
+                same_level
+            }
+        };
+        let string = |single: u32, base: u32, per_iteration: u32| match e.last_iteration {
+            None => single,
+            Some(false) => per_iteration,
+            Some(true) => base + per_iteration,
+        };
+        let multiply = |size: u32| match size {
+            8 => rm(14, 17),
+            16 => rm(22, 25),
+            _ => rm(38, 41),
+        };
+        match self {
+            Instruction::MovR8Imm8 | Instruction::MovR32Imm32 => 2,
+            Instruction::MovR8Rm8 | Instruction::MovR32Rm32 => rm(2, 4),
+            Instruction::MovRm8R8 | Instruction::MovRm32R32 | Instruction::MovRm32Imm32 => 2,
+            // CMP does not write its memory operand back.
+            Instruction::AluRm8R8 | Instruction::AluRm32R32 if e.opcode >> 3 == 7 => rm(2, 5),
+            Instruction::AluRm8R8 | Instruction::AluRm32R32 => rm(2, 7),
+            Instruction::AluR8Rm8 | Instruction::AluR32Rm32 => rm(2, 6),
+            Instruction::AluAlImm8 | Instruction::AluEaxImm32 => 2,
+            Instruction::Code80 | Instruction::Code81 | Instruction::Code83 if e.group == 7 => {
+                rm(2, 5)
+            }
+            Instruction::Code80 | Instruction::Code81 | Instruction::Code83 => rm(2, 7),
+            Instruction::IncR32 => 2,
+            Instruction::PushR32 | Instruction::PushImm32 | Instruction::PushImm8 => 2,
+            Instruction::PopR32 => 4,
+            Instruction::CodeC0
+            | Instruction::CodeC1
+            | Instruction::CodeD0
+            | Instruction::CodeD1
+            | Instruction::CodeD2
+            | Instruction::CodeD3 => match e.group {
+                2 | 3 => rm(9, 10),
+                _ => rm(3, 7),
+            },
+            Instruction::CodeF6 | Instruction::CodeF7 => {
+                let size = if matches!(self, Instruction::CodeF6) {
+                    8
+                } else {
+                    e.operand_size
+                };
+                match (e.group, size) {
+                    (0 | 1, _) => rm(2, 5),
+                    (2 | 3, _) => rm(2, 6),
+                    (4..=6, _) => multiply(size),
+                    (_, 8) => rm(19, 22),
+                    (_, 16) => rm(27, 30),
+                    _ => rm(43, 46),
+                }
+            }
+            Instruction::InAlDx => mode(13, 6),
+            Instruction::OutDxAl => mode(11, 4),
+            Instruction::CodeFf => match e.group {
+                0 | 1 => rm(2, 6),
+                2 | 4 => rm(7, 10),
+                3 => mode(22, far(38, 86)),
+                5 => mode(17, far(31, 31)),
+                _ => 5,
+            },
+            Instruction::CallRel32 => 7,
+            Instruction::Ret | Instruction::RetImm16 => 10,
+            Instruction::Retf | Instruction::RetfImm16 => mode(18, far(32, 68)),
+            Instruction::CallFarPtr => mode(17, far(34, 86)),
+            Instruction::JmpFarPtr => mode(12, far(27, 27)),
+            Instruction::Iret => mode(22, far(22, 38)),
+            Instruction::Leave => 4,
+            Instruction::Enter => 10,
+            Instruction::Pusha => 18,
+            Instruction::Popa => 24,
+            Instruction::Pushf => 4,
+            Instruction::Popf => 5,
+            Instruction::PushSreg | Instruction::MovRm16Sreg => 2,
+            Instruction::PopSreg => mode(7, 21),
+            Instruction::MovSregRm16 => mode(rm(2, 5), rm(18, 19)),
+            Instruction::ShortJump | Instruction::NearJump => 7,
+            Instruction::ShortJcc | Instruction::NearJcc => branch(7, 3),
+            Instruction::Setcc => rm(4, 5),
+            Instruction::MovzxR32Rm8
+            | Instruction::MovzxR32Rm16
+            | Instruction::MovsxR32Rm8
+            | Instruction::MovsxR32Rm16 => rm(3, 6),
+            Instruction::ImulR32Rm32 => multiply(e.operand_size),
+            // BT only reads its operand; BTS, BTR and BTC write it back.
+            Instruction::BitTestRm32R32 if e.opcode == 0xA3 => rm(3, 12),
+            Instruction::BitTestRm32R32 => rm(6, 13),
+            Instruction::Code0fBa if e.group == 4 => rm(3, 6),
+            Instruction::Code0fBa => rm(6, 8),
+            Instruction::BsfR32Rm32 | Instruction::BsrR32Rm32 => 10,
+            Instruction::ShldRm32R32Imm8
+            | Instruction::ShldRm32R32Cl
+            | Instruction::ShrdRm32R32Imm8
+            | Instruction::ShrdRm32R32Cl => rm(3, 7),
+            Instruction::Daa | Instruction::Das | Instruction::Aaa | Instruction::Aas => 4,
+            Instruction::Aam => 17,
+            Instruction::Aad => 19,
+            Instruction::Movs => string(7, 7, 4),
+            Instruction::Cmps => string(10, 5, 9),
+            Instruction::Stos => string(4, 5, 5),
+            Instruction::Lods => string(5, 5, 6),
+            Instruction::Scas => string(7, 5, 8),
+            Instruction::Cld
+            | Instruction::Std
+            | Instruction::Clc
+            | Instruction::Stc
+            | Instruction::Cmc
+            | Instruction::Lahf => 2,
+            Instruction::Cli | Instruction::Sti | Instruction::Sahf => 3,
+            Instruction::Swi => mode(37, far(59, 99)),
+            Instruction::Int3 | Instruction::Icebp => mode(33, far(59, 99)),
+            Instruction::Into => branch(mode(35, far(59, 99)), 3),
+            Instruction::Loop => 11,
+            Instruction::Jecxz => branch(9, 5),
+            Instruction::Hlt => 5,
+            Instruction::Nop | Instruction::XchgEaxR32 | Instruction::Cbw => 3,
+            Instruction::XchgRm8R8 | Instruction::XchgRm32R32 => rm(3, 5),
+            Instruction::LeaR32M | Instruction::Cwd => 2,
+            Instruction::Xlat => 5,
+            Instruction::Fwait => 6,
+            Instruction::FpuEscape => fpu_clocks(e),
+            Instruction::Clts => 5,
+            Instruction::MovR32Cr => 6,
+            Instruction::MovCrR32 => match e.group {
+                0 => 10,
+                2 => 4,
+                _ => 5,
+            },
+            Instruction::MovR32Dr | Instruction::MovDrR32 if e.group < 4 => 22,
+            Instruction::MovR32Dr => 14,
+            Instruction::MovDrR32 => 16,
+            Instruction::Code0f00 => match e.group {
+                0 | 1 => 2,
+                2 => rm(20, 24),
+                3 => rm(23, 27),
+                4 => rm(10, 11),
+                _ => rm(15, 16),
+            },
+            // INVLPG, at its 486 count.
+            Instruction::Code0f01 if e.group == 7 => 12,
+            Instruction::Code0f01 => match e.group {
+                0 | 1 => 9,
+                2 | 3 => 11,
+                4 => rm(2, 3),
+                _ => rm(10, 13),
+            },
+            Instruction::LarR32Rm16 => rm(15, 16),
+            Instruction::LslR32Rm16 | Instruction::ArplRm16R16 => rm(20, 21),
+            // The 486 and Pentium additions have no 386 timing; these are the
+            // counts of the processor that introduced them.
+            Instruction::Bswap => 1,
+            Instruction::Xadd => rm(3, 4),
+            Instruction::Cmpxchg => rm(6, 8),
+            Instruction::Wbinvd => 5,
+            Instruction::Cpuid => 14,
+            Instruction::Rdtsc => 6,
+            Instruction::Rdmsr => 20,
+            Instruction::Wrmsr => 30,
+            Instruction::Code0fC7 => 10,
+        }
+    }
+}
+
+/// Clock counts of the 387 instructions, taken by the escape opcode and
+/// the reg field. Transcendental and other long-running operations are
+/// charged a common count.
+fn fpu_clocks(e: &Execution) -> u32 {
+    let is_integer = e.memory_operand && matches!(e.opcode, 0xDA | 0xDE);
+    match (e.opcode, e.memory_operand, e.group) {
+        // FADD, FMUL, FCOM, FCOMP, FSUB, FSUBR, FDIV and FDIVR.
+        (0xD8 | 0xDA | 0xDC | 0xDE, _, group) => match (group, is_integer) {
+            (1, false) => 46,
+            (1, true) => 82,
+            (2 | 3, false) => 26,
+            (2 | 3, true) => 63,
+            (6 | 7, false) => 89,
+            (6 | 7, true) => 127,
+            (_, false) => 30,
+            (_, true) => 72,
+        },
+        (0xD9, false, 0) => 14,
+        (0xD9, false, 1) | (0xDD, false, 0) => 18,
+        (0xD9, false, 4 | 5) | (0xDD, false, 4 | 5) => 24,
+        (0xD9 | 0xDD, false, _) => 12,
+        (0xD9, true, 0) => 20,
+        (0xD9, true, 2 | 3) => 44,
+        (0xD9, true, 4) => 71,
+        (0xD9, true, 5) => 19,
+        (0xD9, true, 6) => 103,
+        (0xD9, true, _) => 15,
+        (0xDB, false, _) => 33,
+        (0xDB, true, 0) => 52,
+        (0xDB, true, 5) => 44,
+        (0xDB, true, 7) => 53,
+        (0xDB, true, _) => 95,
+        (0xDD, true, 0) => 25,
+        (0xDD, true, 4) => 308,
+        (0xDD, true, 6) => 375,
+        (0xDD, true, 7) => 15,
+        (0xDD, true, _) => 45,
+        (0xDF, false, _) => 13,
+        (0xDF, true, 0) => 65,
+        (0xDF, true, 4) => 275,
+        (0xDF, true, 5) => 67,
+        (0xDF, true, 6) => 534,
+        (0xDF, true, _) => 97,
+        _ => 120,
+    }
+}
+
+/// Converts a number of CPU cycles into ticks of a device clock running at
+/// `rate` Hz.
+pub fn device_ticks(cycles: u64, rate: u64) -> u64 {
+    (u128::from(cycles) * u128::from(rate) / u128::from(CLOCK_RATE)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_ticks_scale_by_rate() {
+        assert_eq!(device_ticks(0, PIT_RATE), 0);
+        assert_eq!(device_ticks(CLOCK_RATE, PIT_RATE), PIT_RATE);
+        assert_eq!(device_ticks(CLOCK_RATE, RTC_RATE), RTC_RATE);
+        assert_eq!(device_ticks(CLOCK_RATE / 2, CLOCK_RATE), CLOCK_RATE / 2);
+    }
+
+    #[test]
+    fn device_ticks_round_down() {
+        // One PIT tick takes about 27.66 CPU clocks.
+        assert_eq!(device_ticks(27, PIT_RATE), 0);
+        assert_eq!(device_ticks(28, PIT_RATE), 1);
+    }
+
+    #[test]
+    fn device_ticks_do_not_overflow() {
+        // A day of clocks times the PIT rate overflows 64 bits.
+        let day = CLOCK_RATE * 86_400;
+        assert_eq!(device_ticks(day, PIT_RATE), PIT_RATE * 86_400);
+        assert_eq!(
+            device_ticks(day, PIT_RATE) / PIT_TICKS_PER_TIMER_TICK,
+            1_573_042
+        );
+    }
+
+    #[test]
+    fn conditional_branch_clocks() {
+        let taken = Execution {
+            branch_taken: true,
+            ..Execution::default()
+        };
+        assert_eq!(Instruction::ShortJcc.clocks(&taken), 7);
+        assert_eq!(Instruction::ShortJcc.clocks(&Execution::default()), 3);
+    }
+}